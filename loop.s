@@ -0,0 +1,21 @@
+# Fill an array with 1 to 8, then add it up. Unlike loop.dat, each instruction is
+# written once, and the loops run as the registers say. Run it with:
+#
+#     tomasulos --asm loop.s
+        .text
+        addi  x1,x0,0x100       # the array
+        addi  x2,x0,8           # its length
+        addi  x3,x0,0
+Fill:   addi  x3,x3,1
+        slli  x4,x3,2
+        add   x4,x4,x1
+        sw    x3,-4(x4)
+        bne   x3,x2,Fill
+        addi  x5,x0,0           # the sum
+        addi  x6,x1,0
+Sum:    lw    x7,0(x6)
+        add   x5,x5,x7
+        addi  x6,x6,4
+        addi  x2,x2,-1
+        bne   x2,x0,Sum
+        sw    x5,32(x1)
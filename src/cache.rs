@@ -0,0 +1,240 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Which line in a set a cache throws out to make room for a new one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Replacement {
+    /// The line which was used least recently
+    #[default]
+    Lru,
+    /// The line which was brought in first
+    Fifo,
+    /// A line chosen at random, from a generator seeded by the configuration
+    Random,
+}
+
+impl Replacement {
+    pub const ALL: [Replacement; 3] = [Replacement::Lru, Replacement::Fifo, Replacement::Random];
+
+    /// The name used in configuration files
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Replacement::Lru => "lru",
+            Replacement::Fifo => "fifo",
+            Replacement::Random => "random",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|policy| policy.name() == name)
+    }
+}
+
+impl Display for Replacement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The shape and timing of a cache
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The capacity in bytes
+    pub size: u64,
+    pub associativity: u64,
+    /// The bytes in each line
+    pub line_size: u64,
+    pub replacement: Replacement,
+    /// The seed for random replacement
+    pub seed: u64,
+    /// The cycles a load spends looking a line up in this cache
+    pub hit_latency: u64,
+    /// The extra cycles to fetch a line from memory when it misses. Only the last
+    /// level's is used, since the levels above it fetch lines from the next level down.
+    pub miss_latency: u64,
+    /// The number of misses which can be outstanding at once
+    pub mshrs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            size: 32768,
+            associativity: 8,
+            line_size: 64,
+            replacement: Replacement::Lru,
+            seed: 1,
+            hit_latency: 1,
+            miss_latency: 10,
+            mshrs: 4,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// The defaults for a level of the hierarchy, counting from 1 for the L1
+    pub fn level(level: usize) -> Self {
+        match level {
+            1 => Self::default(),
+            2 => Self {
+                size: 262144,
+                hit_latency: 10,
+                miss_latency: 100,
+                mshrs: 8,
+                ..Self::default()
+            },
+            _ => Self {
+                size: 2097152,
+                associativity: 16,
+                hit_latency: 30,
+                miss_latency: 100,
+                mshrs: 16,
+                ..Self::default()
+            },
+        }
+    }
+
+    pub fn sets(&self) -> u64 {
+        self.size / (self.line_size * self.associativity)
+    }
+}
+
+/// A set associative cache, which only tracks which lines it holds
+#[derive(Clone, Debug)]
+pub struct Cache {
+    config: CacheConfig,
+    /// The addresses of the lines in each set, most recently used or inserted first
+    sets: Vec<Vec<u64>>,
+    /// The state of the random number generator
+    random: u64,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            sets: vec![Vec::with_capacity(config.associativity as usize); config.sets() as usize],
+            random: config.seed,
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// The address of the start of the line holding an address
+    pub fn line(&self, addr: u64) -> u64 {
+        addr - addr % self.config.line_size
+    }
+
+    fn set_index(&self, line: u64) -> usize {
+        ((line / self.config.line_size) % self.sets.len() as u64) as usize
+    }
+
+    fn set(&mut self, line: u64) -> &mut Vec<u64> {
+        let i = self.set_index(line);
+        &mut self.sets[i]
+    }
+
+    /// Whether the line holding an address is in the cache
+    pub fn contains(&self, addr: u64) -> bool {
+        let line = self.line(addr);
+        self.sets[self.set_index(line)].contains(&line)
+    }
+
+    /// Look up an address, counting it as a use of its line if it's there.
+    /// Returns whether it hit.
+    pub fn lookup(&mut self, addr: u64) -> bool {
+        let line = self.line(addr);
+        let lru = self.config.replacement == Replacement::Lru;
+        let set = self.set(line);
+        let Some(way) = set.iter().position(|l| *l == line) else {
+            return false;
+        };
+        if lru {
+            set.remove(way);
+            set.insert(0, line);
+        }
+        true
+    }
+
+    /// Bring in the line holding an address, if it isn't already there.
+    /// Returns the line thrown out to make room for it, if any.
+    pub fn insert(&mut self, addr: u64) -> Option<u64> {
+        if self.lookup(addr) {
+            return None;
+        }
+        let line = self.line(addr);
+        let ways = self.config.associativity as usize;
+        let replacement = self.config.replacement;
+        let random = next_random(&mut self.random);
+        let set = self.set(line);
+
+        let victim = if set.len() < ways {
+            None
+        } else if replacement == Replacement::Random {
+            Some(set.remove((random % ways as u64) as usize))
+        } else {
+            set.pop()
+        };
+        set.insert(0, line);
+        victim
+    }
+
+    /// Throw out the line holding an address. Returns whether it was there.
+    pub fn remove(&mut self, addr: u64) -> bool {
+        let line = self.line(addr);
+        let set = self.set(line);
+        let before = set.len();
+        set.retain(|l| *l != line);
+        set.len() < before
+    }
+}
+
+/// The next number from a splitmix64 generator
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single set of two 64 byte lines
+    fn two_lines(replacement: Replacement) -> Cache {
+        Cache::new(CacheConfig {
+            size: 128,
+            associativity: 2,
+            line_size: 64,
+            replacement,
+            ..CacheConfig::default()
+        })
+    }
+
+    #[test]
+    fn addresses_in_a_line_hit_once_it_is_brought_in() {
+        let mut cache = two_lines(Replacement::Lru);
+        assert!(!cache.lookup(64));
+        assert_eq!(cache.insert(64), None);
+        assert!(cache.lookup(64));
+        assert!(cache.lookup(127));
+        assert!(!cache.lookup(128));
+        assert!(cache.remove(100));
+        assert!(!cache.contains(64));
+    }
+
+    #[test]
+    fn lru_keeps_lines_which_are_used_but_fifo_does_not() {
+        for (replacement, victim) in [(Replacement::Lru, 64), (Replacement::Fifo, 0)] {
+            let mut cache = two_lines(replacement);
+            cache.insert(0);
+            cache.insert(64);
+            assert!(cache.lookup(0));
+            assert_eq!(cache.insert(128), Some(victim), "{replacement}");
+            assert!(!cache.contains(victim), "{replacement}");
+        }
+    }
+}
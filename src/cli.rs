@@ -0,0 +1,644 @@
+use tomasulos::*;
+
+pub const USAGE: &str = "\
+Usage: tomasulos [OPTIONS]
+
+Simulate a RISC-V trace with Tomasulo's algorithm.
+
+Options:
+  -t, --trace <FILE>     Read the trace from FILE, or stdin if FILE is `-` (default: stdin)
+      --trace-format <FORMAT>
+                         Read the trace as a `trace`, a Spike commit log (`spike`) or a QEMU
+                         `-d in_asm,exec` log (`qemu`) (default: detected from its contents)
+      --xlen <BITS>      Read a Spike or QEMU log as one from a `32` or `64` bit processor
+                         (default: 64 if any address is wider than 8 digits)
+  -a, --asm <FILE>       Run the assembly program in FILE, and simulate the instructions it
+                         executes instead of a trace. Pseudo-instructions such as `li`, `mv`,
+                         `j`, `call` and `ret` are expanded
+  -e, --elf <FILE>       Run the RISC-V binary in FILE from its entry point, and simulate the
+                         instructions it executes instead of a trace
+  -n, --max-instructions <N>
+                         Stop the program or binary after it executes N instructions (default: 10000)
+  -c, --config <FILE>    Read the configuration from FILE, in TOML or the legacy format
+                         (default: config.txt if it exists, otherwise the built-in defaults)
+  -f, --format <FORMAT>  Print the results as `table`, `json` or `csv` (default: table)
+  -o, --output <FILE>    Write the results to FILE instead of stdout
+  -p, --predictor <NAME> Predict branches with `not_taken`, `bimodal`, `gshare` or `tournament`
+  -d, --disambiguation <POLICY>
+                         Order loads and stores with `conservative`, `perfect`, `speculative`
+                         or `store_sets` disambiguation
+      --inclusion <POLICY>
+                         Make the lower cache levels `inclusive` or `exclusive` of the upper ones
+  -r, --regs <FILE>      Read the initial register values from FILE, with lines like `x1 = 0x1000`
+  -x, --functional       Take branch outcomes from executing the trace, instead of its `:T` and
+                         `:N` suffixes
+      --dump-state <FILE>
+                         Write the registers and memory after the last instruction to FILE
+  -h, --help             Print this message
+
+Configuration overrides:";
+
+/// Exit codes that scripts can check
+pub mod exit {
+    pub const SUCCESS: u8 = 0;
+    /// The command line arguments were invalid
+    pub const USAGE: u8 = 2;
+    /// The configuration could not be read or was invalid
+    pub const CONFIG: u8 = 3;
+    /// The trace or program could not be read or had malformed lines
+    pub const TRACE: u8 = 4;
+    /// The results could not be written
+    pub const OUTPUT: u8 = 5;
+}
+
+/// A flag which patches a field of the loaded `Config`
+pub struct Override {
+    pub flag: &'static str,
+    pub description: &'static str,
+    field: fn(&mut Config) -> &mut u64,
+}
+
+pub const OVERRIDES: &[Override] = &[
+    Override {
+        flag: "--rob",
+        description: "reorder buffer entries",
+        field: |c| &mut c.reorder_buffer_entries,
+    },
+    Override {
+        flag: "--lsq",
+        description: "load/store queue entries",
+        field: |c| &mut c.load_store_buffer_entries,
+    },
+    Override {
+        flag: "--eff-addr-rs",
+        description: "effective address reservation stations",
+        field: |c| &mut c.eff_addr_buffer_entries,
+    },
+    Override {
+        flag: "--fp-add-rs",
+        description: "FP adder reservation stations",
+        field: |c| &mut c.fp_add_buffer_entries,
+    },
+    Override {
+        flag: "--fp-mul-rs",
+        description: "FP multiplier reservation stations",
+        field: |c| &mut c.fp_mul_buffer_entries,
+    },
+    Override {
+        flag: "--int-rs",
+        description: "integer reservation stations",
+        field: |c| &mut c.int_buffer_entries,
+    },
+    Override {
+        flag: "--mul-div-rs",
+        description: "integer multiply/divide reservation stations",
+        field: |c| &mut c.mul_div_buffer_entries,
+    },
+    Override {
+        flag: "--eff-addr-units",
+        description: "effective address units",
+        field: |c| c.functional_units_mut(FunctionalUnit::EffectAddr),
+    },
+    Override {
+        flag: "--fp-add-units",
+        description: "FP adder units",
+        field: |c| c.functional_units_mut(FunctionalUnit::FPUAdd),
+    },
+    Override {
+        flag: "--fp-mul-units",
+        description: "FP multiplier units",
+        field: |c| c.functional_units_mut(FunctionalUnit::FPUMul),
+    },
+    Override {
+        flag: "--int-units",
+        description: "integer ALUs",
+        field: |c| c.functional_units_mut(FunctionalUnit::ALU),
+    },
+    Override {
+        flag: "--mul-div-units",
+        description: "integer multiply/divide units",
+        field: |c| c.functional_units_mut(FunctionalUnit::IntMulDiv),
+    },
+    Override {
+        flag: "--issue-width",
+        description: "instructions issued per cycle",
+        field: |c| &mut c.issue_width,
+    },
+    Override {
+        flag: "--commit-width",
+        description: "instructions committed per cycle",
+        field: |c| &mut c.commit_width,
+    },
+    Override {
+        flag: "--cdbs",
+        description: "common data buses",
+        field: |c| &mut c.common_data_buses,
+    },
+    Override {
+        flag: "--predictor-entries",
+        description: "counters in each branch predictor table",
+        field: |c| &mut c.predictor_entries,
+    },
+    Override {
+        flag: "--history-bits",
+        description: "branch outcomes remembered by gshare",
+        field: |c| &mut c.history_bits,
+    },
+    Override {
+        flag: "--mispredict-penalty",
+        description: "cycles to redirect issue after a mispredicted branch",
+        field: |c| &mut c.mispredict_penalty,
+    },
+    Override {
+        flag: "--btb-entries",
+        description: "branch target buffer entries",
+        field: |c| &mut c.btb_entries,
+    },
+    Override {
+        flag: "--btb-associativity",
+        description: "branch target buffer ways per set",
+        field: |c| &mut c.btb_associativity,
+    },
+    Override {
+        flag: "--btb-miss-penalty",
+        description: "cycles to redirect issue after a taken branch misses in the BTB",
+        field: |c| &mut c.btb_miss_penalty,
+    },
+    Override {
+        flag: "--ras-entries",
+        description: "return address stack entries",
+        field: |c| &mut c.ras_entries,
+    },
+    Override {
+        flag: "--store-sets",
+        description: "loads and stores the store set predictor remembers",
+        field: |c| &mut c.store_set_entries,
+    },
+    Override {
+        flag: "--replay-penalty",
+        description: "cycles to redirect issue after replaying a load",
+        field: |c| &mut c.replay_penalty,
+    },
+    Override {
+        flag: "--l1-size",
+        description: "L1 data cache size in bytes",
+        field: |c| &mut c.l1_cache.get_or_insert_with(CacheConfig::default).size,
+    },
+    Override {
+        flag: "--l1-associativity",
+        description: "L1 data cache ways per set",
+        field: |c| {
+            &mut c
+                .l1_cache
+                .get_or_insert_with(CacheConfig::default)
+                .associativity
+        },
+    },
+    Override {
+        flag: "--l1-line-size",
+        description: "L1 data cache line size in bytes",
+        field: |c| {
+            &mut c
+                .l1_cache
+                .get_or_insert_with(CacheConfig::default)
+                .line_size
+        },
+    },
+    Override {
+        flag: "--l1-hit-latency",
+        description: "L1 data cache hit latency",
+        field: |c| {
+            &mut c
+                .l1_cache
+                .get_or_insert_with(CacheConfig::default)
+                .hit_latency
+        },
+    },
+    Override {
+        flag: "--l1-miss-latency",
+        description: "extra latency of an L1 data cache miss, without an L2",
+        field: |c| {
+            &mut c
+                .l1_cache
+                .get_or_insert_with(CacheConfig::default)
+                .miss_latency
+        },
+    },
+    Override {
+        flag: "--l1-mshrs",
+        description: "L1 data cache misses which can be outstanding at once",
+        field: |c| &mut c.l1_cache.get_or_insert_with(CacheConfig::default).mshrs,
+    },
+    Override {
+        flag: "--l2-size",
+        description: "L2 cache size in bytes",
+        field: |c| &mut lower_cache(c, 2).size,
+    },
+    Override {
+        flag: "--l2-hit-latency",
+        description: "L2 cache hit latency",
+        field: |c| &mut lower_cache(c, 2).hit_latency,
+    },
+    Override {
+        flag: "--l3-size",
+        description: "L3 cache size in bytes",
+        field: |c| &mut lower_cache(c, 3).size,
+    },
+    Override {
+        flag: "--int-latency",
+        description: "integer ALU latency",
+        field: |c| c.latencies.class_mut(OpClass::Int),
+    },
+    Override {
+        flag: "--int-mul-latency",
+        description: "integer multiply latency",
+        field: |c| c.latencies.class_mut(OpClass::IntMul),
+    },
+    Override {
+        flag: "--int-div-latency",
+        description: "integer divide and remainder latency",
+        field: |c| c.latencies.class_mut(OpClass::IntDiv),
+    },
+    Override {
+        flag: "--branch-latency",
+        description: "branch latency",
+        field: |c| c.latencies.class_mut(OpClass::Branch),
+    },
+    Override {
+        flag: "--load-latency",
+        description: "load address calculation latency",
+        field: |c| c.latencies.class_mut(OpClass::Load),
+    },
+    Override {
+        flag: "--store-latency",
+        description: "store address calculation latency",
+        field: |c| c.latencies.class_mut(OpClass::Store),
+    },
+    Override {
+        flag: "--fp-add-latency",
+        description: "FP add latency",
+        field: |c| c.latencies.class_mut(OpClass::FPAdd),
+    },
+    Override {
+        flag: "--fp-sub-latency",
+        description: "FP subtract latency",
+        field: |c| c.latencies.class_mut(OpClass::FPSub),
+    },
+    Override {
+        flag: "--fp-mul-latency",
+        description: "FP multiply latency",
+        field: |c| c.latencies.class_mut(OpClass::FPMul),
+    },
+    Override {
+        flag: "--fp-div-latency",
+        description: "FP divide latency",
+        field: |c| c.latencies.class_mut(OpClass::FPDiv),
+    },
+    Override {
+        flag: "--fp-fma-latency",
+        description: "FP fused multiply-add latency",
+        field: |c| c.latencies.class_mut(OpClass::FPFma),
+    },
+    Override {
+        flag: "--fp-sqrt-latency",
+        description: "FP square root latency",
+        field: |c| c.latencies.class_mut(OpClass::FPSqrt),
+    },
+    Override {
+        flag: "--fp-cmp-latency",
+        description: "FP compare, min, max and classify latency",
+        field: |c| c.latencies.class_mut(OpClass::FPCompare),
+    },
+    Override {
+        flag: "--fp-move-latency",
+        description: "FP move and sign injection latency",
+        field: |c| c.latencies.class_mut(OpClass::FPMove),
+    },
+    Override {
+        flag: "--fp-cvt-latency",
+        description: "FP conversion latency",
+        field: |c| c.latencies.class_mut(OpClass::FPConvert),
+    },
+    Override {
+        flag: "--mem-latency",
+        description: "load memory access latency",
+        field: |c| c.memory_latencies.class_mut(OpClass::Load),
+    },
+];
+
+/// Print the usage message, including every configuration override
+pub fn usage() -> String {
+    let mut result = String::from(USAGE);
+    for o in OVERRIDES {
+        let flag = format!("{} <N>", o.flag);
+        if flag.len() < 23 {
+            result += &format!("\n  {flag:<23}Set the {}", o.description);
+        } else {
+            // Flags too long for the column get the description on the next line
+            result += &format!("\n  {flag}\n  {:<23}Set the {}", "", o.description);
+        }
+    }
+    result
+}
+
+/// The L2 or L3, added with the defaults for its level and the L1's line size if it
+/// isn't configured
+fn lower_cache(config: &mut Config, level: usize) -> &mut CacheConfig {
+    let line_size = config.l1_cache.unwrap_or_default().line_size;
+    let cache = match level {
+        2 => &mut config.l2_cache,
+        _ => &mut config.l3_cache,
+    };
+    cache.get_or_insert_with(|| CacheConfig {
+        line_size,
+        ..CacheConfig::level(level)
+    })
+}
+
+/// The most instructions a program or binary executes if `--max-instructions` isn't given
+const DEFAULT_MAX_INSTRUCTIONS: u64 = 10000;
+
+/// The options given on the command line
+pub struct Options {
+    /// The trace file, or `None` for stdin
+    pub trace: Option<String>,
+    /// The format of the trace, or `None` to detect it
+    pub trace_format: Option<TraceFormat>,
+    /// The register width of a log, or `None` to work it out from its addresses
+    pub xlen: Option<Xlen>,
+    /// The assembly program to run instead of a trace, if any
+    pub program: Option<String>,
+    /// The ELF binary to run instead of a trace, if any
+    pub elf: Option<String>,
+    /// The most instructions the program or binary executes
+    pub max_instructions: u64,
+    /// The configuration file, or `None` for the default
+    pub config: Option<String>,
+    pub format: OutputFormat,
+    /// The output file, or `None` for stdout
+    pub output: Option<String>,
+    /// The file of initial register values, if any
+    pub regs: Option<String>,
+    /// Whether to take branch outcomes from executing the trace, as well as addresses
+    pub functional: bool,
+    /// Where to write the architectural state after executing the trace
+    pub dump_state: Option<String>,
+    predictor: Option<PredictorKind>,
+    disambiguation: Option<DisambiguationPolicy>,
+    inclusion: Option<Inclusion>,
+    overrides: Vec<(&'static Override, u64)>,
+}
+
+impl Options {
+    /// Parse the arguments, not including the program name.
+    /// Returns `Ok(None)` if the help message was requested.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut result = Self {
+            trace: None,
+            trace_format: None,
+            xlen: None,
+            program: None,
+            elf: None,
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+            config: None,
+            format: OutputFormat::Table,
+            output: None,
+            regs: None,
+            functional: false,
+            dump_state: None,
+            predictor: None,
+            disambiguation: None,
+            inclusion: None,
+            overrides: Vec::new(),
+        };
+
+        let mut trace_given = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(None);
+            }
+
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "-t" | "--trace" => {
+                    let trace = value()?;
+                    result.trace = if trace == "-" { None } else { Some(trace) };
+                    trace_given = true;
+                }
+                "--trace-format" => {
+                    let name = value()?;
+                    let format = TraceFormat::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown trace format \"{name}\", expected one of {}",
+                            TraceFormat::ALL.map(|format| format.name()).join(", ")
+                        )
+                    })?;
+                    result.trace_format = Some(format);
+                }
+                "--xlen" => {
+                    let bits = value()?;
+                    let xlen = Xlen::from_name(&bits).ok_or_else(|| {
+                        format!(
+                            "Unknown register width \"{bits}\", expected one of {}",
+                            Xlen::ALL.map(|xlen| xlen.name()).join(", ")
+                        )
+                    })?;
+                    result.xlen = Some(xlen);
+                }
+                "-a" | "--asm" => result.program = Some(value()?),
+                "-e" | "--elf" => result.elf = Some(value()?),
+                "-n" | "--max-instructions" => {
+                    let n = value()?;
+                    result.max_instructions = n
+                        .parse::<u64>()
+                        .map_err(|_| format!("Expected a number for {arg}, found \"{n}\""))?;
+                }
+                "-c" | "--config" => result.config = Some(value()?),
+                "-f" | "--format" => result.format = value()?.parse()?,
+                "-o" | "--output" => result.output = Some(value()?),
+                "-r" | "--regs" => result.regs = Some(value()?),
+                "-x" | "--functional" => result.functional = true,
+                "--dump-state" => result.dump_state = Some(value()?),
+                "-p" | "--predictor" => {
+                    let name = value()?;
+                    let predictor = PredictorKind::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown branch predictor \"{name}\", expected one of {}",
+                            PredictorKind::ALL.map(|kind| kind.name()).join(", ")
+                        )
+                    })?;
+                    result.predictor = Some(predictor);
+                }
+                "-d" | "--disambiguation" => {
+                    let name = value()?;
+                    let policy = DisambiguationPolicy::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown disambiguation policy \"{name}\", expected one of {}",
+                            DisambiguationPolicy::ALL
+                                .map(|policy| policy.name())
+                                .join(", ")
+                        )
+                    })?;
+                    result.disambiguation = Some(policy);
+                }
+                "--inclusion" => {
+                    let name = value()?;
+                    let policy = Inclusion::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "Unknown inclusion policy \"{name}\", expected one of {}",
+                            Inclusion::ALL.map(|policy| policy.name()).join(", ")
+                        )
+                    })?;
+                    result.inclusion = Some(policy);
+                }
+                flag => {
+                    let o = OVERRIDES
+                        .iter()
+                        .find(|o| o.flag == flag)
+                        .ok_or_else(|| format!("Unknown argument \"{flag}\""))?;
+                    let n = value()?;
+                    let n = n
+                        .parse::<u64>()
+                        .map_err(|_| format!("Expected a number for {flag}, found \"{n}\""))?;
+                    result.overrides.push((o, n));
+                }
+            }
+        }
+        let inputs = [trace_given, result.program.is_some(), result.elf.is_some()];
+        if inputs.into_iter().filter(|given| *given).count() > 1 {
+            return Err(String::from(
+                "Only one of --trace, --asm and --elf can be given",
+            ));
+        }
+        if result.trace_format.is_some() && (result.program.is_some() || result.elf.is_some()) {
+            return Err(String::from("--trace-format only applies to --trace"));
+        }
+        if result.xlen.is_some() && (result.program.is_some() || result.elf.is_some()) {
+            return Err(String::from("--xlen only applies to --trace"));
+        }
+        Ok(Some(result))
+    }
+
+    /// Patch the loaded configuration with the overrides from the command line
+    pub fn apply_overrides(&self, config: &mut Config) {
+        if let Some(predictor) = self.predictor {
+            config.predictor = predictor;
+        }
+        if let Some(policy) = self.disambiguation {
+            config.disambiguation = policy;
+        }
+        if let Some(policy) = self.inclusion {
+            config.inclusion = policy;
+        }
+        for (o, n) in &self.overrides {
+            *(o.field)(config) = *n;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_are_read_with_their_values() {
+        let options = parse(&["-t", "loop.dat", "-f", "json", "-o", "out.json", "-x"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.trace.as_deref(), Some("loop.dat"));
+        assert_eq!(options.format, OutputFormat::Json);
+        assert_eq!(options.output.as_deref(), Some("out.json"));
+        assert!(options.functional);
+        assert_eq!(options.max_instructions, DEFAULT_MAX_INSTRUCTIONS);
+
+        // `-` reads the trace from stdin, as does leaving it out
+        let options = parse(&["--trace", "-"]).unwrap().unwrap();
+        assert_eq!(options.trace, None);
+        assert!(parse(&["-t", "loop.dat", "--help"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_arguments_are_rejected() {
+        for (args, message) in [
+            (&["--frob"][..], "Unknown argument \"--frob\""),
+            (&["-t"], "Missing value for -t"),
+            (
+                &["--rob", "many"],
+                "Expected a number for --rob, found \"many\"",
+            ),
+            (
+                &["-p", "oracle"],
+                "Unknown branch predictor \"oracle\", expected one of \
+                 not_taken, bimodal, gshare, tournament",
+            ),
+            (
+                &["-t", "loop.dat", "-a", "loop.s"],
+                "Only one of --trace, --asm and --elf can be given",
+            ),
+            (
+                &["-a", "loop.s", "--xlen", "32"],
+                "--xlen only applies to --trace",
+            ),
+        ] {
+            assert_eq!(parse(args).err().as_deref(), Some(message), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn overrides_patch_the_configuration() {
+        let options = parse(&[
+            "--rob",
+            "16",
+            "--int-units",
+            "3",
+            "--issue-width",
+            "4",
+            "--fp-div-latency",
+            "20",
+            "-p",
+            "gshare",
+        ])
+        .unwrap()
+        .unwrap();
+        let mut config = Config::default();
+        options.apply_overrides(&mut config);
+        assert_eq!(config.reorder_buffer_entries, 16);
+        assert_eq!(config.functional_units(FunctionalUnit::ALU), 3);
+        assert_eq!(config.issue_width, 4);
+        assert_eq!(config.commit_width, 1);
+        let fdiv = RiscVOp::parse("fdiv.s f1,f2,f3").unwrap();
+        assert_eq!(config.latencies.get(&fdiv), 20);
+        assert_eq!(config.predictor, PredictorKind::GShare);
+    }
+
+    #[test]
+    fn every_override_is_in_the_usage() {
+        let usage = usage();
+        for o in OVERRIDES {
+            assert!(usage.contains(&format!("{} <N>", o.flag)), "{}", o.flag);
+        }
+    }
+
+    #[test]
+    fn exit_codes_tell_failures_apart() {
+        let codes = [
+            exit::SUCCESS,
+            exit::USAGE,
+            exit::CONFIG,
+            exit::TRACE,
+            exit::OUTPUT,
+        ];
+        assert_eq!(exit::SUCCESS, 0);
+        for (i, code) in codes.iter().enumerate() {
+            assert!(!codes[..i].contains(code), "exit code {code} is reused");
+        }
+    }
+}
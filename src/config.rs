@@ -1,97 +1,774 @@
+use super::*;
 use log::*;
-use std::fmt::{self, Display, Formatter};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
 
+/// Latencies for each class of operation, which individual opcodes can override
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencyTable {
+    pub classes: BTreeMap<OpClass, u64>,
+    /// Overrides for individual opcodes, by their canonical mnemonic
+    pub opcodes: BTreeMap<String, u64>,
+}
+
+impl LatencyTable {
+    /// The latency for an operation, from its opcode or else its class
+    pub fn get(&self, op: &RiscVOp) -> u64 {
+        self.opcodes
+            .get(op.mnemonic())
+            .or_else(|| self.classes.get(&op.class()))
+            .copied()
+            .unwrap_or(1)
+    }
+
+    pub fn class_mut(&mut self, class: OpClass) -> &mut u64 {
+        self.classes.entry(class).or_insert(1)
+    }
+
+    /// Set the latency for a class name, such as `fp_add`, or a mnemonic, such as `fdiv.s`.
+    /// Only the given classes, and the opcodes in them, can be set.
+    fn set(&mut self, key: &str, latency: u64, classes: &[OpClass]) -> Result<(), String> {
+        if let Some(class) = OpClass::from_name(key).filter(|c| classes.contains(c)) {
+            self.classes.insert(class, latency);
+        } else if RiscVOp::OPCODES
+            .iter()
+            .any(|(op, class)| *op == key && classes.contains(class))
+        {
+            self.opcodes.insert(key.to_string(), latency);
+        } else {
+            return Err(format!(
+                "unknown operation class or opcode \"{key}\", expected {} or one of their opcodes",
+                classes
+                    .iter()
+                    .map(|class| class.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Every entry in the table, with its name and latency
+    fn entries(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.classes
+            .iter()
+            .map(|(class, latency)| (class.name(), *latency))
+            .chain(
+                self.opcodes
+                    .iter()
+                    .map(|(op, latency)| (op.as_str(), *latency)),
+            )
+    }
+}
+
+/// How often an execution unit can start a new instruction
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pipelining {
+    /// A new instruction can start every cycle
+    Pipelined,
+    /// A new instruction can start once the previous one finishes executing
+    #[default]
+    Unpipelined,
+    /// A new instruction can start every N cycles
+    Interval(u64),
+}
+
+impl Pipelining {
+    fn parse(value: &toml::Value) -> Result<Self, String> {
+        match value {
+            toml::Value::String(s) if s == "pipelined" => Ok(Pipelining::Pipelined),
+            toml::Value::String(s) if s == "unpipelined" => Ok(Pipelining::Unpipelined),
+            toml::Value::Integer(n) => Ok(Pipelining::Interval(*n)),
+            value => Err(format!(
+                "expected \"pipelined\", \"unpipelined\" or an initiation interval, found {value}"
+            )),
+        }
+    }
+}
+
+impl Display for Pipelining {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pipelining::Pipelined => write!(f, "pipelined"),
+            Pipelining::Unpipelined => write!(f, "unpipelined"),
+            Pipelining::Interval(n) => write!(f, "every {n} cycles"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Config {
     pub eff_addr_buffer_entries: u64,
     pub fp_add_buffer_entries: u64,
     pub fp_mul_buffer_entries: u64,
     pub int_buffer_entries: u64,
+    pub mul_div_buffer_entries: u64,
     pub reorder_buffer_entries: u64,
+    /// The number of loads and stores which can be in flight at once
+    pub load_store_buffer_entries: u64,
+
+    /// The number of physical execution units of each kind.
+    /// Units which aren't listed get one per reservation station.
+    pub functional_units: BTreeMap<FunctionalUnit, u64>,
+    /// Whether each kind of unit is pipelined. Units which aren't listed are unpipelined.
+    pub pipelining: BTreeMap<FunctionalUnit, Pipelining>,
+    /// Overrides for the pipelining of individual opcodes, by their canonical mnemonic
+    pub opcode_pipelining: BTreeMap<String, Pipelining>,
+
+    /// The number of instructions which can be issued each cycle
+    pub issue_width: u64,
+    /// The number of instructions which can be committed each cycle
+    pub commit_width: u64,
+    /// The number of results which can be written back each cycle
+    pub common_data_buses: u64,
+
+    /// How branches are predicted when they're issued
+    pub predictor: PredictorKind,
+    /// The number of counters in each of the predictor's tables
+    pub predictor_entries: u64,
+    /// The number of recent branch outcomes gshare predictors remember
+    pub history_bits: u64,
+    /// The cycles after a mispredicted branch resolves before the
+    /// correct path can be issued
+    pub mispredict_penalty: u64,
+    /// The number of branch targets the branch target buffer holds
+    pub btb_entries: u64,
+    pub btb_associativity: u64,
+    /// The cycles lost redirecting issue when a branch predicted taken
+    /// misses in the branch target buffer, so its target isn't known until it's decoded
+    pub btb_miss_penalty: u64,
+    /// The number of return addresses the return address stack holds, or 0 for none
+    pub ras_entries: u64,
+
+    /// How loads decide whether they can go ahead of older stores
+    pub disambiguation: DisambiguationPolicy,
+    /// The number of loads and stores the store set predictor remembers the sets of
+    pub store_set_entries: u64,
+    /// The cycles after a load is replayed before it can be issued again
+    pub replay_penalty: u64,
+    /// The level 1 data cache, or `None` to give loads the memory latencies instead
+    pub l1_cache: Option<CacheConfig>,
+    /// The level 2 cache, below the L1
+    pub l2_cache: Option<CacheConfig>,
+    /// The level 3 cache, below the L2
+    pub l3_cache: Option<CacheConfig>,
+    /// Whether lower cache levels also hold the lines in the levels above them
+    pub inclusion: Inclusion,
 
-    pub fp_add_buffer_latency: u64,
-    pub fp_sub_buffer_latency: u64,
-    pub fp_mul_buffer_latency: u64,
-    pub fp_div_buffer_latency: u64,
+    /// The values registers hold before the first instruction. The rest start at 0.
+    pub registers: BTreeMap<Register, u64>,
+    /// The 4 byte words memory holds before the first instruction, by address.
+    /// The rest of memory starts at 0.
+    pub data: BTreeMap<u64, u32>,
+
+    /// The cycles each operation spends in the execute stage
+    pub latencies: LatencyTable,
+    /// The cycles each load spends accessing memory
+    pub memory_latencies: LatencyTable,
+}
+
+impl Default for Config {
+    /// The defaults are the same as the `config.txt` shipped with the simulator
+    fn default() -> Self {
+        Self {
+            eff_addr_buffer_entries: 2,
+            fp_add_buffer_entries: 3,
+            fp_mul_buffer_entries: 3,
+            int_buffer_entries: 2,
+            mul_div_buffer_entries: 2,
+            reorder_buffer_entries: 5,
+            load_store_buffer_entries: 8,
+
+            functional_units: BTreeMap::new(),
+            pipelining: BTreeMap::new(),
+            opcode_pipelining: BTreeMap::new(),
+
+            issue_width: 1,
+            commit_width: 1,
+            common_data_buses: 1,
+
+            predictor: PredictorKind::NotTaken,
+            predictor_entries: 1024,
+            history_bits: 8,
+            mispredict_penalty: 1,
+            btb_entries: 64,
+            btb_associativity: 4,
+            btb_miss_penalty: 1,
+            ras_entries: 8,
+
+            disambiguation: DisambiguationPolicy::Perfect,
+            store_set_entries: 1024,
+            replay_penalty: 1,
+            l1_cache: None,
+            l2_cache: None,
+            l3_cache: None,
+            inclusion: Inclusion::Inclusive,
+
+            registers: BTreeMap::new(),
+            data: BTreeMap::new(),
+
+            latencies: LatencyTable {
+                classes: BTreeMap::from([
+                    (OpClass::Int, 1),
+                    (OpClass::IntMul, 3),
+                    (OpClass::IntDiv, 20),
+                    (OpClass::Branch, 1),
+                    (OpClass::Load, 1),
+                    (OpClass::Store, 1),
+                    (OpClass::FPAdd, 2),
+                    (OpClass::FPSub, 2),
+                    (OpClass::FPMul, 5),
+                    (OpClass::FPDiv, 10),
+                    (OpClass::FPFma, 5),
+                    (OpClass::FPSqrt, 10),
+                    (OpClass::FPCompare, 1),
+                    (OpClass::FPMove, 1),
+                    (OpClass::FPConvert, 2),
+                ]),
+                opcodes: BTreeMap::new(),
+            },
+            memory_latencies: LatencyTable {
+                classes: BTreeMap::from([(OpClass::Load, 1)]),
+                opcodes: BTreeMap::new(),
+            },
+        }
+    }
 }
 
 impl Config {
+    /// Read a configuration file in either the TOML or the legacy format.
+    ///
+    /// Files ending in `.toml` are always read as TOML. Otherwise, the format
+    /// is detected from the contents. Missing keys fall back to their defaults,
+    /// and the result is validated.
     pub fn parse(filename: &str) -> Result<Self, String> {
-        // buffers
-        //
-        // eff addr: 2
-        // fp adds: 3
-        // fp muls: 3
-        // ints: 2
-        // reorder: 5
-        //
-        // latencies
-        //
-        // fp_add: 2
-        // fp_sub: 2
-        // fp_mul: 5
-        // fp_div: 10
-
         // Read the file
         let contents = std::fs::read_to_string(filename)
             .map_err(|e| format!("Failed to read {}: {}", filename, e))?;
         info!("Contents of {}: {}", filename, contents);
 
-        let mut eff_addr_buffer_entries = 0;
-        let mut fp_add_buffer_entries = 0;
-        let mut fp_mul_buffer_entries = 0;
-        let mut int_buffer_entries = 0;
-        let mut reorder_buffer_entries = 0;
-        let mut fp_add_buffer_latency = 0;
-        let mut fp_sub_buffer_latency = 0;
-        let mut fp_mul_buffer_latency = 0;
-        let mut fp_div_buffer_latency = 0;
-
-        let mut valid_count = 0;
-        for line in contents.lines() {
-            let mut parts = line.split(':');
-            if parts.clone().count() != 2 {
-                debug!("Skipping line: {}", line);
+        let result = if filename.ends_with(".toml") || Self::is_toml(&contents) {
+            Self::from_toml(&contents)
+        } else {
+            Self::from_legacy(&contents)
+        };
+        let result = result.map_err(|e| format!("{filename}: {e}"))?;
+        result.validate().map_err(|e| format!("{filename}: {e}"))?;
+        Ok(result)
+    }
+
+    /// Read a file of initial register values, with one `reg = value` line for each
+    /// register, such as `x1 = 0x1000`
+    pub fn parse_registers(filename: &str) -> Result<BTreeMap<Register, u64>, String> {
+        let contents = std::fs::read_to_string(filename)
+            .map_err(|e| format!("Failed to read {}: {}", filename, e))?;
+        let mut registers = BTreeMap::new();
+        for entry in toml::parse(&contents).map_err(|e| format!("{filename}: {e}"))? {
+            if !entry.section.is_empty() {
+                return Err(format!(
+                    "{filename}: {}",
+                    entry.error("expected a register, not a section")
+                ));
+            }
+            let (reg, value) = register_entry(&entry).map_err(|e| format!("{filename}: {e}"))?;
+            registers.insert(reg, value);
+        }
+        Ok(registers)
+    }
+
+    /// The number of reservation stations for a functional unit
+    pub fn reservation_stations(&self, unit: FunctionalUnit) -> u64 {
+        match unit {
+            FunctionalUnit::ALU => self.int_buffer_entries,
+            FunctionalUnit::EffectAddr => self.eff_addr_buffer_entries,
+            FunctionalUnit::FPUMul => self.fp_mul_buffer_entries,
+            FunctionalUnit::FPUAdd => self.fp_add_buffer_entries,
+            FunctionalUnit::IntMulDiv => self.mul_div_buffer_entries,
+        }
+    }
+
+    /// The number of physical execution units of a kind
+    pub fn functional_units(&self, unit: FunctionalUnit) -> u64 {
+        self.functional_units
+            .get(&unit)
+            .copied()
+            .unwrap_or_else(|| self.reservation_stations(unit))
+    }
+
+    pub fn functional_units_mut(&mut self, unit: FunctionalUnit) -> &mut u64 {
+        let default = self.reservation_stations(unit);
+        self.functional_units.entry(unit).or_insert(default)
+    }
+
+    /// The data caches, from the L1 down, stopping at the first level which isn't configured
+    pub fn caches(&self) -> Vec<CacheConfig> {
+        [self.l1_cache, self.l2_cache, self.l3_cache]
+            .into_iter()
+            .map_while(|cache| cache)
+            .collect()
+    }
+
+    /// How an operation is pipelined, from its opcode or else its unit
+    pub fn pipelining(&self, op: &RiscVOp) -> Pipelining {
+        self.opcode_pipelining
+            .get(op.mnemonic())
+            .or_else(|| self.pipelining.get(&op.functional_unit()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The cycles before a unit can start another instruction after this one
+    pub fn initiation_interval(&self, op: &RiscVOp) -> u64 {
+        match self.pipelining(op) {
+            Pipelining::Pipelined => 1,
+            Pipelining::Unpipelined => self.latencies.get(op),
+            Pipelining::Interval(n) => n,
+        }
+    }
+
+    /// Does this look like a TOML file, rather than the legacy format?
+    fn is_toml(contents: &str) -> bool {
+        contents
+            .lines()
+            .map(str::trim)
+            .any(|line| line.starts_with('[') || (line.contains('=') && !line.starts_with('#')))
+    }
+
+    /// Read a TOML configuration, such as:
+    ///
+    /// ```toml
+    /// [buffers]
+    /// eff_addr = 2
+    /// fp_add = 3
+    /// fp_mul = 3
+    /// int = 2
+    /// mul_div = 2
+    /// reorder = 5
+    /// load_store = 8
+    ///
+    /// [units]
+    /// int = 2
+    /// fp_mul = 1
+    ///
+    /// [pipelining]
+    /// fp_mul = "pipelined"
+    /// "fdiv.s" = "unpipelined"
+    /// fp_add = 2
+    ///
+    /// [width]
+    /// issue = 4
+    /// commit = 4
+    /// cdb = 2
+    ///
+    /// [branch]
+    /// predictor = "gshare"
+    /// entries = 1024
+    /// history = 8
+    /// penalty = 2
+    /// btb_entries = 64
+    /// btb_associativity = 4
+    /// btb_miss_penalty = 1
+    /// ras_entries = 8
+    ///
+    /// [memory]
+    /// disambiguation = "store_sets"
+    /// store_sets = 1024
+    /// replay_penalty = 1
+    ///
+    /// [registers]
+    /// x1 = 0x1000
+    /// f2 = 0x3f800000
+    ///
+    /// [data]
+    /// 0x1000 = 5
+    /// 0x1004 = 0xffffffff
+    ///
+    /// [caches]
+    /// inclusion = "inclusive"
+    ///
+    /// [caches.l1]
+    /// size = 32768
+    /// associativity = 8
+    /// line_size = 64
+    /// replacement = "lru"
+    /// hit_latency = 1
+    /// mshrs = 4
+    ///
+    /// [caches.l2]
+    /// size = 262144
+    /// hit_latency = 10
+    /// miss_latency = 100
+    ///
+    /// [latencies]
+    /// fp_add = 2
+    /// fp_sub = 2
+    /// fp_mul = 5
+    /// fp_div = 10
+    /// "fdiv.s" = 12
+    ///
+    /// [latencies.memory]
+    /// load = 3
+    /// ```
+    ///
+    /// Latencies are keyed by an operation class, or by an opcode to override its class.
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let mut result = Self::default();
+        for entry in toml::parse(contents)? {
+            debug!("{} = {}", entry.name(), entry.value);
+            let table = match entry.section.as_str() {
+                "latencies" => Some((&mut result.latencies, &OpClass::ALL[..])),
+                "latencies.memory" => Some((&mut result.memory_latencies, &[OpClass::Load][..])),
+                _ => None,
+            };
+            if let Some((table, classes)) = table {
+                table
+                    .set(&entry.key, entry.integer()?, classes)
+                    .map_err(|e| entry.error(e))?;
+                continue;
+            }
+
+            if entry.section == "pipelining" {
+                let pipelining = Pipelining::parse(&entry.value).map_err(|e| entry.error(e))?;
+                if let Some(unit) = FunctionalUnit::from_name(&entry.key) {
+                    result.pipelining.insert(unit, pipelining);
+                } else if RiscVOp::OPCODES.iter().any(|(op, _)| *op == entry.key) {
+                    result
+                        .opcode_pipelining
+                        .insert(entry.key.clone(), pipelining);
+                } else {
+                    return Err(entry.error(format!(
+                        "unknown functional unit or opcode, expected one of {} or an opcode",
+                        FunctionalUnit::ALL.map(|unit| unit.name()).join(", ")
+                    )));
+                }
+                continue;
+            }
+
+            if entry.section == "units" {
+                let unit = FunctionalUnit::from_name(&entry.key).ok_or_else(|| {
+                    entry.error(format!(
+                        "unknown functional unit, expected one of {}",
+                        FunctionalUnit::ALL.map(|unit| unit.name()).join(", ")
+                    ))
+                })?;
+                result.functional_units.insert(unit, entry.integer()?);
+                continue;
+            }
+
+            if (entry.section.as_str(), entry.key.as_str()) == ("branch", "predictor") {
+                result.predictor = PredictorKind::from_name(entry.string()?).ok_or_else(|| {
+                    entry.error(format!(
+                        "unknown branch predictor, expected one of {}",
+                        PredictorKind::ALL.map(|kind| kind.name()).join(", ")
+                    ))
+                })?;
+                continue;
+            }
+
+            let cache = match entry.section.as_str() {
+                "caches.l1" => Some((&mut result.l1_cache, 1)),
+                "caches.l2" => Some((&mut result.l2_cache, 2)),
+                "caches.l3" => Some((&mut result.l3_cache, 3)),
+                _ => None,
+            };
+            if let Some((cache, level)) = cache {
+                let cache = cache.get_or_insert_with(|| CacheConfig::level(level));
+                if entry.key == "replacement" {
+                    cache.replacement =
+                        Replacement::from_name(entry.string()?).ok_or_else(|| {
+                            entry.error(format!(
+                                "unknown replacement policy, expected one of {}",
+                                Replacement::ALL.map(|policy| policy.name()).join(", ")
+                            ))
+                        })?;
+                    continue;
+                }
+                let field = match entry.key.as_str() {
+                    "size" => &mut cache.size,
+                    "associativity" => &mut cache.associativity,
+                    "line_size" => &mut cache.line_size,
+                    "seed" => &mut cache.seed,
+                    "hit_latency" => &mut cache.hit_latency,
+                    "miss_latency" => &mut cache.miss_latency,
+                    "mshrs" => &mut cache.mshrs,
+                    _ => return Err(entry.error("unknown cache parameter")),
+                };
+                *field = entry.integer()?;
+                continue;
+            }
+
+            if (entry.section.as_str(), entry.key.as_str()) == ("caches", "inclusion") {
+                result.inclusion = Inclusion::from_name(entry.string()?).ok_or_else(|| {
+                    entry.error(format!(
+                        "unknown inclusion policy, expected one of {}",
+                        Inclusion::ALL.map(|policy| policy.name()).join(", ")
+                    ))
+                })?;
+                continue;
+            }
+
+            if entry.section == "registers" {
+                let (reg, value) = register_entry(&entry)?;
+                result.registers.insert(reg, value);
+                continue;
+            }
+
+            if entry.section == "data" {
+                let (addr, word) = data_entry(&entry)?;
+                result.data.insert(addr, word);
+                continue;
+            }
+
+            if (entry.section.as_str(), entry.key.as_str()) == ("memory", "disambiguation") {
+                result.disambiguation = DisambiguationPolicy::from_name(entry.string()?)
+                    .ok_or_else(|| {
+                        entry.error(format!(
+                            "unknown disambiguation policy, expected one of {}",
+                            DisambiguationPolicy::ALL
+                                .map(|policy| policy.name())
+                                .join(", ")
+                        ))
+                    })?;
                 continue;
-            } else {
-                valid_count += 1;
             }
-            let name = parts.next().unwrap().trim();
-            let value = parts.next().unwrap().trim().parse::<u64>().unwrap();
+
+            let field = match (entry.section.as_str(), entry.key.as_str()) {
+                ("buffers", "eff_addr") => &mut result.eff_addr_buffer_entries,
+                ("buffers", "fp_add") => &mut result.fp_add_buffer_entries,
+                ("buffers", "fp_mul") => &mut result.fp_mul_buffer_entries,
+                ("buffers", "int") => &mut result.int_buffer_entries,
+                ("buffers", "mul_div") => &mut result.mul_div_buffer_entries,
+                ("buffers", "reorder") => &mut result.reorder_buffer_entries,
+                ("buffers", "load_store") => &mut result.load_store_buffer_entries,
+                ("width", "issue") => &mut result.issue_width,
+                ("width", "commit") => &mut result.commit_width,
+                ("width", "cdb") => &mut result.common_data_buses,
+                ("branch", "entries") => &mut result.predictor_entries,
+                ("branch", "history") => &mut result.history_bits,
+                ("branch", "penalty") => &mut result.mispredict_penalty,
+                ("branch", "btb_entries") => &mut result.btb_entries,
+                ("branch", "btb_associativity") => &mut result.btb_associativity,
+                ("branch", "btb_miss_penalty") => &mut result.btb_miss_penalty,
+                ("branch", "ras_entries") => &mut result.ras_entries,
+                ("memory", "store_sets") => &mut result.store_set_entries,
+                ("memory", "replay_penalty") => &mut result.replay_penalty,
+                _ => return Err(entry.error("unknown config parameter")),
+            };
+            *field = entry.integer()?;
+        }
+        Ok(result)
+    }
+
+    /// Read the legacy `name: value` format, such as:
+    ///
+    /// ```text
+    /// buffers
+    ///
+    /// eff addr: 2
+    /// fp adds: 3
+    /// fp muls: 3
+    /// ints: 2
+    /// reorder: 5
+    ///
+    /// latencies
+    ///
+    /// fp_add: 2
+    /// fp_sub: 2
+    /// fp_mul: 5
+    /// fp_div: 10
+    /// ```
+    pub fn from_legacy(contents: &str) -> Result<Self, String> {
+        let mut result = Self::default();
+        for (i, line) in contents.lines().enumerate() {
+            let Some((name, value)) = line.split_once(':') else {
+                debug!("Skipping line: {}", line);
+                continue;
+            };
+            let name = name.trim();
+            let value = value.trim();
+            let value = value.parse::<u64>().map_err(|_| {
+                format!(
+                    "line {}: {name}: expected a non-negative integer, found \"{value}\"",
+                    i + 1
+                )
+            })?;
             debug!("{}: {}", name, value);
-            match name {
-                "eff addr" => eff_addr_buffer_entries = value,
-                "fp adds" => fp_add_buffer_entries = value,
-                "fp muls" => fp_mul_buffer_entries = value,
-                "ints" => int_buffer_entries = value,
-                "reorder" => reorder_buffer_entries = value,
-                "fp_add" => fp_add_buffer_latency = value,
-                "fp_sub" => fp_sub_buffer_latency = value,
-                "fp_mul" => fp_mul_buffer_latency = value,
-                "fp_div" => fp_div_buffer_latency = value,
-                _ => return Err(format!("Unknown config parameter: {}", name)),
-            }
-        }
-        if valid_count != 9 {
+            let field = match name {
+                "eff addr" => &mut result.eff_addr_buffer_entries,
+                "fp adds" => &mut result.fp_add_buffer_entries,
+                "fp muls" => &mut result.fp_mul_buffer_entries,
+                "ints" => &mut result.int_buffer_entries,
+                "reorder" => &mut result.reorder_buffer_entries,
+                "fp_add" => result.latencies.class_mut(OpClass::FPAdd),
+                "fp_sub" => result.latencies.class_mut(OpClass::FPSub),
+                "fp_mul" => result.latencies.class_mut(OpClass::FPMul),
+                "fp_div" => result.latencies.class_mut(OpClass::FPDiv),
+                _ => {
+                    return Err(format!(
+                        "line {}: Unknown config parameter: {}",
+                        i + 1,
+                        name
+                    ))
+                }
+            };
+            *field = value;
+        }
+        Ok(result)
+    }
+
+    /// Reject configurations that can't be simulated
+    pub fn validate(&self) -> Result<(), String> {
+        let at_least_one = [
+            ("buffers.eff_addr", self.eff_addr_buffer_entries),
+            ("buffers.fp_add", self.fp_add_buffer_entries),
+            ("buffers.fp_mul", self.fp_mul_buffer_entries),
+            ("buffers.int", self.int_buffer_entries),
+            ("buffers.mul_div", self.mul_div_buffer_entries),
+            ("buffers.reorder", self.reorder_buffer_entries),
+            ("buffers.load_store", self.load_store_buffer_entries),
+            ("width.issue", self.issue_width),
+            ("width.commit", self.commit_width),
+            ("width.cdb", self.common_data_buses),
+            ("branch.entries", self.predictor_entries),
+            ("branch.btb_entries", self.btb_entries),
+            ("branch.btb_associativity", self.btb_associativity),
+            ("memory.store_sets", self.store_set_entries),
+        ];
+        for (name, value) in at_least_one {
+            if value == 0 {
+                return Err(format!("{name} must be at least 1, found 0"));
+            }
+        }
+
+        if !self.btb_entries.is_multiple_of(self.btb_associativity) {
             return Err(format!(
-                "Expected 9 config parameters, found {}",
-                valid_count
+                "branch.btb_entries must be a multiple of branch.btb_associativity, found {} and {}",
+                self.btb_entries, self.btb_associativity
             ));
         }
 
-        let result = Self {
-            eff_addr_buffer_entries,
-            fp_add_buffer_entries,
-            fp_mul_buffer_entries,
-            int_buffer_entries,
-            reorder_buffer_entries,
+        if self.history_bits > 63 {
+            return Err(format!(
+                "branch.history must be at most 63, found {}",
+                self.history_bits
+            ));
+        }
 
-            fp_add_buffer_latency,
-            fp_sub_buffer_latency,
-            fp_mul_buffer_latency,
-            fp_div_buffer_latency,
-        };
-        Ok(result)
+        let caches = [
+            ("caches.l1", &self.l1_cache),
+            ("caches.l2", &self.l2_cache),
+            ("caches.l3", &self.l3_cache),
+        ];
+        for (level, (section, cache)) in caches.iter().enumerate() {
+            let Some(cache) = cache else {
+                continue;
+            };
+            validate_cache(section, cache)?;
+            if level > 0 && caches[level - 1].1.is_none() {
+                return Err(format!(
+                    "{section} needs a {} above it",
+                    caches[level - 1].0
+                ));
+            }
+            let l1 = self.l1_cache.unwrap_or_default();
+            if cache.line_size != l1.line_size {
+                return Err(format!(
+                    "{section}.line_size must be the same as caches.l1.line_size ({}), found {}",
+                    l1.line_size, cache.line_size
+                ));
+            }
+        }
+
+        for (unit, count) in &self.functional_units {
+            if *count == 0 {
+                return Err(format!("units.{} must be at least 1, found 0", unit.name()));
+            }
+        }
+
+        let pipelining = self
+            .pipelining
+            .iter()
+            .map(|(unit, p)| (unit.name(), p))
+            .chain(
+                self.opcode_pipelining
+                    .iter()
+                    .map(|(op, p)| (op.as_str(), p)),
+            );
+        for (name, pipelining) in pipelining {
+            if *pipelining == Pipelining::Interval(0) {
+                return Err(format!(
+                    "pipelining.{name} must have an initiation interval of at least 1, found 0"
+                ));
+            }
+        }
+
+        for (section, table) in [
+            ("latencies", &self.latencies),
+            ("latencies.memory", &self.memory_latencies),
+        ] {
+            for (name, latency) in table.entries() {
+                if latency == 0 && name.contains('.') {
+                    return Err(format!("{section}.\"{name}\" must be at least 1, found 0"));
+                } else if latency == 0 {
+                    return Err(format!("{section}.{name} must be at least 1, found 0"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A register's initial value, such as `x1 = 0x1000`. Floating point registers are
+/// given as the bits of their value.
+fn register_entry(entry: &toml::Entry) -> Result<(Register, u64), String> {
+    let reg = Register::parse(&entry.key)
+        .ok_or_else(|| entry.error("unknown register, expected x0 to x31 or f0 to f31"))?;
+    let value = entry.integer()?;
+    if reg == Register::GP(0) && value != 0 {
+        return Err(entry.error(format!("x0 is always 0, found {value}")));
     }
+    Ok((reg, value))
+}
+
+/// A word of memory's initial value, such as `0x1000 = 5`
+fn data_entry(entry: &toml::Entry) -> Result<(u64, u32), String> {
+    let addr = match entry.key.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => entry.key.parse::<u64>().ok(),
+    };
+    let addr = addr.ok_or_else(|| entry.error("expected an address"))?;
+    let value = entry.integer()?;
+    let word = u32::try_from(value)
+        .map_err(|_| entry.error(format!("expected a 4 byte word, found {value:#x}")))?;
+    Ok((addr, word))
+}
+
+/// Reject caches which can't be divided into sets of whole lines
+fn validate_cache(section: &str, cache: &CacheConfig) -> Result<(), String> {
+    let at_least_one = [
+        ("size", cache.size),
+        ("associativity", cache.associativity),
+        ("line_size", cache.line_size),
+        ("hit_latency", cache.hit_latency),
+        ("miss_latency", cache.miss_latency),
+        ("mshrs", cache.mshrs),
+    ];
+    for (name, value) in at_least_one {
+        if value == 0 {
+            return Err(format!("{section}.{name} must be at least 1, found 0"));
+        }
+    }
+
+    let set_size = cache.line_size * cache.associativity;
+    if !cache.size.is_multiple_of(set_size) {
+        return Err(format!(
+            "{section}.size must be a multiple of line_size * associativity ({set_size}), found {}",
+            cache.size
+        ));
+    }
+    Ok(())
 }
 
 impl Display for Config {
@@ -103,13 +780,76 @@ impl Display for Config {
         //     fp adds: 3
         //     fp muls: 3
         //        ints: 2
+        //    mul divs: 2
         //     reorder: 5
-
+        //  load/store: 8
+        //
+        // units:
+        //    eff addr: 2
+        //      fp add: 3
+        //      fp mul: 3
+        //         int: 2
+        //     mul div: 2
+        //
+        // pipelining:
+        //    eff addr: unpipelined
+        //      fp add: unpipelined
+        //      fp mul: unpipelined
+        //         int: unpipelined
+        //     mul div: unpipelined
+        //
+        // width:
+        //      issue: 1
+        //     commit: 1
+        //        cdb: 1
+        //
+        // branch prediction:
+        //   predictor: not_taken
+        //     entries: 1024
+        //     history: 8
+        //     penalty: 1
+        //         btb: 64 entries, 4-way
+        //    btb miss: 1
+        //         ras: 8
+        //
+        // memory:
+        //   disambiguation: perfect
+        //       store sets: 1024
+        //           replay: 1
+        //
+        // caches:
+        //   inclusion: inclusive
+        //          l1: 32768 bytes, 8-way, 64 byte lines, lru
+        //              hit 1, 4 mshrs
+        //          l2: 262144 bytes, 8-way, 64 byte lines, lru
+        //              hit 10, 8 mshrs
+        //      memory: 100
+        //
+        // registers: (only if any are given)
+        //        x1: 0x1000
+        //
+        // data: (only if any are given)
+        //    0x1000: 0x00000005
+        //
         // latencies:
+        //       int: 1
+        //   int mul: 3
+        //   int div: 20
+        //    branch: 1
+        //      load: 1
+        //     store: 1
         //    fp add: 2
         //    fp sub: 2
         //    fp mul: 5
         //    fp div: 10
+        //    fp fma: 5
+        //   fp sqrt: 10
+        //    fp cmp: 1
+        //   fp move: 1
+        //    fp cvt: 2
+        //
+        // memory latencies:
+        //      load: 1
 
         writeln!(f, "Configuration")?;
         writeln!(f, "-------------")?;
@@ -118,13 +858,248 @@ impl Display for Config {
         writeln!(f, "    fp adds: {}", self.fp_add_buffer_entries)?;
         writeln!(f, "    fp muls: {}", self.fp_mul_buffer_entries)?;
         writeln!(f, "       ints: {}", self.int_buffer_entries)?;
+        writeln!(f, "   mul divs: {}", self.mul_div_buffer_entries)?;
         writeln!(f, "    reorder: {}", self.reorder_buffer_entries)?;
+        writeln!(f, " load/store: {}", self.load_store_buffer_entries)?;
+        writeln!(f)?;
+        writeln!(f, "units:")?;
+        writeln!(
+            f,
+            "   eff addr: {}",
+            self.functional_units(FunctionalUnit::EffectAddr)
+        )?;
+        writeln!(
+            f,
+            "     fp add: {}",
+            self.functional_units(FunctionalUnit::FPUAdd)
+        )?;
+        writeln!(
+            f,
+            "     fp mul: {}",
+            self.functional_units(FunctionalUnit::FPUMul)
+        )?;
+        writeln!(
+            f,
+            "        int: {}",
+            self.functional_units(FunctionalUnit::ALU)
+        )?;
+        writeln!(
+            f,
+            "    mul div: {}",
+            self.functional_units(FunctionalUnit::IntMulDiv)
+        )?;
+        writeln!(f)?;
+        writeln!(f, "pipelining:")?;
+        for unit in [
+            FunctionalUnit::EffectAddr,
+            FunctionalUnit::FPUAdd,
+            FunctionalUnit::FPUMul,
+            FunctionalUnit::ALU,
+            FunctionalUnit::IntMulDiv,
+        ] {
+            let pipelining = self.pipelining.get(&unit).copied().unwrap_or_default();
+            writeln!(f, "{:>11}: {}", unit.name().replace('_', " "), pipelining)?;
+        }
+        for (op, pipelining) in &self.opcode_pipelining {
+            writeln!(f, "{:>11}: {}", op, pipelining)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "width:")?;
+        writeln!(f, "      issue: {}", self.issue_width)?;
+        writeln!(f, "     commit: {}", self.commit_width)?;
+        writeln!(f, "        cdb: {}", self.common_data_buses)?;
+        writeln!(f)?;
+        writeln!(f, "branch prediction:")?;
+        writeln!(f, "  predictor: {}", self.predictor)?;
+        writeln!(f, "    entries: {}", self.predictor_entries)?;
+        writeln!(f, "    history: {}", self.history_bits)?;
+        writeln!(f, "    penalty: {}", self.mispredict_penalty)?;
+        writeln!(
+            f,
+            "        btb: {} entries, {}-way",
+            self.btb_entries, self.btb_associativity
+        )?;
+        writeln!(f, "   btb miss: {}", self.btb_miss_penalty)?;
+        writeln!(f, "        ras: {}", self.ras_entries)?;
+        writeln!(f)?;
+        writeln!(f, "memory:")?;
+        writeln!(f, "  disambiguation: {}", self.disambiguation)?;
+        writeln!(f, "      store sets: {}", self.store_set_entries)?;
+        writeln!(f, "          replay: {}", self.replay_penalty)?;
+        writeln!(f)?;
+        writeln!(f, "caches:")?;
+        let caches = self.caches();
+        if let Some(last) = caches.last() {
+            writeln!(f, "  inclusion: {}", self.inclusion)?;
+            for (level, cache) in caches.iter().enumerate() {
+                writeln!(
+                    f,
+                    "         l{}: {} bytes, {}-way, {} byte lines, {}",
+                    level + 1,
+                    cache.size,
+                    cache.associativity,
+                    cache.line_size,
+                    cache.replacement
+                )?;
+                writeln!(
+                    f,
+                    "             hit {}, {} mshrs",
+                    cache.hit_latency, cache.mshrs
+                )?;
+            }
+            writeln!(f, "     memory: {}", last.miss_latency)?;
+        } else {
+            writeln!(f, "       none")?;
+        }
         writeln!(f)?;
+        if !self.registers.is_empty() {
+            writeln!(f, "registers:")?;
+            for (reg, value) in &self.registers {
+                writeln!(f, "{:>9}: {value:#x}", reg.to_string())?;
+            }
+            writeln!(f)?;
+        }
+        if !self.data.is_empty() {
+            writeln!(f, "data:")?;
+            for (addr, word) in &self.data {
+                writeln!(f, "{:>9}: {word:#010x}", format!("{addr:#x}"))?;
+            }
+            writeln!(f)?;
+        }
         writeln!(f, "latencies:")?;
-        writeln!(f, "   fp add: {}", self.fp_add_buffer_latency)?;
-        writeln!(f, "   fp sub: {}", self.fp_sub_buffer_latency)?;
-        writeln!(f, "   fp mul: {}", self.fp_mul_buffer_latency)?;
-        writeln!(f, "   fp div: {}", self.fp_div_buffer_latency)?;
+        for (name, latency) in self.latencies.entries() {
+            writeln!(f, "{:>9}: {}", name.replace('_', " "), latency)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "memory latencies:")?;
+        for (name, latency) in self.memory_latencies.entries() {
+            writeln!(f, "{:>9}: {}", name.replace('_', " "), latency)?;
+        }
         writeln!(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_keys_keep_their_defaults() {
+        let config = Config::from_toml(
+            "[buffers]\n\
+             reorder = 16\n\
+             fp_add = 4\n\
+             \n\
+             [latencies]\n\
+             fp_div = 30\n",
+        )
+        .unwrap();
+        assert_eq!(config.reorder_buffer_entries, 16);
+        assert_eq!(config.fp_add_buffer_entries, 4);
+        assert_eq!(config.int_buffer_entries, 2);
+        let fdiv = RiscVOp::parse("fdiv.s f1,f2,f3").unwrap();
+        let fmul = RiscVOp::parse("fmul.s f1,f2,f3").unwrap();
+        assert_eq!(config.latencies.get(&fdiv), 30);
+        assert_eq!(config.latencies.get(&fmul), 5);
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn opcode_latencies_take_priority_over_their_class() {
+        let config = Config::from_toml(
+            "[latencies]\n\
+             fp_div = 20\n\
+             \"fdiv.d\" = 30\n\
+             int = 2\n",
+        )
+        .unwrap();
+        let latency = |line| config.latencies.get(&RiscVOp::parse(line).unwrap());
+        assert_eq!(latency("fdiv.d f1,f2,f3"), 30);
+        assert_eq!(latency("fdiv.s f1,f2,f3"), 20);
+        assert_eq!(latency("addi x1,x1,1"), 2);
+        // Stores and branches keep their own classes' latencies
+        assert_eq!(latency("sw x1,0(x2)"), 1);
+
+        // Memory latencies can only be set for loads
+        let config = Config::from_toml("[latencies.memory]\nload = 3\n\"lw\" = 5").unwrap();
+        let latency = |line| config.memory_latencies.get(&RiscVOp::parse(line).unwrap());
+        assert_eq!(latency("lw x1,0(x2)"), 5);
+        assert_eq!(latency("lb x1,0(x2)"), 3);
+        assert_eq!(
+            Config::from_toml("[latencies.memory]\nfp_add = 2").unwrap_err(),
+            "line 2: latencies.memory.fp_add: unknown operation class or opcode \"fp_add\", \
+             expected load or one of their opcodes"
+        );
+    }
+
+    #[test]
+    fn units_default_to_one_per_reservation_station() {
+        let config = Config::from_toml("[buffers]\nfp_mul = 4\n\n[units]\nint = 1\n").unwrap();
+        assert_eq!(config.functional_units(FunctionalUnit::ALU), 1);
+        assert_eq!(config.functional_units(FunctionalUnit::FPUMul), 4);
+        assert_eq!(config.functional_units(FunctionalUnit::FPUAdd), 3);
+    }
+
+    #[test]
+    fn unknown_and_mistyped_keys_are_rejected() {
+        for (contents, message) in [
+            (
+                "[buffers]\nrob = 4",
+                "line 2: buffers.rob: unknown config parameter",
+            ),
+            (
+                "[buffers]\nreorder = \"4\"",
+                "line 2: buffers.reorder: expected an integer, found \"4\"",
+            ),
+            ("reorder = 4", "line 1: reorder: unknown config parameter"),
+            (
+                "[branch]\npredictor = \"oracle\"",
+                "line 2: branch.predictor: unknown branch predictor, expected one of \
+                 not_taken, bimodal, gshare, tournament",
+            ),
+            (
+                "[registers]\nx0 = 1",
+                "line 2: registers.x0: x0 is always 0, found 1",
+            ),
+        ] {
+            assert_eq!(Config::from_toml(contents).unwrap_err(), message);
+        }
+    }
+
+    #[test]
+    fn configurations_which_cant_be_simulated_are_rejected() {
+        assert_eq!(Config::default().validate(), Ok(()));
+        for (contents, message) in [
+            (
+                "[buffers]\nreorder = 0",
+                "buffers.reorder must be at least 1, found 0",
+            ),
+            ("[width]\ncdb = 0", "width.cdb must be at least 1, found 0"),
+            (
+                "[branch]\nbtb_entries = 10\nbtb_associativity = 4",
+                "branch.btb_entries must be a multiple of branch.btb_associativity, \
+                 found 10 and 4",
+            ),
+            (
+                "[branch]\nhistory = 64",
+                "branch.history must be at most 63, found 64",
+            ),
+            ("[units]\nint = 0", "units.int must be at least 1, found 0"),
+            (
+                "[latencies]\nfp_add = 0",
+                "latencies.fp_add must be at least 1, found 0",
+            ),
+            (
+                "[latencies]\n\"fdiv.s\" = 0",
+                "latencies.\"fdiv.s\" must be at least 1, found 0",
+            ),
+            (
+                "[caches.l2]\nsize = 1024",
+                "caches.l2 needs a caches.l1 above it",
+            ),
+        ] {
+            let config = Config::from_toml(contents).unwrap();
+            assert_eq!(config.validate().unwrap_err(), message, "{contents}");
+        }
+    }
+}
@@ -0,0 +1,602 @@
+use super::*;
+use std::fmt::{self, Display, Formatter};
+
+/// The width of the integer registers a binary was compiled for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Xlen {
+    Rv32,
+    Rv64,
+}
+
+impl Xlen {
+    pub const ALL: [Xlen; 2] = [Xlen::Rv32, Xlen::Rv64];
+
+    /// The name used on the command line, which is the number of bits
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Xlen::Rv32 => "32",
+            Xlen::Rv64 => "64",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|xlen| xlen.name() == name)
+    }
+
+    /// A value as the 64 bit registers hold it, which for RV32 is sign extended
+    pub const fn extend(&self, value: u64) -> u64 {
+        match self {
+            Xlen::Rv32 => value as i32 as i64 as u64,
+            Xlen::Rv64 => value,
+        }
+    }
+}
+
+/// Why an instruction couldn't be decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A 16 bit instruction from the C extension
+    Compressed,
+    /// `ecall` or `ebreak`, which hand control to the environment, so the program ends there
+    EnvironmentCall,
+    /// An instruction outside RV32/RV64 IMFD, such as `fence.i` or a CSR access, or one
+    /// which isn't valid for the register width
+    Unsupported,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Compressed => write!(f, "compressed instructions aren't supported"),
+            DecodeError::EnvironmentCall => write!(f, "environment call"),
+            DecodeError::Unsupported => write!(f, "unsupported instruction"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The fields of a 32 bit instruction
+#[derive(Clone, Copy)]
+struct Fields(u32);
+
+impl Fields {
+    fn opcode(self) -> u32 {
+        self.0 & 0x7f
+    }
+
+    fn rd(self) -> u64 {
+        (self.0 >> 7 & 0x1f) as u64
+    }
+
+    fn funct3(self) -> u32 {
+        self.0 >> 12 & 0x7
+    }
+
+    fn rs1(self) -> u64 {
+        (self.0 >> 15 & 0x1f) as u64
+    }
+
+    fn rs2(self) -> u64 {
+        (self.0 >> 20 & 0x1f) as u64
+    }
+
+    fn rs3(self) -> u64 {
+        (self.0 >> 27) as u64
+    }
+
+    fn funct7(self) -> u32 {
+        self.0 >> 25
+    }
+
+    /// The precision of a floating point operation, or `None` for half or quad precision
+    fn precision(self) -> Option<Precision> {
+        match self.0 >> 25 & 0x3 {
+            0 => Some(Precision::Single),
+            1 => Some(Precision::Double),
+            _ => None,
+        }
+    }
+
+    /// The `rm` field, which is `None` for the dynamic rounding mode
+    fn rounding(self) -> Result<Option<Rounding>, DecodeError> {
+        Ok(Some(match self.funct3() {
+            0 => Rounding::NearestEven,
+            1 => Rounding::TowardZero,
+            2 => Rounding::Down,
+            3 => Rounding::Up,
+            4 => Rounding::NearestMaxMagnitude,
+            7 => return Ok(None),
+            _ => return Err(DecodeError::Unsupported),
+        }))
+    }
+
+    fn i_immediate(self) -> i64 {
+        (self.0 as i32 >> 20) as i64
+    }
+
+    fn s_immediate(self) -> i64 {
+        ((self.0 as i32 >> 25) << 5) as i64 | (self.0 >> 7 & 0x1f) as i64
+    }
+
+    fn b_immediate(self) -> i64 {
+        ((self.0 as i32 >> 31) << 12) as i64
+            | ((self.0 >> 7 & 0x1) << 11) as i64
+            | ((self.0 >> 25 & 0x3f) << 5) as i64
+            | ((self.0 >> 8 & 0xf) << 1) as i64
+    }
+
+    /// The 20 bit immediate of `lui` and `auipc`, which they move up to bits 12 to 31
+    fn u_immediate(self) -> u64 {
+        (self.0 >> 12) as u64
+    }
+
+    fn j_immediate(self) -> i64 {
+        ((self.0 as i32 >> 31) << 20) as i64
+            | (self.0 & 0xff000) as i64
+            | ((self.0 >> 20 & 0x1) << 11) as i64
+            | ((self.0 >> 21 & 0x3ff) << 1) as i64
+    }
+}
+
+fn gp(reg: u64) -> Operand {
+    Operand::Register(Register::GP(reg))
+}
+
+fn fp(reg: u64) -> Operand {
+    Operand::Register(Register::FP(reg))
+}
+
+fn immediate(imm: i64) -> Operand {
+    Operand::Immediate(imm as u64)
+}
+
+/// The operation an RV32 instruction does on registers holding sign extended 32 bit
+/// values. Those whose results would no longer be sign extended use the word operations
+/// from RV64I, or for the upper halves of products, the forms which work out the upper
+/// 32 bits.
+fn rv32_op(op: IntOp) -> IntOp {
+    match op {
+        IntOp::Add => IntOp::AddWord,
+        IntOp::Sub => IntOp::SubWord,
+        IntOp::ShiftLeft => IntOp::ShiftLeftWord,
+        IntOp::ShiftRightLogical => IntOp::ShiftRightLogicalWord,
+        IntOp::ShiftRightArithmetic => IntOp::ShiftRightArithmeticWord,
+        IntOp::Mul => IntOp::MulWord,
+        IntOp::MulHigh => IntOp::MulHighWord,
+        IntOp::MulHighSignedUnsigned => IntOp::MulHighSignedUnsignedWord,
+        IntOp::MulHighUnsigned => IntOp::MulHighUnsignedWord,
+        IntOp::Div => IntOp::DivWord,
+        IntOp::DivUnsigned => IntOp::DivUnsignedWord,
+        IntOp::Rem => IntOp::RemWord,
+        IntOp::RemUnsigned => IntOp::RemUnsignedWord,
+        op => op,
+    }
+}
+
+impl RiscVOp {
+    /// Decode an instruction from the binary encoding, at address `pc` so branches and
+    /// jumps know where they go.
+    ///
+    /// Registers are always 64 bits wide, so an RV32 program runs with its values sign
+    /// extended, and its instructions whose results would be different decode to the
+    /// word operations which give the same values, such as `add` to `addw`.
+    pub fn decode(word: u32, pc: u64, xlen: Xlen) -> Result<Self, DecodeError> {
+        use DecodeError::Unsupported;
+
+        if word & 0x3 != 0x3 {
+            return Err(DecodeError::Compressed);
+        }
+        let fields = Fields(word);
+        let rv32 = xlen == Xlen::Rv32;
+        let rd = fields.rd();
+        let rs1 = fields.rs1();
+        let rs2 = fields.rs2();
+        let funct3 = fields.funct3();
+        let funct7 = fields.funct7();
+
+        let op = match fields.opcode() {
+            // LOAD and LOAD-FP
+            0x03 | 0x07 => {
+                let fp_load = fields.opcode() == 0x07;
+                let width = match (fp_load, funct3) {
+                    (false, 0) => Width::Byte,
+                    (false, 1) => Width::Half,
+                    (false, 2) => Width::Word,
+                    (false, 3) if !rv32 => Width::Double,
+                    (false, 4) => Width::ByteUnsigned,
+                    (false, 5) => Width::HalfUnsigned,
+                    (false, 6) if !rv32 => Width::WordUnsigned,
+                    (true, 2) => Width::Float,
+                    (true, 3) => Width::FloatDouble,
+                    _ => return Err(Unsupported),
+                };
+                let dst = if fp_load { fp(rd) } else { gp(rd) };
+                let mem = Operand::Indirect(Register::GP(rs1), fields.i_immediate() as u64);
+                RiscVOp::Load(width, dst, mem, None)
+            }
+            // STORE and STORE-FP
+            0x23 | 0x27 => {
+                let fp_store = fields.opcode() == 0x27;
+                let width = match (fp_store, funct3) {
+                    (false, 0) => Width::Byte,
+                    (false, 1) => Width::Half,
+                    (false, 2) => Width::Word,
+                    (false, 3) if !rv32 => Width::Double,
+                    (true, 2) => Width::Float,
+                    (true, 3) => Width::FloatDouble,
+                    _ => return Err(Unsupported),
+                };
+                let src = if fp_store { fp(rs2) } else { gp(rs2) };
+                let mem = Operand::Indirect(Register::GP(rs1), fields.s_immediate() as u64);
+                RiscVOp::Store(width, src, mem, None)
+            }
+            // OP-IMM and OP-IMM-32
+            0x13 | 0x1b => {
+                let word_op = fields.opcode() == 0x1b;
+                if word_op && rv32 {
+                    return Err(Unsupported);
+                }
+                // Shifts keep their amount in the low bits of the immediate, and the
+                // kind of shift above it
+                let shift_bits = if rv32 || word_op { 5 } else { 6 };
+                let shift = fields.i_immediate() & ((1 << shift_bits) - 1);
+                let shift_kind = (word >> 20) as i64 >> shift_bits;
+                let (op, imm) = match (word_op, funct3) {
+                    (false, 0) => (IntOp::Add, fields.i_immediate()),
+                    (false, 2) => (IntOp::SetLessThan, fields.i_immediate()),
+                    (false, 3) => (IntOp::SetLessThanUnsigned, fields.i_immediate()),
+                    (false, 4) => (IntOp::Xor, fields.i_immediate()),
+                    (false, 6) => (IntOp::Or, fields.i_immediate()),
+                    (false, 7) => (IntOp::And, fields.i_immediate()),
+                    (false, 1) if shift_kind == 0 => (IntOp::ShiftLeft, shift),
+                    (false, 5) if shift_kind == 0 => (IntOp::ShiftRightLogical, shift),
+                    (false, 5) if shift_kind << shift_bits == 0x400 => {
+                        (IntOp::ShiftRightArithmetic, shift)
+                    }
+                    (true, 0) => (IntOp::AddWord, fields.i_immediate()),
+                    (true, 1) if shift_kind == 0 => (IntOp::ShiftLeftWord, shift),
+                    (true, 5) if shift_kind == 0 => (IntOp::ShiftRightLogicalWord, shift),
+                    (true, 5) if shift_kind << shift_bits == 0x400 => {
+                        (IntOp::ShiftRightArithmeticWord, shift)
+                    }
+                    _ => return Err(Unsupported),
+                };
+                let op = if rv32 { rv32_op(op) } else { op };
+                RiscVOp::IntImmediate(op, gp(rd), gp(rs1), immediate(imm))
+            }
+            // OP and OP-32
+            0x33 | 0x3b => {
+                let word_op = fields.opcode() == 0x3b;
+                if word_op && rv32 {
+                    return Err(Unsupported);
+                }
+                let op = match (word_op, funct7, funct3) {
+                    (false, 0x00, 0) => IntOp::Add,
+                    (false, 0x20, 0) => IntOp::Sub,
+                    (false, 0x00, 1) => IntOp::ShiftLeft,
+                    (false, 0x00, 2) => IntOp::SetLessThan,
+                    (false, 0x00, 3) => IntOp::SetLessThanUnsigned,
+                    (false, 0x00, 4) => IntOp::Xor,
+                    (false, 0x00, 5) => IntOp::ShiftRightLogical,
+                    (false, 0x20, 5) => IntOp::ShiftRightArithmetic,
+                    (false, 0x00, 6) => IntOp::Or,
+                    (false, 0x00, 7) => IntOp::And,
+                    (false, 0x01, 0) => IntOp::Mul,
+                    (false, 0x01, 1) => IntOp::MulHigh,
+                    (false, 0x01, 2) => IntOp::MulHighSignedUnsigned,
+                    (false, 0x01, 3) => IntOp::MulHighUnsigned,
+                    (false, 0x01, 4) => IntOp::Div,
+                    (false, 0x01, 5) => IntOp::DivUnsigned,
+                    (false, 0x01, 6) => IntOp::Rem,
+                    (false, 0x01, 7) => IntOp::RemUnsigned,
+                    (true, 0x00, 0) => IntOp::AddWord,
+                    (true, 0x20, 0) => IntOp::SubWord,
+                    (true, 0x00, 1) => IntOp::ShiftLeftWord,
+                    (true, 0x00, 5) => IntOp::ShiftRightLogicalWord,
+                    (true, 0x20, 5) => IntOp::ShiftRightArithmeticWord,
+                    (true, 0x01, 0) => IntOp::MulWord,
+                    (true, 0x01, 4) => IntOp::DivWord,
+                    (true, 0x01, 5) => IntOp::DivUnsignedWord,
+                    (true, 0x01, 6) => IntOp::RemWord,
+                    (true, 0x01, 7) => IntOp::RemUnsignedWord,
+                    _ => return Err(Unsupported),
+                };
+                let op = if rv32 { rv32_op(op) } else { op };
+                RiscVOp::Int(op, gp(rd), gp(rs1), gp(rs2))
+            }
+            // LUI
+            0x37 => RiscVOp::LoadUpperImmediate(gp(rd), Operand::Immediate(fields.u_immediate())),
+            // AUIPC
+            0x17 => {
+                RiscVOp::AddUpperImmediateToPc(gp(rd), Operand::Immediate(fields.u_immediate()))
+            }
+            // BRANCH
+            0x63 => {
+                let condition = match funct3 {
+                    0 => Condition::Equal,
+                    1 => Condition::NotEqual,
+                    4 => Condition::LessThan,
+                    5 => Condition::GreaterEqual,
+                    6 => Condition::LessThanUnsigned,
+                    7 => Condition::GreaterEqualUnsigned,
+                    _ => return Err(Unsupported),
+                };
+                let target = Label::at(pc.wrapping_add(fields.b_immediate() as u64));
+                RiscVOp::Branch(condition, gp(rs1), gp(rs2), target, None)
+            }
+            // JAL
+            0x6f => {
+                let target = Label::at(pc.wrapping_add(fields.j_immediate() as u64));
+                RiscVOp::JumpAndLink(gp(rd), target)
+            }
+            // JALR
+            0x67 if funct3 == 0 => {
+                let target = Operand::Indirect(Register::GP(rs1), fields.i_immediate() as u64);
+                RiscVOp::JumpAndLinkRegister(gp(rd), target)
+            }
+            // MADD, MSUB, NMSUB and NMADD
+            0x43 | 0x47 | 0x4b | 0x4f => {
+                let op = match fields.opcode() {
+                    0x43 => FusedOp::MulAdd,
+                    0x47 => FusedOp::MulSub,
+                    0x4b => FusedOp::NegMulSub,
+                    _ => FusedOp::NegMulAdd,
+                };
+                let precision = fields.precision().ok_or(Unsupported)?;
+                fields.rounding()?;
+                let src3 = fp(fields.rs3());
+                RiscVOp::FusedMulAdd(op, precision, fp(rd), fp(rs1), fp(rs2), src3)
+            }
+            // OP-FP
+            0x53 => Self::decode_float(fields, rv32)?,
+            // MISC-MEM, of which only `fence` is supported, not `fence.i`. The ordering
+            // in `fm` is ignored, since `fence` doesn't wait for anything anyway.
+            0x0f if funct3 == 0 => RiscVOp::Fence(
+                FenceSet::from_bits((word >> 24 & 0xf) as u8),
+                FenceSet::from_bits((word >> 20 & 0xf) as u8),
+            ),
+            // SYSTEM
+            0x73 if word == 0x0000_0073 || word == 0x0010_0073 => {
+                return Err(DecodeError::EnvironmentCall)
+            }
+            _ => return Err(Unsupported),
+        };
+        Ok(op)
+    }
+
+    /// Decode an OP-FP instruction, whose `funct7` holds the operation and precision.
+    /// Other than conversions, operations always round to the nearest even, so their
+    /// rounding mode is only checked.
+    fn decode_float(fields: Fields, rv32: bool) -> Result<Self, DecodeError> {
+        use DecodeError::Unsupported;
+
+        let precision = fields.precision().ok_or(Unsupported)?;
+        let (rd, rs1, rs2) = (fields.rd(), fields.rs1(), fields.rs2());
+        let funct3 = fields.funct3();
+        let float = |op| RiscVOp::Float(op, precision, fp(rd), fp(rs1), fp(rs2));
+        let unary = |op, dst, src| RiscVOp::FloatUnary(op, precision, dst, src);
+        let format = match precision {
+            Precision::Single => FloatFormat::Single,
+            Precision::Double => FloatFormat::Double,
+        };
+        // The integer format of a conversion, which RV32 only has 32 bit ones of
+        let integer = match rs2 {
+            0 => Some(FloatFormat::Word),
+            1 => Some(FloatFormat::WordUnsigned),
+            2 if !rv32 => Some(FloatFormat::Long),
+            3 if !rv32 => Some(FloatFormat::LongUnsigned),
+            _ => None,
+        };
+
+        Ok(match fields.funct7() >> 2 {
+            0x00 => float(FloatOp::Add).checked(fields)?,
+            0x01 => float(FloatOp::Sub).checked(fields)?,
+            0x02 => float(FloatOp::Mul).checked(fields)?,
+            0x03 => float(FloatOp::Div).checked(fields)?,
+            0x0b if rs2 == 0 => unary(FloatUnaryOp::Sqrt, fp(rd), fp(rs1)).checked(fields)?,
+            0x04 => match funct3 {
+                0 => float(FloatOp::SignInject),
+                1 => float(FloatOp::SignInjectNegate),
+                2 => float(FloatOp::SignInjectXor),
+                _ => return Err(Unsupported),
+            },
+            0x05 => match funct3 {
+                0 => float(FloatOp::Min),
+                1 => float(FloatOp::Max),
+                _ => return Err(Unsupported),
+            },
+            0x14 => {
+                let comparison = match funct3 {
+                    2 => Comparison::Equal,
+                    1 => Comparison::LessThan,
+                    0 => Comparison::LessEqual,
+                    _ => return Err(Unsupported),
+                };
+                RiscVOp::FloatCompare(comparison, precision, gp(rd), fp(rs1), fp(rs2))
+            }
+            // Between single and double precision
+            0x08 => {
+                let from = match (precision, rs2) {
+                    (Precision::Single, 1) => FloatFormat::Double,
+                    (Precision::Double, 0) => FloatFormat::Single,
+                    _ => return Err(Unsupported),
+                };
+                RiscVOp::Convert(format, from, fp(rd), fp(rs1), fields.rounding()?)
+            }
+            // To an integer
+            0x18 => {
+                let to = integer.ok_or(Unsupported)?;
+                RiscVOp::Convert(to, format, gp(rd), fp(rs1), fields.rounding()?)
+            }
+            // From an integer
+            0x1a => {
+                let from = integer.ok_or(Unsupported)?;
+                RiscVOp::Convert(format, from, fp(rd), gp(rs1), fields.rounding()?)
+            }
+            // RV32 has no 64 bit moves between the register files
+            0x1c if rs2 == 0 && funct3 == 0 && !(rv32 && precision == Precision::Double) => {
+                unary(FloatUnaryOp::MoveToInt, gp(rd), fp(rs1))
+            }
+            0x1c if rs2 == 0 && funct3 == 1 => unary(FloatUnaryOp::Classify, gp(rd), fp(rs1)),
+            0x1e if rs2 == 0 && funct3 == 0 && !(rv32 && precision == Precision::Double) => {
+                unary(FloatUnaryOp::MoveFromInt, fp(rd), gp(rs1))
+            }
+            _ => return Err(Unsupported),
+        })
+    }
+
+    /// Check the rounding mode of an arithmetic operation is a valid one
+    fn checked(self, fields: Fields) -> Result<Self, DecodeError> {
+        fields.rounding().map(|_| self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode each encoding at 0x1000 and compare it with how it's displayed. The
+    /// encodings are from the LLVM assembler.
+    fn check(xlen: Xlen, cases: &[(u32, &str)]) {
+        for (word, expected) in cases {
+            let op = RiscVOp::decode(*word, 0x1000, xlen);
+            assert_eq!(
+                op.map(|op| op.to_string()).as_deref(),
+                Ok(*expected),
+                "{word:#010x}"
+            );
+        }
+    }
+
+    fn check_unsupported(xlen: Xlen, words: &[u32]) {
+        for word in words {
+            let op = RiscVOp::decode(*word, 0x1000, xlen).map(|op| op.to_string());
+            assert_eq!(op, Err(DecodeError::Unsupported), "{word:#010x}");
+        }
+    }
+
+    #[test]
+    fn immediates() {
+        check(
+            Xlen::Rv64,
+            &[
+                (0xfeb50ce3, "beq    x10,x11,0xff8"),
+                (0x7e029fe3, "bne    x5,x0,0x1ffe"),
+                (0x8124d063, "bge    x9,x18,0x0"),
+                (0x801ff0ef, "jal    x1,0x800"),
+                (0x7ffff06f, "jal    x0,0x100ffe"),
+                (0xffc280e7, "jalr   x1,-4(x5)"),
+                (0x80b12023, "sw     x11,-2048(x2)"),
+                (0x7e553fa3, "sd     x5,2047(x10)"),
+                (0xfea43c27, "fsd    f10,-8(x8)"),
+                (0xfff5a503, "lw     x10,-1(x11)"),
+                (0xfffff537, "lui    x10,1048575"),
+                (0x12345297, "auipc  x5,74565"),
+            ],
+        );
+    }
+
+    #[test]
+    fn shifts() {
+        check(
+            Xlen::Rv64,
+            &[
+                (0x03f59513, "slli   x10,x11,63"),
+                (0x0205d513, "srli   x10,x11,32"),
+                (0x4215d513, "srai   x10,x11,33"),
+                (0x01f5951b, "slliw  x10,x11,31"),
+                (0x4055d51b, "sraiw  x10,x11,5"),
+            ],
+        );
+        check(
+            Xlen::Rv32,
+            &[
+                (0x01f59513, "slliw  x10,x11,31"),
+                (0x0015d513, "srliw  x10,x11,1"),
+                (0x41f5d513, "sraiw  x10,x11,31"),
+            ],
+        );
+        // srai with bits set above its funct6, and RV32 shifts by more than 31
+        check_unsupported(Xlen::Rv64, &[0x4405d513]);
+        check_unsupported(Xlen::Rv32, &[0x02059513, 0x4215d513]);
+    }
+
+    #[test]
+    fn float_operations() {
+        check(
+            Xlen::Rv64,
+            &[
+                (0x00c5f553, "fadd.s f10,f11,f12"),
+                (0x0ac5f553, "fsub.d f10,f11,f12"),
+                (0x10c5f553, "fmul.s f10,f11,f12"),
+                (0x1ac5f553, "fdiv.d f10,f11,f12"),
+                (0x5a05f553, "fsqrt.d f10,f11"),
+                (0x20c59553, "fsgnjn.s f10,f11,f12"),
+                (0x2ac59553, "fmax.d f10,f11,f12"),
+                (0xa0c5a553, "feq.s  x10,f11,f12"),
+                (0xa2c59553, "flt.d  x10,f11,f12"),
+                (0xc0059553, "fcvt.w.s x10,f11,rtz"),
+                (0xc2259553, "fcvt.l.d x10,f11,rtz"),
+                (0xd235f553, "fcvt.d.lu f10,x11"),
+                (0x4015f553, "fcvt.s.d f10,f11"),
+                (0x42058553, "fcvt.d.s f10,f11,rne"),
+                (0xe0058553, "fmv.x.w x10,f11"),
+                (0xf2058553, "fmv.d.x f10,x11"),
+                (0xe0059553, "fclass.s x10,f11"),
+                (0x6ac5f543, "fmadd.d f10,f11,f12,f13"),
+            ],
+        );
+    }
+
+    #[test]
+    fn rv32_word_operations() {
+        check(
+            Xlen::Rv32,
+            &[
+                (0xfff58513, "addiw  x10,x11,-1"),
+                (0x00c58533, "addw   x10,x11,x12"),
+                (0x40c58533, "subw   x10,x11,x12"),
+                (0x00c59533, "sllw   x10,x11,x12"),
+                (0x40c5d533, "sraw   x10,x11,x12"),
+                (0x00c5c533, "xor    x10,x11,x12"),
+                (0x02c58533, "mulw   x10,x11,x12"),
+                (0x02c59533, "mulh   x10,x11,x12"),
+                (0x02c5a533, "mulhsu x10,x11,x12"),
+                (0x02c5b533, "mulhu  x10,x11,x12"),
+                (0x02c5d533, "divuw  x10,x11,x12"),
+                (0x02c5f533, "remuw  x10,x11,x12"),
+                (0xc2059553, "fcvt.w.d x10,f11,rtz"),
+                (0xd2158553, "fcvt.d.wu f10,x11,rne"),
+            ],
+        );
+    }
+
+    #[test]
+    fn rv64_only_instructions_are_rejected_on_rv32() {
+        // ld, addw, addiw, fcvt.l.d and fmv.x.d
+        let words = [0x0085b503, 0x00c5853b, 0x0005851b, 0xc2259553, 0xe2058553];
+        for word in words {
+            assert!(RiscVOp::decode(word, 0x1000, Xlen::Rv64).is_ok());
+        }
+        check_unsupported(Xlen::Rv32, &words);
+    }
+
+    #[test]
+    fn system_instructions() {
+        check(
+            Xlen::Rv32,
+            &[(0x0100000f, "fence  w,0"), (0x0ff0000f, "fence  iorw,iorw")],
+        );
+        // fence.i
+        check_unsupported(Xlen::Rv32, &[0x0000100f]);
+        for word in [0x00000073, 0x00100073] {
+            let op = RiscVOp::decode(word, 0x1000, Xlen::Rv64).map(|op| op.to_string());
+            assert_eq!(op, Err(DecodeError::EnvironmentCall));
+        }
+        assert_eq!(
+            RiscVOp::decode(0x4501, 0x1000, Xlen::Rv64).map(|op| op.to_string()),
+            Err(DecodeError::Compressed)
+        );
+    }
+}
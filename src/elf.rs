@@ -0,0 +1,210 @@
+use super::*;
+use log::*;
+
+/// Where the stack starts for a binary, unless the configuration sets `x2`
+pub const STACK_POINTER: u64 = 0x7fff_fff0;
+
+const MAGIC: &[u8] = b"\x7fELF";
+const CLASS_32: u8 = 1;
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const TYPE_SHARED: u16 = 3;
+const MACHINE_RISCV: u16 = 243;
+const SEGMENT_LOAD: u32 = 1;
+const FLAG_EXECUTE: u32 = 1;
+
+/// A part of a binary to be loaded into memory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub addr: u64,
+    /// The bytes from the file, which may be fewer than `size`, with the rest zeroed
+    pub data: Vec<u8>,
+    pub size: u64,
+    pub executable: bool,
+}
+
+/// A RISC-V executable, in either the 32 or 64 bit ELF format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Elf {
+    pub xlen: Xlen,
+    /// The address of the first instruction
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+/// Little endian fields of a file, at offsets from its start
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&self, offset: u64, len: u64) -> Result<&[u8], String> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.0.get(offset as usize..end as usize))
+            .ok_or_else(|| format!("truncated at offset {offset:#x}"))
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16, String> {
+        let bytes = self.bytes(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32, String> {
+        let bytes = self.bytes(offset, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: u64) -> Result<u64, String> {
+        let bytes = self.bytes(offset, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// A field which is a word in a 32 bit file and a double word in a 64 bit one.
+    /// Addresses in a 32 bit file are sign extended, like the registers holding them.
+    fn address(&self, offset: u64, xlen: Xlen) -> Result<u64, String> {
+        match xlen {
+            Xlen::Rv32 => Ok(xlen.extend(self.u32(offset)? as u64)),
+            Xlen::Rv64 => self.u64(offset),
+        }
+    }
+}
+
+impl Elf {
+    /// Parse an executable, keeping the segments which are loaded into memory
+    pub fn parse(file: &[u8]) -> Result<Self, String> {
+        let file = Reader(file);
+        if file.bytes(0, 4).ok() != Some(MAGIC) {
+            return Err("not an ELF file".to_string());
+        }
+        let xlen = match file.bytes(4, 1)?[0] {
+            CLASS_32 => Xlen::Rv32,
+            CLASS_64 => Xlen::Rv64,
+            class => return Err(format!("unknown ELF class {class}")),
+        };
+        if file.bytes(5, 1)?[0] != LITTLE_ENDIAN {
+            return Err("not little endian".to_string());
+        }
+        match file.u16(16)? {
+            TYPE_EXECUTABLE | TYPE_SHARED => {}
+            kind => return Err(format!("not an executable, the file type is {kind}")),
+        }
+        let machine = file.u16(18)?;
+        if machine != MACHINE_RISCV {
+            return Err(format!("not a RISC-V binary, the machine is {machine}"));
+        }
+
+        let entry = file.address(24, xlen)?;
+        let (phoff, phentsize, phnum) = match xlen {
+            Xlen::Rv32 => (file.u32(28)? as u64, file.u16(42)?, file.u16(44)?),
+            Xlen::Rv64 => (file.u64(32)?, file.u16(54)?, file.u16(56)?),
+        };
+        let mut segments = Vec::new();
+        for i in 0..phnum as u64 {
+            let header = phoff + i * phentsize as u64;
+            if file.u32(header)? != SEGMENT_LOAD {
+                continue;
+            }
+            let (flags, offset, addr, file_size, size) = match xlen {
+                Xlen::Rv32 => (
+                    file.u32(header + 24)?,
+                    file.u32(header + 4)? as u64,
+                    file.address(header + 8, xlen)?,
+                    file.u32(header + 16)? as u64,
+                    file.u32(header + 20)? as u64,
+                ),
+                Xlen::Rv64 => (
+                    file.u32(header + 4)?,
+                    file.u64(header + 8)?,
+                    file.address(header + 16, xlen)?,
+                    file.u64(header + 32)?,
+                    file.u64(header + 40)?,
+                ),
+            };
+            segments.push(Segment {
+                addr,
+                data: file.bytes(offset, file_size)?.to_vec(),
+                size,
+                executable: flags & FLAG_EXECUTE != 0,
+            });
+        }
+        Ok(Elf {
+            xlen,
+            entry,
+            segments,
+        })
+    }
+
+    /// Copy the segments into memory, and start at the entry point
+    pub fn load(&self, state: &mut ArchState) {
+        for segment in &self.segments {
+            // Only bytes which were written show up in a dump, so the zeroed rest of a
+            // segment is left out
+            for (i, byte) in segment.data.iter().enumerate() {
+                state
+                    .memory
+                    .write(segment.addr.wrapping_add(i as u64), 1, *byte as u64);
+            }
+        }
+        state.pc = self.entry;
+    }
+
+    /// Whether an address is in one of the executable segments
+    pub fn is_code(&self, addr: u64) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| segment.executable && addr.wrapping_sub(segment.addr) < segment.size)
+    }
+}
+
+/// Run a binary which has been loaded into `state`, from its entry point, for at most
+/// `budget` instructions, and return the instructions which ran. It stops early at an
+/// `ecall` or `ebreak`, or an instruction which can't be decoded or isn't in the code.
+pub fn run_binary(elf: &Elf, state: &mut ArchState, budget: u64) -> Vec<Instruction> {
+    let mut executed = Vec::new();
+    loop {
+        let pc = state.pc;
+        if executed.len() as u64 == budget {
+            info!("Stopped after {budget} instructions, at {pc:#x}");
+            break;
+        }
+        if !elf.is_code(pc) {
+            warn!("Stopped at {pc:#x}, which isn't in an executable segment");
+            break;
+        }
+        let word = state.memory.read(pc, 4) as u32;
+        let op = match RiscVOp::decode(word, pc, elf.xlen) {
+            Ok(op) => op,
+            Err(DecodeError::EnvironmentCall) => {
+                info!("Stopped at the environment call at {pc:#x}");
+                break;
+            }
+            Err(e) => {
+                warn!("Stopped at {pc:#x}, {word:#010x}: {e}");
+                break;
+            }
+        };
+
+        let mut instruction = Instruction {
+            pc,
+            op,
+            next_pc: pc.wrapping_add(4),
+        };
+        if let Some(addr) = instruction.op.effective_addr(&state.registers) {
+            instruction.op = instruction.op.with_addr(addr);
+        }
+        let execution = instruction.op.execute(pc, state);
+        if let Some(taken) = execution.taken {
+            instruction.op = instruction.op.with_taken(taken);
+        }
+        if let Some(target) = execution.target {
+            instruction.next_pc = target;
+        } else if execution.taken == Some(true) {
+            if let Some(addr) = instruction.op.label().and_then(|label| label.address()) {
+                instruction.next_pc = addr;
+            }
+        }
+        state.pc = instruction.next_pc;
+        executed.push(instruction);
+    }
+    executed
+}
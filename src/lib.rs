@@ -1,9 +1,26 @@
+mod cache;
 mod config;
+mod decode;
+mod elf;
+mod logs;
+mod lsq;
+mod memory;
 mod op;
 mod pipeline;
+mod predictor;
 mod table;
+mod toml;
+mod trace;
 
+pub use cache::*;
 pub use config::*;
+pub use decode::*;
+pub use elf::*;
+pub use logs::*;
+pub use lsq::*;
+pub use memory::*;
 pub use op::*;
 pub use pipeline::*;
+pub use predictor::*;
 pub use table::*;
+pub use trace::*;
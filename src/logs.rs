@@ -0,0 +1,614 @@
+use super::*;
+use log::*;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// The formats a trace can be read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// This simulator's own format, one instruction in assembly per line
+    Trace,
+    /// The commit log Spike writes with `--log-commits`
+    Spike,
+    /// The log QEMU writes with `-d in_asm,exec`
+    Qemu,
+}
+
+impl TraceFormat {
+    pub const ALL: [TraceFormat; 3] = [TraceFormat::Trace, TraceFormat::Spike, TraceFormat::Qemu];
+
+    /// The name used on the command line
+    pub const fn name(&self) -> &'static str {
+        match self {
+            TraceFormat::Trace => "trace",
+            TraceFormat::Spike => "spike",
+            TraceFormat::Qemu => "qemu",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    /// Guess the format from the first line which isn't blank. Spike's lines start with
+    /// `core`, and QEMU's log starts with a line of dashes before the first block it
+    /// translates. Anything else is taken to be a trace.
+    pub fn detect(contents: &str) -> Self {
+        let first = contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default();
+        let mut tokens = first.split_whitespace();
+        if tokens.next() == Some("core") && tokens.next().is_some_and(|t| t.ends_with(':')) {
+            TraceFormat::Spike
+        } else if first.starts_with("----") && first.chars().all(|c| c == '-')
+            || first.starts_with("IN:")
+            || first.starts_with("Trace ")
+        {
+            TraceFormat::Qemu
+        } else {
+            TraceFormat::Trace
+        }
+    }
+
+    /// Read a trace in this format. The register width only applies to logs, and is
+    /// worked out from their addresses if it isn't given.
+    pub fn parse(&self, contents: &str, xlen: Option<Xlen>) -> Result<Trace, Vec<TraceError>> {
+        match self {
+            TraceFormat::Trace => parse_trace(contents),
+            TraceFormat::Spike => parse_spike_log(contents, xlen),
+            TraceFormat::Qemu => parse_qemu_log(contents, xlen),
+        }
+    }
+}
+
+impl Display for TraceFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// An instruction which ran, as a simulator logged it, before it's decoded, since that
+/// depends on the register width of the whole log
+#[derive(Clone, Copy)]
+struct Logged {
+    /// The line it was logged on, starting at 1
+    line: usize,
+    pc: u64,
+    encoding: u64,
+    /// The memory address it accessed, if it was logged
+    addr: Option<u64>,
+}
+
+impl Logged {
+    /// Decode the instruction, with its addresses sign extended for RV32
+    fn decode(&self, xlen: Xlen) -> Entry {
+        let pc = xlen.extend(self.pc);
+        let mut op = decode(self.encoding, pc, xlen);
+        if let Some(addr) = self.addr {
+            op = op.map(|op| op.with_addr(xlen.extend(addr)));
+        }
+        Entry {
+            line: self.line,
+            pc,
+            op,
+        }
+    }
+}
+
+/// A decoded instruction which ran
+#[derive(Clone, Copy)]
+struct Entry {
+    /// The line it was logged on, starting at 1
+    line: usize,
+    pc: u64,
+    /// The decoded instruction, which may not be one this simulator supports
+    op: Result<RiscVOp, DecodeError>,
+}
+
+/// The tokens of a line, with their columns, starting at 0
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+    line.split_whitespace()
+        .map(|token| (token.as_ptr() as usize - line.as_ptr() as usize, token))
+        .collect()
+}
+
+/// The digits of a hexadecimal number, which may or may not start with `0x`
+fn hex_digits(text: &str) -> &str {
+    text.strip_prefix("0x").unwrap_or(text)
+}
+
+fn hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(hex_digits(text), 16).ok()
+}
+
+/// Parse a hexadecimal token, or make an error of the given kind for it
+fn parse_hex(
+    (column, token): (usize, &str),
+    line: usize,
+    kind: TraceErrorKind,
+) -> Result<u64, TraceError> {
+    hex(token).ok_or_else(|| TraceError {
+        line,
+        column: column + 1,
+        token: token.to_string(),
+        kind,
+    })
+}
+
+/// The widest address in a log, which gives its register width. Both simulators print
+/// addresses and registers with 8 digits for RV32 and 16 for RV64.
+#[derive(Default)]
+struct Widest(usize);
+
+impl Widest {
+    /// Parse a hexadecimal address, and count its digits
+    fn parse(
+        &mut self,
+        token: (usize, &str),
+        line: usize,
+        kind: TraceErrorKind,
+    ) -> Result<u64, TraceError> {
+        let value = parse_hex(token, line, kind)?;
+        self.see(token.1);
+        Ok(value)
+    }
+
+    fn see(&mut self, token: &str) {
+        self.0 = self.0.max(hex_digits(token).len());
+    }
+
+    /// The register width, unless it was given
+    fn xlen(&self, xlen: Option<Xlen>) -> Xlen {
+        xlen.unwrap_or(if self.0 > 8 { Xlen::Rv64 } else { Xlen::Rv32 })
+    }
+}
+
+/// Decode an instruction, which is an unsupported one if it's longer than 32 bits
+fn decode(encoding: u64, pc: u64, xlen: Xlen) -> Result<RiscVOp, DecodeError> {
+    u32::try_from(encoding)
+        .map_err(|_| DecodeError::Unsupported)
+        .and_then(|encoding| RiscVOp::decode(encoding, pc, xlen))
+}
+
+/// Turn the instructions in a log into a trace, in the order they ran. Each ran after
+/// the one before it, so that's where branches went. Instructions this simulator can't
+/// run, such as compressed ones, are left out, as is a branch or jump at the end of
+/// the log, since where it went wasn't logged.
+fn into_trace(entries: Vec<Entry>) -> Trace {
+    let mut instructions = Vec::new();
+    let mut left_out = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let mut op = match entry.op {
+            Ok(op) => op,
+            // These hand control to the environment, which the log shows returning
+            Err(DecodeError::EnvironmentCall) => continue,
+            Err(e) => {
+                if left_out == 0 {
+                    warn!(
+                        "line {}: left out the instruction at {:#x}: {e}",
+                        entry.line, entry.pc
+                    );
+                }
+                left_out += 1;
+                continue;
+            }
+        };
+        let mut instruction = Instruction::new(entry.pc, op);
+        match entries.get(i + 1) {
+            Some(next) => {
+                instruction.next_pc = next.pc;
+                if op.is_branch() {
+                    op = op.with_taken(next.pc != entry.pc.wrapping_add(4));
+                    instruction.op = op;
+                }
+            }
+            None if op.is_branch() || op.is_jump() => {
+                warn!(
+                    "line {}: left out the {} at {:#x}, since the log ends before it goes \
+                     anywhere",
+                    entry.line,
+                    op.mnemonic(),
+                    entry.pc
+                );
+                continue;
+            }
+            None => {}
+        }
+        instructions.push(instruction);
+    }
+    if left_out > 1 {
+        warn!("Left out {left_out} instructions which can't be simulated");
+    }
+    Trace {
+        instructions,
+        labels: SymbolTable::default(),
+        addressed: true,
+    }
+}
+
+/// Parse the address, encoding and memory address of an instruction in Spike's log
+fn spike_entry(
+    number: usize,
+    line: &str,
+    pc: (usize, &str),
+    (column, word): (usize, &str),
+    rest: &[(usize, &str)],
+    widest: &mut Widest,
+) -> Result<Logged, TraceError> {
+    let pc = widest.parse(pc, number, TraceErrorKind::BadPc)?;
+    let encoding = word.trim_start_matches('(').trim_end_matches(')');
+    let encoding = parse_hex((column + 1, encoding), number, TraceErrorKind::BadEncoding)?;
+    let addr = match rest.iter().position(|(_, token)| *token == "mem") {
+        Some(at) => {
+            let addr = rest.get(at + 1).copied().unwrap_or((line.len(), ""));
+            Some(widest.parse(addr, number, TraceErrorKind::BadMemoryAddress)?)
+        }
+        None => None,
+    };
+    Ok(Logged {
+        line: number,
+        pc,
+        encoding,
+        addr,
+    })
+}
+
+/// Parse the commit log Spike writes with `--log-commits`, which has a line like
+/// `core   0: 3 0x0000000080000010 (0x0182b283) x5  0x0000000000001020 mem 0x0000000080000018`
+/// for each instruction it retires. The instructions are decoded from their encoding in
+/// brackets, and loads and stores take the address after `mem`. Other lines, such as
+/// those for traps, are skipped.
+///
+/// The register width is the one given, or else RV64 if any address is wider than 8 digits.
+pub fn parse_spike_log(contents: &str, xlen: Option<Xlen>) -> Result<Trace, Vec<TraceError>> {
+    let mut entries = Vec::new();
+    let mut widest = Widest::default();
+    let mut errors = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let number = i + 1;
+        let tokens = tokens(line);
+        let [(_, "core"), _, (_, privilege), pc, word, rest @ ..] = tokens.as_slice() else {
+            debug!("Skipping line {number}: {line}");
+            continue;
+        };
+        if !privilege.chars().all(|c| c.is_ascii_digit()) || !word.1.starts_with('(') {
+            debug!("Skipping line {number}: {line}");
+            continue;
+        }
+
+        match spike_entry(number, line, *pc, *word, rest, &mut widest) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let xlen = widest.xlen(xlen);
+    Ok(into_trace(
+        entries.iter().map(|entry| entry.decode(xlen)).collect(),
+    ))
+}
+
+/// A register and its value from QEMU's `-d cpu` dump, such as `x10/a0 0000000000000001`
+fn dumped_register(name: &str, value: &str) -> Option<(Register, u64)> {
+    let (number, _) = name.split_once('/')?;
+    let register = if let Some(n) = number.strip_prefix('x') {
+        Register::GP(n.parse().ok().filter(|n| *n < 32)?)
+    } else {
+        Register::FP(number.strip_prefix('f')?.parse().ok().filter(|n| *n < 32)?)
+    };
+    Some((register, hex(value)?))
+}
+
+/// Parse the address and encoding of an instruction in a block QEMU translated
+fn qemu_entry(
+    number: usize,
+    line: &str,
+    pc: (usize, &str),
+    word: Option<(usize, &str)>,
+    widest: &mut Widest,
+) -> Result<Logged, TraceError> {
+    let pc = widest.parse(pc, number, TraceErrorKind::BadPc)?;
+    let word = word.unwrap_or((line.len(), ""));
+    let encoding = parse_hex(word, number, TraceErrorKind::BadEncoding)?;
+    Ok(Logged {
+        line: number,
+        pc,
+        encoding,
+        addr: None,
+    })
+}
+
+/// Parse the log QEMU writes with `-d in_asm,exec,nochain`. Each block of instructions is
+/// logged after `IN:` when it's translated, with lines like
+/// `0x0000000080000000:  00000297          auipc t0,0`, and a line like
+/// `Trace 0: 0x7f2f5c000100 [00000000/0000000080000000/00000000/ff020000]` each time it
+/// runs. The instructions are decoded from their encodings.
+///
+/// QEMU doesn't log memory addresses, so they're worked out by running the instructions
+/// from the registers in the `-d cpu` dump which follows each `Trace` line, if there is
+/// one. Loads don't know what's in memory, so an address that depends on one is only
+/// right once the next dump comes along.
+///
+/// The register width is the one given, or else RV64 if any address in a block, or
+/// register in a dump, is wider than 8 digits. The addresses on `Trace` lines don't
+/// count, since newer versions of QEMU always print them with 16.
+pub fn parse_qemu_log(contents: &str, xlen: Option<Xlen>) -> Result<Trace, Vec<TraceError>> {
+    // The instructions of each block which has been translated, by its first address
+    let mut blocks: BTreeMap<u64, Vec<Logged>> = BTreeMap::new();
+    // The block being translated, if any
+    let mut block: Option<Vec<Logged>> = None;
+    let mut entries: Vec<Logged> = Vec::new();
+    let mut widest = Widest::default();
+    // Where the block which ran last starts in `entries`
+    let mut block_start = 0;
+    // The registers dumped before the entry at each index
+    let mut dumps: BTreeMap<usize, Vec<(Register, u64)>> = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let number = i + 1;
+        let tokens = tokens(line);
+        let first = tokens.first().map_or("", |(_, token)| *token);
+
+        if let Some(instructions) = &mut block {
+            if tokens.is_empty() {
+                let instructions = block.take().unwrap_or_default();
+                if let Some(start) = instructions.first() {
+                    blocks.insert(start.pc, instructions);
+                }
+                continue;
+            }
+            // Other lines, such as `Priv: 3; Virt: 0`, describe the block
+            let Some(pc) = first.strip_suffix(':').filter(|pc| pc.starts_with("0x")) else {
+                continue;
+            };
+            let word = tokens.get(1).copied();
+            match qemu_entry(number, line, (tokens[0].0, pc), word, &mut widest) {
+                Ok(entry) => instructions.push(entry),
+                Err(e) => errors.push(e),
+            }
+        } else if first == "IN:" {
+            block = Some(Vec::new());
+        } else if first == "Trace" {
+            // Older versions of QEMU log only the address in the brackets, and newer
+            // ones log it after `cs_base`
+            let Some(start) = line.find('[') else {
+                continue;
+            };
+            let end = line[start..].find(']').map_or(line.len(), |n| start + n);
+            let fields: Vec<_> = line[start + 1..end].split('/').collect();
+            let field = fields
+                .get(1)
+                .or(fields.first())
+                .copied()
+                .unwrap_or_default();
+            let column = start + 1 + line[start + 1..].find(field).unwrap_or_default();
+            let pc = match parse_hex((column, field), number, TraceErrorKind::BadPc) {
+                Ok(pc) => pc,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            match blocks.get(&pc) {
+                Some(instructions) => {
+                    block_start = entries.len();
+                    entries.extend(instructions);
+                }
+                None => errors.push(TraceError {
+                    line: number,
+                    column: column + 1,
+                    token: field.to_string(),
+                    kind: TraceErrorKind::UnknownBlock,
+                }),
+            }
+        } else {
+            // The registers are dumped in pairs of a name and a value
+            for pair in tokens.windows(2) {
+                if pair[0].1 == "pc" || pair[0].1.starts_with('x') {
+                    widest.see(pair[1].1);
+                }
+                if let Some(register) = dumped_register(pair[0].1, pair[1].1) {
+                    // The dump is of the state the block that was just logged starts from
+                    dumps.entry(block_start).or_default().push(register);
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    if dumps.is_empty() {
+        warn!(
+            "The QEMU log has no register dumps, so memory addresses are worked out with \
+             every register starting at 0. Log with `-d in_asm,exec,cpu,nochain` for the \
+             right ones."
+        );
+    }
+    let xlen = widest.xlen(xlen);
+    let mut entries: Vec<Entry> = entries.iter().map(|entry| entry.decode(xlen)).collect();
+    let mut state = ArchState::default();
+    for (i, entry) in entries.iter_mut().enumerate() {
+        for (register, value) in dumps.get(&i).into_iter().flatten() {
+            // Floating point registers are always dumped with all their bits
+            let value = match register {
+                Register::GP(_) => xlen.extend(*value),
+                Register::FP(_) => *value,
+            };
+            state.registers.write(*register, value);
+        }
+        if let Ok(op) = &mut entry.op {
+            if let Some(addr) = op.effective_addr(&state.registers) {
+                *op = op.with_addr(addr);
+            }
+            op.execute(entry.pc, &mut state);
+        }
+    }
+    Ok(into_trace(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instructions(trace: &Trace) -> Vec<String> {
+        trace
+            .instructions
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect()
+    }
+
+    const SPIKE_64: &str = "\
+core   0: 3 0x0000000080000000 (0x00000297) x5  0x0000000080000000
+core   0: 3 0x0000000080000004 (0x0182b283) x5  0x0000000000001020 mem 0x0000000080000018
+core   0: 3 0x0000000080000008 (0x00028463)
+core   0: exception trap_illegal_instruction, epc 0x000000008000000c
+core   0: 3 0x0000000080000010 (0x00000073)
+";
+
+    const SPIKE_32: &str = "\
+core   0: 3 0x80000000 (0x00000297) x5  0x80000000
+core   0: 3 0x80000004 (0x0002a303) x6  0x00000000 mem 0x80000000
+core   0: 3 0x80000008 (0x00628463)
+core   0: 3 0x8000000c (0x00000013)
+";
+
+    const QEMU_32: &str = "\
+----------------
+IN: 
+Priv: 3; Virt: 0
+0x80000000:  00000297          auipc           t0,0
+0x80000004:  0002a303          lw              t1,0(t0)
+0x80000008:  00628463          beq             t0,t1,8
+
+Trace 0: 0x7f0000000100 [00000000/0000000080000000/00000000/ff020000] 
+ pc       80000000
+ x0/zero  00000000 x1/ra    00000000 x2/sp    00000000 x3/gp    00000000
+----------------
+IN: 
+0x8000000c:  00000013          nop
+
+Trace 0: 0x7f0000000140 [00000000/000000008000000c/00000000/ff020000] 
+";
+
+    #[test]
+    fn detect() {
+        assert_eq!(TraceFormat::detect(SPIKE_64), TraceFormat::Spike);
+        assert_eq!(TraceFormat::detect(QEMU_32), TraceFormat::Qemu);
+        assert_eq!(
+            TraceFormat::detect("\nIN: \n0x80000000:  00000297"),
+            TraceFormat::Qemu
+        );
+        assert_eq!(
+            TraceFormat::detect("Trace 0: 0x7f0000000100 [0000000080000000]"),
+            TraceFormat::Qemu
+        );
+        assert_eq!(TraceFormat::detect("add x1,x2,x3"), TraceFormat::Trace);
+        assert_eq!(
+            TraceFormat::detect("core: add x1,x2,x3"),
+            TraceFormat::Trace
+        );
+        assert_eq!(TraceFormat::detect(""), TraceFormat::Trace);
+    }
+
+    #[test]
+    fn spike_log() {
+        let trace = parse_spike_log(SPIKE_64, None).unwrap();
+        assert_eq!(
+            instructions(&trace),
+            [
+                "0x80000000: auipc  x5,0",
+                "0x80000004: ld     x5,24(x5):2147483672",
+                "0x80000008: beq    x5,x0,0x80000010:T",
+            ]
+        );
+        assert_eq!(trace.instructions[2].next_pc, 0x80000010);
+    }
+
+    #[test]
+    fn rv32_spike_log() {
+        let trace = parse_spike_log(SPIKE_32, None).unwrap();
+        assert_eq!(
+            instructions(&trace),
+            [
+                "0xffffffff80000000: auipc  x5,0",
+                "0xffffffff80000004: lw     x6,0(x5):18446744071562067968",
+                "0xffffffff80000008: beq    x5,x6,0xffffffff80000010:N",
+                "0xffffffff8000000c: addiw  x0,x0,0",
+            ]
+        );
+
+        // A width which is given wins over the one the addresses imply
+        let trace = parse_spike_log(SPIKE_32, Some(Xlen::Rv64)).unwrap();
+        assert_eq!(
+            trace.instructions[3].to_string(),
+            "0x8000000c: addi   x0,x0,0"
+        );
+    }
+
+    #[test]
+    fn trailing_branch_is_left_out() {
+        let log = SPIKE_32.lines().take(3).collect::<Vec<_>>().join("\n");
+        let trace = parse_spike_log(&log, None).unwrap();
+        assert_eq!(trace.instructions.len(), 2);
+    }
+
+    #[test]
+    fn bad_spike_log() {
+        let errors = parse_spike_log("core   0: 3 0x8000zz00 (0x00000013)", None).unwrap_err();
+        assert_eq!(
+            errors,
+            [TraceError {
+                line: 1,
+                column: 13,
+                token: "0x8000zz00".to_string(),
+                kind: TraceErrorKind::BadPc,
+            }]
+        );
+        let errors = parse_spike_log("core   0: 3 0x80000000 (0x0002a303) mem", None);
+        assert_eq!(
+            errors.unwrap_err()[0].kind,
+            TraceErrorKind::BadMemoryAddress
+        );
+    }
+
+    #[test]
+    fn qemu_log() {
+        // The blocks are found from the 16 digit addresses in the `Trace` lines, but the
+        // instructions are still RV32 ones
+        let trace = parse_qemu_log(QEMU_32, None).unwrap();
+        assert_eq!(
+            instructions(&trace),
+            [
+                "0xffffffff80000000: auipc  x5,0",
+                "0xffffffff80000004: lw     x6,0(x5):18446744071562067968",
+                "0xffffffff80000008: beq    x5,x6,0xffffffff80000010:N",
+                "0xffffffff8000000c: addiw  x0,x0,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn qemu_log_of_an_unknown_block() {
+        let log = "Trace 0: 0x7f0000000100 [00000000/0000000080000000/00000000/ff020000]";
+        let errors = parse_qemu_log(log, None).unwrap_err();
+        assert_eq!(
+            errors,
+            [TraceError {
+                line: 1,
+                column: 35,
+                token: "0000000080000000".to_string(),
+                kind: TraceErrorKind::UnknownBlock,
+            }]
+        );
+    }
+}
@@ -0,0 +1,384 @@
+use super::*;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Display, Formatter},
+};
+
+/// How loads decide whether they can go ahead of older stores
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DisambiguationPolicy {
+    /// Loads wait until every older store has calculated its address
+    Conservative,
+    /// Loads know every address in advance from the trace, so they only wait
+    /// for older stores to the same address
+    #[default]
+    Perfect,
+    /// Loads go ahead of older stores whose addresses aren't known yet, and are
+    /// replayed if one of them turns out to write the same address
+    Speculative,
+    /// Speculative, except loads wait for the stores which they conflicted with before
+    StoreSets,
+}
+
+impl DisambiguationPolicy {
+    pub const ALL: [DisambiguationPolicy; 4] = [
+        DisambiguationPolicy::Conservative,
+        DisambiguationPolicy::Perfect,
+        DisambiguationPolicy::Speculative,
+        DisambiguationPolicy::StoreSets,
+    ];
+
+    /// The name used in configuration files
+    pub const fn name(&self) -> &'static str {
+        match self {
+            DisambiguationPolicy::Conservative => "conservative",
+            DisambiguationPolicy::Perfect => "perfect",
+            DisambiguationPolicy::Speculative => "speculative",
+            DisambiguationPolicy::StoreSets => "store_sets",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|policy| policy.name() == name)
+    }
+}
+
+impl Display for DisambiguationPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What a load in the memory stage should do, given the older stores in flight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadSource {
+    /// No older store writes the address, so the load reads memory
+    Memory,
+    /// The youngest older store to the address has its data, which the load can take
+    Forward(usize),
+    /// The load has to wait for an older store to calculate its address or get its data
+    Wait(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct QueueEntry {
+    tag: usize,
+    pc: u64,
+    store: bool,
+    addr: u64,
+    /// Whether the address, and for a store its data, have been calculated
+    executed: bool,
+    /// Where a load got its value from, once it has
+    source: Option<LoadSource>,
+    /// The store a load is predicted to depend on by the store sets
+    depends_on: Option<usize>,
+}
+
+/// Predicts which stores a load depends on, from the stores it was
+/// previously found reading ahead of, as in Chrysos and Emer's store sets
+#[derive(Clone, Debug)]
+pub struct StoreSets {
+    /// The store set of each load and store, indexed by their address
+    ids: Vec<Option<usize>>,
+    /// The tag of the last store issued in each store set, until it executes
+    last_store: BTreeMap<usize, usize>,
+    next_id: usize,
+}
+
+impl StoreSets {
+    pub fn new(entries: usize) -> Self {
+        Self {
+            ids: vec![None; entries],
+            last_store: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    fn index(&self, pc: u64) -> usize {
+        ((pc >> 2) % self.ids.len() as u64) as usize
+    }
+
+    /// The store a load should wait for, if it's in a store set
+    pub fn load(&self, pc: u64) -> Option<usize> {
+        let id = self.ids[self.index(pc)]?;
+        self.last_store.get(&id).copied()
+    }
+
+    /// Remember a store as the last one issued in its set
+    pub fn store(&mut self, pc: u64, tag: usize) {
+        if let Some(id) = self.ids[self.index(pc)] {
+            self.last_store.insert(id, tag);
+        }
+    }
+
+    /// Forget a store which has executed or been squashed, so loads no longer wait for it
+    pub fn forget(&mut self, tag: usize) {
+        self.last_store.retain(|_, store| *store != tag);
+    }
+
+    /// Put a load and a store it conflicted with in the same set, merging their sets if
+    /// they're both in one
+    pub fn train(&mut self, load_pc: u64, store_pc: u64) {
+        let (load, store) = (self.index(load_pc), self.index(store_pc));
+        let id = match (self.ids[load], self.ids[store]) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(id), None) | (None, Some(id)) => id,
+            (None, None) => {
+                self.next_id += 1;
+                self.next_id - 1
+            }
+        };
+        self.ids[load] = Some(id);
+        self.ids[store] = Some(id);
+    }
+}
+
+/// The loads and stores in flight, in the order they were issued.
+/// Stores write memory when they commit, so until then a younger load to the
+/// same address has to take its value from the store.
+#[derive(Clone, Debug)]
+pub struct LoadStoreQueue {
+    entries: VecDeque<QueueEntry>,
+    capacity: usize,
+    policy: DisambiguationPolicy,
+    store_sets: StoreSets,
+}
+
+impl LoadStoreQueue {
+    pub fn new(config: &Config) -> Self {
+        let capacity = config.load_store_buffer_entries as usize;
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            policy: config.disambiguation,
+            store_sets: StoreSets::new(config.store_set_entries as usize),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.capacity
+    }
+
+    /// Add a load or store when it's issued
+    pub fn push(&mut self, tag: usize, pc: u64, store: bool, addr: u64) {
+        let mut depends_on = None;
+        if self.policy == DisambiguationPolicy::StoreSets {
+            if store {
+                self.store_sets.store(pc, tag);
+            } else {
+                depends_on = self.store_sets.load(pc);
+            }
+        }
+        self.entries.push_back(QueueEntry {
+            tag,
+            pc,
+            store,
+            addr,
+            executed: false,
+            source: None,
+            depends_on,
+        });
+    }
+
+    /// Record that an instruction has calculated its address, and for a store, has its data.
+    /// Returns the tags of the younger loads to the same address which already got their
+    /// value from somewhere older, and have to be replayed, oldest first.
+    pub fn executed(&mut self, tag: usize) -> Vec<usize> {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.tag == tag) else {
+            return Vec::new();
+        };
+        entry.executed = true;
+        let entry = *entry;
+        if !entry.store {
+            return Vec::new();
+        }
+        self.store_sets.forget(tag);
+
+        let violations = self
+            .entries
+            .iter()
+            .filter(|load| !load.store && load.tag > tag && load.addr == entry.addr)
+            .filter(|load| match load.source {
+                Some(LoadSource::Memory) => true,
+                Some(LoadSource::Forward(store)) => store < tag,
+                _ => false,
+            })
+            .map(|load| (load.tag, load.pc))
+            .collect::<Vec<_>>();
+        for (_, pc) in &violations {
+            self.store_sets.train(*pc, entry.pc);
+        }
+        violations.into_iter().map(|(tag, _)| tag).collect()
+    }
+
+    /// Where the load with this tag gets its value from. Once it has decided to read memory
+    /// or take a store's value, that doesn't change.
+    pub fn load_source(&mut self, tag: usize) -> LoadSource {
+        let Some(load) = self.entries.iter().find(|entry| entry.tag == tag).copied() else {
+            return LoadSource::Memory;
+        };
+        if let Some(source) = load.source {
+            return source;
+        }
+
+        let older_stores = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.store && entry.tag < tag);
+        let mut matching = older_stores.clone().filter(|store| store.addr == load.addr);
+        let source = match self.policy {
+            DisambiguationPolicy::Conservative => {
+                match older_stores.clone().find(|store| !store.executed) {
+                    Some(store) => LoadSource::Wait(store.tag),
+                    None => Self::forward_from(matching.next()),
+                }
+            }
+            DisambiguationPolicy::Perfect => Self::forward_from(matching.next()),
+            DisambiguationPolicy::Speculative | DisambiguationPolicy::StoreSets => {
+                let predicted = load
+                    .depends_on
+                    .and_then(|dependence| older_stores.clone().find(|s| s.tag == dependence))
+                    .filter(|store| !store.executed);
+                match predicted {
+                    Some(store) => LoadSource::Wait(store.tag),
+                    // Stores which haven't calculated their address yet are assumed not to match
+                    None => Self::forward_from(matching.find(|store| store.executed)),
+                }
+            }
+        };
+
+        if !matches!(source, LoadSource::Wait(_)) {
+            if let Some(entry) = self.entries.iter_mut().find(|entry| entry.tag == tag) {
+                entry.source = Some(source);
+            }
+        }
+        source
+    }
+
+    /// Take the value of the youngest older store to the same address, if there is one
+    fn forward_from(store: Option<&QueueEntry>) -> LoadSource {
+        match store {
+            Some(store) if store.executed => LoadSource::Forward(store.tag),
+            Some(store) => LoadSource::Wait(store.tag),
+            None => LoadSource::Memory,
+        }
+    }
+
+    /// Remove an instruction when it commits
+    pub fn remove(&mut self, tag: usize) {
+        self.entries.retain(|entry| entry.tag != tag);
+    }
+
+    /// Remove every instruction issued after the one with this tag
+    pub fn squash_after(&mut self, tag: usize) {
+        for entry in self.entries.iter().filter(|entry| entry.tag > tag) {
+            self.store_sets.forget(entry.tag);
+        }
+        self.entries.retain(|entry| entry.tag <= tag);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(policy: DisambiguationPolicy) -> LoadStoreQueue {
+        LoadStoreQueue::new(&Config {
+            disambiguation: policy,
+            ..Config::default()
+        })
+    }
+
+    #[test]
+    fn loads_take_the_youngest_older_store() {
+        let mut lsq = queue(DisambiguationPolicy::Perfect);
+        lsq.push(1, 0x0, true, 64);
+        lsq.push(2, 0x4, true, 64);
+        lsq.push(3, 0x8, false, 64);
+        lsq.push(4, 0xc, false, 128);
+        assert_eq!(lsq.load_source(3), LoadSource::Wait(2));
+        assert_eq!(lsq.load_source(4), LoadSource::Memory);
+        lsq.executed(1);
+        assert_eq!(lsq.load_source(3), LoadSource::Wait(2));
+        lsq.executed(2);
+        assert_eq!(lsq.load_source(3), LoadSource::Forward(2));
+    }
+
+    #[test]
+    fn loads_dont_take_younger_or_committed_stores() {
+        let mut lsq = queue(DisambiguationPolicy::Perfect);
+        lsq.push(1, 0x0, true, 64);
+        lsq.push(2, 0x4, false, 64);
+        lsq.push(3, 0x8, true, 128);
+        lsq.push(4, 0xc, false, 128);
+        lsq.executed(1);
+        lsq.executed(3);
+        assert_eq!(lsq.load_source(4), LoadSource::Forward(3));
+        // Once the store commits, memory has its value
+        lsq.remove(1);
+        assert_eq!(lsq.load_source(2), LoadSource::Memory);
+    }
+
+    #[test]
+    fn conservative_loads_wait_for_every_store_address() {
+        let mut lsq = queue(DisambiguationPolicy::Conservative);
+        lsq.push(1, 0x0, true, 64);
+        lsq.push(2, 0x4, false, 128);
+        assert_eq!(lsq.load_source(2), LoadSource::Wait(1));
+        assert!(lsq.executed(1).is_empty());
+        assert_eq!(lsq.load_source(2), LoadSource::Memory);
+    }
+
+    #[test]
+    fn speculative_loads_are_replayed() {
+        let mut lsq = queue(DisambiguationPolicy::Speculative);
+        lsq.push(1, 0x0, true, 64);
+        lsq.push(2, 0x4, false, 64);
+        lsq.push(3, 0x8, false, 128);
+        assert_eq!(lsq.load_source(2), LoadSource::Memory);
+        assert_eq!(lsq.load_source(3), LoadSource::Memory);
+        assert_eq!(lsq.executed(1), [2]);
+
+        // Once the load is replayed, it takes the store's value
+        lsq.squash_after(1);
+        lsq.push(4, 0x4, false, 64);
+        assert_eq!(lsq.load_source(4), LoadSource::Forward(1));
+    }
+
+    #[test]
+    fn store_sets_learn_from_violations() {
+        let mut lsq = queue(DisambiguationPolicy::StoreSets);
+        lsq.push(1, 0x0, true, 64);
+        lsq.push(2, 0x4, false, 64);
+        assert_eq!(lsq.load_source(2), LoadSource::Memory);
+        assert_eq!(lsq.executed(1), [2]);
+        lsq.remove(1);
+        lsq.remove(2);
+
+        // The same store and load wait for each other from then on, even when they
+        // don't conflict
+        lsq.push(3, 0x0, true, 256);
+        lsq.push(4, 0x4, false, 512);
+        assert_eq!(lsq.load_source(4), LoadSource::Wait(3));
+        assert!(lsq.executed(3).is_empty());
+        assert_eq!(lsq.load_source(4), LoadSource::Memory);
+    }
+
+    #[test]
+    fn perfect_loads_are_never_replayed() {
+        let mut lsq = queue(DisambiguationPolicy::Perfect);
+        lsq.push(1, 0x0, true, 64);
+        lsq.push(2, 0x4, false, 64);
+        assert_eq!(lsq.load_source(2), LoadSource::Wait(1));
+        assert!(lsq.executed(1).is_empty());
+    }
+}
@@ -1,65 +1,167 @@
 use log::*;
+use std::process::ExitCode;
 use tomasulos::*;
 
-const CONFIG: Config = Config {
-    eff_addr_buffer_entries: 2,
-    fp_add_buffer_entries: 3,
-    fp_mul_buffer_entries: 3,
-    int_buffer_entries: 2,
-    reorder_buffer_entries: 5,
+mod cli;
+use cli::{exit, Options};
 
-    fp_add_buffer_latency: 2,
-    fp_sub_buffer_latency: 2,
-    fp_mul_buffer_latency: 5,
-    fp_div_buffer_latency: 10,
-};
+/// The configuration file to read if `--config` isn't given
+const DEFAULT_CONFIG: &str = "config.txt";
 
-#[allow(dead_code)]
-fn parse_file(filename: &str) -> Vec<RiscVOp> {
-    use std::fs::File;
-    use std::io::{BufRead, BufReader};
+/// Read and parse a trace or program, logging every malformed line
+fn load_trace(
+    filename: Option<&str>,
+    parse: impl FnOnce(&str) -> Result<Trace, Vec<TraceError>>,
+) -> Result<Trace, ()> {
+    use std::io::Read;
 
-    let file = File::open(filename).unwrap();
-    let reader = BufReader::new(file);
-    let mut instructions = Vec::new();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let op = RiscVOp::parse(&line);
-        instructions.push(op);
+    let name = filename.unwrap_or("<stdin>");
+    let mut contents = String::new();
+    let read = match filename {
+        Some(filename) => std::fs::read_to_string(filename).map(|c| contents = c),
+        None => std::io::stdin().read_to_string(&mut contents).map(|_| ()),
+    };
+    if let Err(e) = read {
+        error!("Failed to read {name}: {e}");
+        return Err(());
     }
-    instructions
-}
-
-fn parse_stdin() -> Vec<RiscVOp> {
-    use std::io::{BufRead, BufReader};
 
-    let stdin = std::io::stdin();
-    let reader = BufReader::new(stdin);
-    let mut instructions = Vec::new();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let op = RiscVOp::parse(&line);
-        instructions.push(op);
-    }
-    instructions
+    parse(&contents).map_err(|errors| {
+        for e in &errors {
+            error!("{name}: {e}");
+        }
+        error!("{name}: {} malformed line(s)", errors.len());
+    })
 }
 
-fn main() {
+fn main() -> ExitCode {
     env_logger::init();
 
-    let config = Config::parse("config.txt").unwrap_or_else(|e| {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", cli::usage());
+            return ExitCode::from(exit::SUCCESS);
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{}", cli::usage());
+            return ExitCode::from(exit::USAGE);
+        }
+    };
+
+    let config = match &options.config {
+        Some(filename) => Config::parse(filename),
+        None if std::path::Path::new(DEFAULT_CONFIG).exists() => Config::parse(DEFAULT_CONFIG),
+        None => {
+            info!("No {DEFAULT_CONFIG} found, using the default configuration");
+            Ok(Config::default())
+        }
+    };
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::from(exit::CONFIG);
+        }
+    };
+    options.apply_overrides(&mut config);
+    if let Some(filename) = &options.regs {
+        match Config::parse_registers(filename) {
+            Ok(registers) => config.registers.extend(registers),
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::from(exit::CONFIG);
+            }
+        }
+    }
+    if let Err(e) = config.validate() {
         error!("{}", e);
-        std::process::exit(1);
-    });
+        return ExitCode::from(exit::CONFIG);
+    }
     info!("{}", config);
 
-    let instructions = parse_stdin();
-    for (i, op) in instructions.iter().enumerate() {
-        info!("{}: {}", i, op);
+    let mut state = ArchState {
+        pc: 0,
+        registers: RegisterFile::new(&config.registers),
+        memory: SparseMemory::new(&config.data),
+    };
+    let instructions = if let Some(filename) = &options.program {
+        // Run the program to find the instructions it executes
+        let program = match load_trace(Some(filename), parse_program) {
+            Ok(program) => program,
+            Err(()) => return ExitCode::from(exit::TRACE),
+        };
+        run_program(&program, &mut state, options.max_instructions)
+    } else if let Some(filename) = &options.elf {
+        let elf = match std::fs::read(filename) {
+            Ok(bytes) => Elf::parse(&bytes).map_err(|e| format!("{filename}: {e}")),
+            Err(e) => Err(format!("Failed to read {filename}: {e}")),
+        };
+        let elf = match elf {
+            Ok(elf) => elf,
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::from(exit::TRACE);
+            }
+        };
+        // Binaries expect a stack, so give them one if the configuration doesn't
+        if !config.registers.contains_key(&Register::GP(2)) {
+            state.registers.write(Register::GP(2), STACK_POINTER);
+        }
+        elf.load(&mut state);
+        run_binary(&elf, &mut state, options.max_instructions)
+    } else {
+        let mut format = TraceFormat::Trace;
+        let trace = load_trace(options.trace.as_deref(), |contents| {
+            format = options
+                .trace_format
+                .unwrap_or_else(|| TraceFormat::detect(contents));
+            info!("Reading the trace as {format}");
+            format.parse(contents, options.xlen)
+        });
+        let mut trace = match trace {
+            Ok(trace) => trace,
+            Err(()) => return ExitCode::from(exit::TRACE),
+        };
+        // Work out the addresses of loads and stores, and with --functional, the branch
+        // outcomes. Logs from other simulators already have them.
+        if format == TraceFormat::Trace {
+            execute_trace(&mut trace, &mut state, options.functional);
+        }
+        trace.instructions
+    };
+    for (i, instruction) in instructions.iter().enumerate() {
+        info!("{}: {}", i, instruction);
     }
 
     let mut table = TomasuloTable::new();
-    println!("{}", CONFIG);
     table.run(instructions, &config);
-    println!("{}", table);
+
+    let output = match options.format {
+        OutputFormat::Table => format!("{}\n{}\n", config, table),
+        format => table.render(format),
+    };
+    let written = match &options.output {
+        Some(filename) => std::fs::write(filename, output),
+        None => {
+            use std::io::Write;
+            std::io::stdout().write_all(output.as_bytes())
+        }
+    };
+    if let Err(e) = written {
+        error!(
+            "Failed to write {}: {e}",
+            options.output.as_deref().unwrap_or("<stdout>")
+        );
+        return ExitCode::from(exit::OUTPUT);
+    }
+
+    if let Some(filename) = &options.dump_state {
+        if let Err(e) = std::fs::write(filename, state.to_string()) {
+            error!("Failed to write {filename}: {e}");
+            return ExitCode::from(exit::OUTPUT);
+        }
+    }
+
+    ExitCode::from(exit::SUCCESS)
 }
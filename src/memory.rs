@@ -0,0 +1,386 @@
+use super::*;
+use std::fmt::{self, Display, Formatter};
+
+/// Whether the lower levels of the hierarchy also hold the lines in the levels above them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Inclusion {
+    /// Every line in a level is also in the levels below it, so a line
+    /// thrown out of a lower level is thrown out of the levels above it too
+    #[default]
+    Inclusive,
+    /// A line is in at most one level. Lines move up to the L1 when they're used,
+    /// and the lines they replace move down a level.
+    Exclusive,
+}
+
+impl Inclusion {
+    pub const ALL: [Inclusion; 2] = [Inclusion::Inclusive, Inclusion::Exclusive];
+
+    /// The name used in configuration files
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Inclusion::Inclusive => "inclusive",
+            Inclusion::Exclusive => "exclusive",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|policy| policy.name() == name)
+    }
+}
+
+impl Display for Inclusion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Where a load or store found its line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServedBy {
+    /// The line was in this level, counting from 0 for the L1
+    Cache(usize),
+    /// The line was already being fetched into this level for an earlier miss,
+    /// so the access waited for that instead
+    Mshr(usize),
+    /// The line wasn't in any level
+    Memory,
+}
+
+impl ServedBy {
+    /// Whether the line was in the L1
+    pub fn is_l1_hit(&self) -> bool {
+        *self == ServedBy::Cache(0)
+    }
+}
+
+impl Display for ServedBy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ServedBy::Cache(level) => write!(f, "L{}", level + 1),
+            ServedBy::Mshr(_) => write!(f, "MSHR"),
+            ServedBy::Memory => write!(f, "mem"),
+        }
+    }
+}
+
+/// How one level of the hierarchy was used
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    /// The accesses which didn't find their line, including those which
+    /// waited for a line already being fetched
+    pub misses: u64,
+    /// The cycles loads couldn't start a miss because every MSHR was in use
+    pub mshr_full: u64,
+}
+
+/// A load or store which has been looked up in the hierarchy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    /// The cycles until the data arrives, counting the one it was looked up in
+    pub latency: u64,
+    pub served_by: ServedBy,
+}
+
+/// A miss status holding register, tracking a line being fetched into a level
+#[derive(Clone, Copy, Debug)]
+struct Mshr {
+    line: u64,
+    /// The cycle the line arrives, and the register is free again
+    ready: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Level {
+    cache: Cache,
+    mshrs: Vec<Mshr>,
+    stats: CacheStats,
+}
+
+impl Level {
+    /// When the line holding an address will arrive, if it's being fetched
+    fn pending(&self, addr: u64) -> Option<u64> {
+        let line = self.cache.line(addr);
+        self.mshrs
+            .iter()
+            .find(|mshr| mshr.line == line)
+            .map(|mshr| mshr.ready)
+    }
+
+    fn mshrs_full(&self) -> bool {
+        self.mshrs.len() as u64 >= self.cache.config().mshrs
+    }
+}
+
+/// The data caches, from the L1 down to the last level before memory.
+/// Loads which miss hold an MSHR in each level they missed in until their line
+/// arrives, so several misses can be outstanding at once. Later loads to a line
+/// which is already being fetched wait for it instead of starting another miss.
+///
+/// Lines are brought into the caches as soon as they're looked up. Only the
+/// MSHRs know that they haven't really arrived yet.
+#[derive(Clone, Debug)]
+pub struct MemoryHierarchy {
+    levels: Vec<Level>,
+    inclusion: Inclusion,
+    /// The cycles to fetch a line from memory, after missing in every level
+    memory_latency: u64,
+    cycle: u64,
+}
+
+impl MemoryHierarchy {
+    /// Build the caches described by the configuration, or `None` if there's no L1
+    pub fn new(config: &Config) -> Option<Self> {
+        let caches = config.caches();
+        let memory_latency = caches.last()?.miss_latency;
+        Some(Self {
+            levels: caches
+                .into_iter()
+                .map(|config| Level {
+                    cache: Cache::new(config),
+                    mshrs: Vec::new(),
+                    stats: CacheStats::default(),
+                })
+                .collect(),
+            inclusion: config.inclusion,
+            memory_latency,
+            cycle: 0,
+        })
+    }
+
+    /// The cycles a load which hits in the L1 spends in the memory stage
+    pub fn hit_latency(&self) -> u64 {
+        self.levels[0].cache.config().hit_latency
+    }
+
+    /// The hits, misses and MSHR stalls of each level, from the L1 down
+    pub fn stats(&self) -> Vec<CacheStats> {
+        self.levels.iter().map(|level| level.stats).collect()
+    }
+
+    /// Move on to the next cycle, freeing the MSHRs whose lines have arrived
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+        let cycle = self.cycle;
+        for level in &mut self.levels {
+            level.mshrs.retain(|mshr| mshr.ready > cycle);
+        }
+    }
+
+    /// Look up the address a load reads. Returns the level whose MSHRs were all in
+    /// use if it missed and couldn't be tracked, in which case it has to try again.
+    pub fn load(&mut self, addr: u64) -> Result<Access, usize> {
+        // Find how far down the line is without changing anything, since the load
+        // might not be able to go ahead
+        let mut latency = 0;
+        let mut served_by = ServedBy::Memory;
+        let mut full = None;
+        for (i, level) in self.levels.iter().enumerate() {
+            latency += level.cache.config().hit_latency;
+            if let Some(ready) = level.pending(addr) {
+                latency = latency.max(ready - self.cycle);
+                served_by = ServedBy::Mshr(i);
+                break;
+            }
+            if level.cache.contains(addr) {
+                served_by = ServedBy::Cache(i);
+                break;
+            }
+            if level.mshrs_full() {
+                full = Some(i);
+                break;
+            }
+        }
+        if let Some(i) = full {
+            self.levels[i].stats.mshr_full += 1;
+            return Err(i);
+        }
+        if served_by == ServedBy::Memory {
+            latency += self.memory_latency;
+        }
+
+        let missed = match served_by {
+            ServedBy::Cache(i) | ServedBy::Mshr(i) => i,
+            ServedBy::Memory => self.levels.len(),
+        };
+        let ready = self.cycle + latency;
+        let line = self.levels[0].cache.line(addr);
+        for level in &mut self.levels[..missed] {
+            level.mshrs.push(Mshr { line, ready });
+        }
+        self.fill(addr, served_by);
+        Ok(Access { latency, served_by })
+    }
+
+    /// Write the address a store writes as it commits. Stores go through a write buffer,
+    /// so they don't take an MSHR or hold anything up.
+    pub fn store(&mut self, addr: u64) -> ServedBy {
+        let served_by = self
+            .levels
+            .iter()
+            .position(|level| level.cache.contains(addr))
+            .map_or(ServedBy::Memory, ServedBy::Cache);
+        self.fill(addr, served_by);
+        served_by
+    }
+
+    /// Count an access, and move its line up to the L1
+    fn fill(&mut self, addr: u64, served_by: ServedBy) {
+        let found = match served_by {
+            ServedBy::Cache(i) => {
+                self.levels[i].stats.hits += 1;
+                self.levels[i].cache.lookup(addr);
+                Some(i)
+            }
+            ServedBy::Mshr(i) => {
+                self.levels[i].stats.misses += 1;
+                Some(i)
+            }
+            ServedBy::Memory => None,
+        };
+        let missed = found.unwrap_or(self.levels.len());
+        for level in &mut self.levels[..missed] {
+            level.stats.misses += 1;
+        }
+
+        match self.inclusion {
+            Inclusion::Inclusive => {
+                // Fill from the bottom up, so the levels above see the line last
+                for i in (0..missed).rev() {
+                    if let Some(victim) = self.levels[i].cache.insert(addr) {
+                        for level in &mut self.levels[..i] {
+                            level.cache.remove(victim);
+                        }
+                    }
+                }
+            }
+            Inclusion::Exclusive => {
+                if let Some(i) = found.filter(|i| *i > 0) {
+                    self.levels[i].cache.remove(addr);
+                }
+                if found != Some(0) {
+                    // Each level's victim moves down to the next, and the last level's is dropped
+                    let mut line = Some(addr);
+                    for level in &mut self.levels {
+                        let Some(addr) = line else {
+                            break;
+                        };
+                        line = level.cache.insert(addr);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cache of two 64 byte lines in one set, which takes a cycle to hit, and ten more
+    /// to fetch a line from memory
+    fn two_lines() -> CacheConfig {
+        CacheConfig {
+            size: 128,
+            associativity: 2,
+            line_size: 64,
+            hit_latency: 1,
+            miss_latency: 10,
+            mshrs: 2,
+            ..CacheConfig::default()
+        }
+    }
+
+    fn hierarchy(levels: usize, inclusion: Inclusion) -> MemoryHierarchy {
+        MemoryHierarchy::new(&Config {
+            l1_cache: Some(two_lines()),
+            l2_cache: (levels > 1).then(two_lines),
+            inclusion,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    /// Load an address once the hierarchy has finished any misses in flight
+    fn load_when_idle(caches: &mut MemoryHierarchy, addr: u64) -> ServedBy {
+        for _ in 0..100 {
+            caches.tick();
+        }
+        caches.load(addr).unwrap().served_by
+    }
+
+    #[test]
+    fn loads_to_a_line_being_fetched_wait_for_it() {
+        let mut caches = hierarchy(1, Inclusion::Inclusive);
+        let miss = caches.load(0x100).unwrap();
+        assert_eq!(miss.served_by, ServedBy::Memory);
+        assert_eq!(miss.latency, 11);
+
+        for _ in 0..3 {
+            caches.tick();
+        }
+        let merged = caches.load(0x108).unwrap();
+        assert_eq!(merged.served_by, ServedBy::Mshr(0));
+        assert_eq!(merged.latency, 8);
+
+        // The line arrives at cycle 11, after which it hits
+        for _ in 0..7 {
+            caches.tick();
+        }
+        assert_eq!(caches.load(0x110).unwrap().served_by, ServedBy::Mshr(0));
+        caches.tick();
+        let hit = caches.load(0x118).unwrap();
+        assert_eq!(hit.served_by, ServedBy::Cache(0));
+        assert_eq!(hit.latency, 1);
+
+        let stats = caches.stats()[0];
+        assert_eq!((stats.hits, stats.misses, stats.mshr_full), (1, 3, 0));
+    }
+
+    #[test]
+    fn misses_wait_for_a_free_mshr() {
+        let mut caches = hierarchy(1, Inclusion::Inclusive);
+        caches.load(0x000).unwrap();
+        caches.load(0x040).unwrap();
+        assert_eq!(caches.load(0x080), Err(0));
+        assert_eq!(caches.stats()[0].mshr_full, 1);
+        for _ in 0..11 {
+            caches.tick();
+        }
+        assert_eq!(caches.load(0x080).unwrap().served_by, ServedBy::Memory);
+    }
+
+    #[test]
+    fn inclusive_evictions_throw_lines_out_of_the_levels_above() {
+        let mut caches = hierarchy(2, Inclusion::Inclusive);
+        load_when_idle(&mut caches, 0x000);
+        load_when_idle(&mut caches, 0x040);
+        // The L1 keeps 0x000 as its most recently used line, but the L2 doesn't see
+        // the hit, so throws it out for the next line and takes it out of the L1 too
+        assert_eq!(load_when_idle(&mut caches, 0x000), ServedBy::Cache(0));
+        load_when_idle(&mut caches, 0x080);
+        assert_eq!(load_when_idle(&mut caches, 0x000), ServedBy::Memory);
+    }
+
+    #[test]
+    fn exclusive_levels_hold_different_lines() {
+        let mut caches = hierarchy(2, Inclusion::Exclusive);
+        for addr in [0x000, 0x040, 0x080, 0x0c0] {
+            assert_eq!(load_when_idle(&mut caches, addr), ServedBy::Memory);
+        }
+        // The first two lines moved down to the L2 to make room, and move back up when
+        // they're used, pushing the L1's least recently used line down in their place
+        assert_eq!(load_when_idle(&mut caches, 0x000), ServedBy::Cache(1));
+        assert_eq!(load_when_idle(&mut caches, 0x000), ServedBy::Cache(0));
+        assert_eq!(load_when_idle(&mut caches, 0x080), ServedBy::Cache(1));
+        assert_eq!(load_when_idle(&mut caches, 0x040), ServedBy::Cache(1));
+
+        // With an inclusive L2 of the same size, only the last two lines are kept
+        let mut caches = hierarchy(2, Inclusion::Inclusive);
+        for addr in [0x000, 0x040, 0x080, 0x0c0] {
+            load_when_idle(&mut caches, addr);
+        }
+        assert_eq!(load_when_idle(&mut caches, 0x000), ServedBy::Memory);
+    }
+}
@@ -1,4 +1,11 @@
-use std::fmt::{self, *};
+use super::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Debug, Display, Formatter},
+    num::FpCategory,
+    ops::Deref,
+    sync::Mutex,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum FunctionalUnit {
@@ -6,6 +13,105 @@ pub enum FunctionalUnit {
     EffectAddr,
     FPUMul,
     FPUAdd,
+    /// Integer multiplies and divides
+    IntMulDiv,
+}
+
+impl FunctionalUnit {
+    pub const ALL: [FunctionalUnit; 5] = [
+        FunctionalUnit::ALU,
+        FunctionalUnit::EffectAddr,
+        FunctionalUnit::FPUMul,
+        FunctionalUnit::FPUAdd,
+        FunctionalUnit::IntMulDiv,
+    ];
+
+    /// The name used for this unit in configuration files
+    pub const fn name(&self) -> &'static str {
+        match self {
+            FunctionalUnit::ALU => "int",
+            FunctionalUnit::EffectAddr => "eff_addr",
+            FunctionalUnit::FPUMul => "fp_mul",
+            FunctionalUnit::FPUAdd => "fp_add",
+            FunctionalUnit::IntMulDiv => "mul_div",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|unit| unit.name() == name)
+    }
+}
+
+/// Groups of operations which share a default latency
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OpClass {
+    /// Integer ALU operations
+    Int,
+    /// Integer multiplies
+    IntMul,
+    /// Integer divides and remainders
+    IntDiv,
+    Branch,
+    Load,
+    Store,
+    FPAdd,
+    FPSub,
+    FPMul,
+    FPDiv,
+    /// Fused multiply-adds
+    FPFma,
+    FPSqrt,
+    /// Comparisons, minimums, maximums and classification
+    FPCompare,
+    /// Sign injection, and moves between the integer and floating point registers
+    FPMove,
+    /// Conversions between integers and each precision
+    FPConvert,
+}
+
+impl OpClass {
+    pub const ALL: [OpClass; 15] = [
+        OpClass::Int,
+        OpClass::IntMul,
+        OpClass::IntDiv,
+        OpClass::Branch,
+        OpClass::Load,
+        OpClass::Store,
+        OpClass::FPAdd,
+        OpClass::FPSub,
+        OpClass::FPMul,
+        OpClass::FPDiv,
+        OpClass::FPFma,
+        OpClass::FPSqrt,
+        OpClass::FPCompare,
+        OpClass::FPMove,
+        OpClass::FPConvert,
+    ];
+
+    /// The name used for this class in configuration files
+    pub const fn name(&self) -> &'static str {
+        match self {
+            OpClass::Int => "int",
+            OpClass::IntMul => "int_mul",
+            OpClass::IntDiv => "int_div",
+            OpClass::Branch => "branch",
+            OpClass::Load => "load",
+            OpClass::Store => "store",
+            OpClass::FPAdd => "fp_add",
+            OpClass::FPSub => "fp_sub",
+            OpClass::FPMul => "fp_mul",
+            OpClass::FPDiv => "fp_div",
+            OpClass::FPFma => "fp_fma",
+            OpClass::FPSqrt => "fp_sqrt",
+            OpClass::FPCompare => "fp_cmp",
+            OpClass::FPMove => "fp_move",
+            OpClass::FPConvert => "fp_cvt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|class| class.name() == name)
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -14,14 +120,23 @@ pub enum Register {
     FP(u64),
 }
 
+/// The number of registers in each register file
+const REGISTER_COUNT: u64 = 32;
+
 impl Register {
+    /// Parse an integer or floating point register, such as `x1` or `f2`
+    pub fn parse(reg: &str) -> Option<Self> {
+        Self::parse_gp(reg).or_else(|| Self::parse_fp(reg))
+    }
+
     fn parse_gp(reg: &str) -> Option<Self> {
         let reg = reg.trim();
 
-        if reg.starts_with('x') {
-            let reg = reg.trim_start_matches('x');
+        if let Some(reg) = reg.strip_prefix('x') {
             if let Ok(reg) = reg.parse::<u64>() {
-                return Some(Register::GP(reg));
+                if reg < REGISTER_COUNT {
+                    return Some(Register::GP(reg));
+                }
             }
         }
 
@@ -31,19 +146,16 @@ impl Register {
     fn parse_fp(reg: &str) -> Option<Self> {
         let reg = reg.trim();
 
-        if reg.starts_with('f') {
-            let reg = reg.trim_start_matches('f');
+        if let Some(reg) = reg.strip_prefix('f') {
             if let Ok(reg) = reg.parse::<u64>() {
-                return Some(Register::FP(reg));
+                if reg < REGISTER_COUNT {
+                    return Some(Register::FP(reg));
+                }
             }
         }
 
         None
     }
-
-    fn parse(reg: &str) -> Option<Self> {
-        Self::parse_gp(reg).or_else(|| Self::parse_fp(reg))
-    }
 }
 
 impl From<Operand> for Register {
@@ -73,28 +185,91 @@ impl Debug for Register {
     }
 }
 
-const GLOBAL_NAME_SIZE: usize = 64;
+/// A label, such as the target of a branch. Names are interned, so labels are cheap
+/// to copy and compare, and can be as long as they like.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Label {
+    name: &'static str,
+    /// The index of the instruction the label marks, once the trace has been parsed,
+    /// or `None` if it isn't defined
+    target: Option<usize>,
+    /// The address the label is at, for instructions decoded from a binary
+    address: Option<u64>,
+}
+
+impl Label {
+    /// A label with this name, which may contain letters, digits, `_` and `.`
+    pub fn parse(name: &str) -> Option<Self> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+        valid.then(|| Self {
+            name: intern(name),
+            target: None,
+            address: None,
+        })
+    }
+
+    /// A label for an address, named like `0x10074`
+    pub fn at(address: u64) -> Self {
+        Self {
+            name: intern(&format!("{address:#x}")),
+            target: None,
+            address: Some(address),
+        }
+    }
+
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub const fn target(&self) -> Option<usize> {
+        self.target
+    }
+
+    pub const fn address(&self) -> Option<u64> {
+        self.address
+    }
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Debug for Label {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        match self.target {
+            Some(target) => write!(f, " (#{target})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Keep one copy of each label name for the rest of the run
+fn intern(name: &str) -> &'static str {
+    static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name) {
+        return name;
+    }
+    let name: &'static str = Box::leak(name.into());
+    names.insert(name);
+    name
+}
 
 #[derive(Clone, Copy)]
 pub enum Operand {
     Immediate(u64),
     Register(Register),
     Indirect(Register, u64),
-    Global([char; GLOBAL_NAME_SIZE]),
     None,
 }
 
 impl Operand {
-    fn global(name: &str) -> Self {
-        let chars = name.chars().collect::<Vec<char>>();
-        // Convert into array
-        let mut result = ['\0'; GLOBAL_NAME_SIZE];
-        for i in 0..chars.len() {
-            result[i] = chars[i];
-        }
-        Self::Global(result)
-    }
-
     pub const fn is_reg(&self) -> bool {
         matches!(self, Operand::Register(_))
     }
@@ -114,369 +289,2658 @@ impl Operand {
             _ => None,
         }
     }
+}
 
-    fn parse(reg: &str) -> Self {
-        let reg = reg.trim();
-        if let Some(reg) = Register::parse(reg) {
-            return Operand::Register(reg);
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Immediate(imm) => write!(f, "{}", *imm as i64),
+            Operand::Register(reg) => write!(f, "{}", reg),
+            Operand::Indirect(reg, imm) => write!(f, "{}({reg})", *imm as i64),
+            Operand::None => write!(f, ""),
         }
+    }
+}
 
-        if reg.starts_with("0x") {
-            let reg = reg.trim_start_matches("0x").trim();
-            if let Ok(reg) = u64::from_str_radix(reg, 16) {
-                return Operand::Immediate(reg);
-            }
+impl Debug for Operand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Immediate(imm) => write!(f, "{}", *imm as i64),
+            Operand::Register(reg) => write!(f, "{}", reg),
+            Operand::Indirect(reg, imm) => write!(f, "{}({reg})", *imm as i64),
+            Operand::None => write!(f, ""),
         }
+    }
+}
 
-        if let Ok(reg) = reg.parse::<u64>() {
-            return Operand::Immediate(reg);
-        }
+/// The most registers an instruction reads or writes, which is the three sources of a
+/// fused multiply-add
+pub const MAX_OPERANDS: usize = 3;
 
-        // Parse an indirect address
-        // Get the string until the `(` character
-        let mut operand = reg.split('(');
-        if operand.clone().count() == 1 {
-            return Self::global(operand.next().unwrap());
-        }
+/// The registers an instruction reads or writes, held inline since there are so few
+#[derive(Clone, Copy)]
+pub struct OperandList {
+    operands: [Operand; MAX_OPERANDS],
+    len: usize,
+}
 
-        let offset = operand.next().unwrap().trim();
-        let offset = if let Ok(offset) = offset.parse::<u64>() {
-            offset
-        } else {
-            panic!("Could not parse offset \"{offset}\"");
+impl OperandList {
+    /// The operands which are registers, or addresses computed from one, in order
+    fn registers(operands: &[Operand]) -> Self {
+        let mut result = Self {
+            operands: [Operand::None; MAX_OPERANDS],
+            len: 0,
         };
+        for operand in operands
+            .iter()
+            .filter(|operand| operand.dep_reg().is_some())
+        {
+            result.operands[result.len] = *operand;
+            result.len += 1;
+        }
+        result
+    }
+}
 
-        // Get the string until the `)` character
-        let mut operand = operand.next().unwrap().split(')');
-        let reg = operand.next().unwrap().trim();
-        let reg = if let Some(reg) = Register::parse(reg) {
-            reg
-        } else {
-            panic!("Could not parse register \"{reg}\"");
-        };
+impl Deref for OperandList {
+    type Target = [Operand];
 
-        Operand::Indirect(reg, offset)
+    fn deref(&self) -> &[Operand] {
+        &self.operands[..self.len]
     }
 }
 
-impl Display for Operand {
+impl Debug for OperandList {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// How many bytes a load or store moves, and how a load fills the rest of the register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+    Double,
+    ByteUnsigned,
+    HalfUnsigned,
+    WordUnsigned,
+    /// A single precision value, in a floating point register
+    Float,
+    /// A double precision value, in a floating point register
+    FloatDouble,
+}
+
+impl Width {
+    pub const ALL: [Width; 9] = [
+        Width::Byte,
+        Width::Half,
+        Width::Word,
+        Width::Double,
+        Width::ByteUnsigned,
+        Width::HalfUnsigned,
+        Width::WordUnsigned,
+        Width::Float,
+        Width::FloatDouble,
+    ];
+
+    pub const fn bytes(&self) -> u64 {
         match self {
-            Operand::Immediate(imm) => write!(f, "{}", imm),
-            Operand::Register(reg) => write!(f, "{}", reg),
-            Operand::Indirect(reg, imm) => write!(f, "{imm}({reg})"),
-            Operand::Global(name) => {
-                for c in name.iter() {
-                    if *c == '\0' {
-                        break;
-                    }
-                    write!(f, "{}", c)?;
-                }
-                Ok(())
-            }
-            Operand::None => write!(f, ""),
+            Width::Byte | Width::ByteUnsigned => 1,
+            Width::Half | Width::HalfUnsigned => 2,
+            Width::Word | Width::WordUnsigned | Width::Float => 4,
+            Width::Double | Width::FloatDouble => 8,
         }
     }
-}
 
-impl Debug for Operand {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    pub const fn is_fp(&self) -> bool {
+        matches!(self, Width::Float | Width::FloatDouble)
+    }
+
+    const fn load_mnemonic(&self) -> &'static str {
         match self {
-            Operand::Immediate(imm) => write!(f, "{}", imm),
-            Operand::Register(reg) => write!(f, "{}", reg),
-            Operand::Indirect(reg, imm) => write!(f, "{imm}({reg})"),
-            Operand::Global(name) => write!(f, "{}", name.iter().collect::<String>()),
-            Operand::None => write!(f, ""),
+            Width::Byte => "lb",
+            Width::Half => "lh",
+            Width::Word => "lw",
+            Width::Double => "ld",
+            Width::ByteUnsigned => "lbu",
+            Width::HalfUnsigned => "lhu",
+            Width::WordUnsigned => "lwu",
+            Width::Float => "flw",
+            Width::FloatDouble => "fld",
+        }
+    }
+
+    /// Stores don't extend anything, so there are no unsigned stores
+    const fn store_mnemonic(&self) -> &'static str {
+        match self {
+            Width::Byte | Width::ByteUnsigned => "sb",
+            Width::Half | Width::HalfUnsigned => "sh",
+            Width::Word | Width::WordUnsigned => "sw",
+            Width::Double => "sd",
+            Width::Float => "fsw",
+            Width::FloatDouble => "fsd",
+        }
+    }
+
+    /// Fill a register from the bytes a load read
+    fn extend(&self, value: u64) -> u64 {
+        match self {
+            Width::Byte => value as u8 as i8 as i64 as u64,
+            Width::Half => value as u16 as i16 as i64 as u64,
+            Width::Word => value as u32 as i32 as i64 as u64,
+            Width::Double
+            | Width::FloatDouble
+            | Width::ByteUnsigned
+            | Width::HalfUnsigned
+            | Width::WordUnsigned => value,
+            Width::Float => nan_box(value as u32),
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum RiscVOp {
-    LoadWord(Operand, Operand, u64),
-    StoreWord(Operand, Operand, u64),
-    LoadFloat(Operand, Operand, u64),
-    StoreFloat(Operand, Operand, u64),
-    Add(Operand, Operand, Operand),
-    Sub(Operand, Operand, Operand),
-    BranchEqual(Operand, Operand, Operand),
-    BranchNotEqual(Operand, Operand, Operand),
-    FloatAdd(Operand, Operand, Operand),
-    FloatSub(Operand, Operand, Operand),
-    FloatMul(Operand, Operand, Operand),
-    FloatDiv(Operand, Operand, Operand),
+/// The integer operations. Most take either two registers or a register and an immediate.
+/// The word operations from RV64I work on the low 32 bits of their sources, and sign
+/// extend their result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntOp {
+    Add,
+    Sub,
+    ShiftLeft,
+    SetLessThan,
+    SetLessThanUnsigned,
+    Xor,
+    ShiftRightLogical,
+    ShiftRightArithmetic,
+    Or,
+    And,
+    AddWord,
+    SubWord,
+    ShiftLeftWord,
+    ShiftRightLogicalWord,
+    ShiftRightArithmeticWord,
+    /// The M extension, which only takes registers
+    Mul,
+    /// The upper 64 bits of the product of two signed values
+    MulHigh,
+    /// The upper 64 bits of the product of a signed and an unsigned value
+    MulHighSignedUnsigned,
+    MulHighUnsigned,
+    Div,
+    DivUnsigned,
+    Rem,
+    RemUnsigned,
+    MulWord,
+    /// The upper 32 bits of products of the low 32 bits of the sources, which is what
+    /// `mulh`, `mulhsu` and `mulhu` work out in RV32 programs. They're only decoded from
+    /// 32 bit binaries, and go by the same mnemonics.
+    MulHighWord,
+    MulHighSignedUnsignedWord,
+    MulHighUnsignedWord,
+    DivWord,
+    DivUnsignedWord,
+    RemWord,
+    RemUnsignedWord,
 }
 
-impl RiscVOp {
-    pub fn functional_unit(&self) -> FunctionalUnit {
-        if self.is_branch() {
-            FunctionalUnit::EffectAddr
-        } else if self.is_alu() {
-            FunctionalUnit::ALU
-        } else if self.is_fp_add() {
-            FunctionalUnit::FPUAdd
-        } else if self.is_fp_mul() {
-            FunctionalUnit::FPUMul
-        } else if self.is_data_transfer() {
-            FunctionalUnit::EffectAddr
-        } else {
-            panic!("Unknown functional unit for operation \"{self}\"");
+impl IntOp {
+    /// Every operation a trace can name, which leaves out the RV32 upper products, since
+    /// their mnemonics name the RV64 ones
+    pub const ALL: [IntOp; 28] = [
+        IntOp::Add,
+        IntOp::Sub,
+        IntOp::ShiftLeft,
+        IntOp::SetLessThan,
+        IntOp::SetLessThanUnsigned,
+        IntOp::Xor,
+        IntOp::ShiftRightLogical,
+        IntOp::ShiftRightArithmetic,
+        IntOp::Or,
+        IntOp::And,
+        IntOp::AddWord,
+        IntOp::SubWord,
+        IntOp::ShiftLeftWord,
+        IntOp::ShiftRightLogicalWord,
+        IntOp::ShiftRightArithmeticWord,
+        IntOp::Mul,
+        IntOp::MulHigh,
+        IntOp::MulHighSignedUnsigned,
+        IntOp::MulHighUnsigned,
+        IntOp::Div,
+        IntOp::DivUnsigned,
+        IntOp::Rem,
+        IntOp::RemUnsigned,
+        IntOp::MulWord,
+        IntOp::DivWord,
+        IntOp::DivUnsignedWord,
+        IntOp::RemWord,
+        IntOp::RemUnsignedWord,
+    ];
+
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            IntOp::Add => "add",
+            IntOp::Sub => "sub",
+            IntOp::ShiftLeft => "sll",
+            IntOp::SetLessThan => "slt",
+            IntOp::SetLessThanUnsigned => "sltu",
+            IntOp::Xor => "xor",
+            IntOp::ShiftRightLogical => "srl",
+            IntOp::ShiftRightArithmetic => "sra",
+            IntOp::Or => "or",
+            IntOp::And => "and",
+            IntOp::AddWord => "addw",
+            IntOp::SubWord => "subw",
+            IntOp::ShiftLeftWord => "sllw",
+            IntOp::ShiftRightLogicalWord => "srlw",
+            IntOp::ShiftRightArithmeticWord => "sraw",
+            IntOp::Mul => "mul",
+            IntOp::MulHigh => "mulh",
+            IntOp::MulHighSignedUnsigned => "mulhsu",
+            IntOp::MulHighUnsigned => "mulhu",
+            IntOp::Div => "div",
+            IntOp::DivUnsigned => "divu",
+            IntOp::Rem => "rem",
+            IntOp::RemUnsigned => "remu",
+            IntOp::MulWord => "mulw",
+            IntOp::MulHighWord => "mulh",
+            IntOp::MulHighSignedUnsignedWord => "mulhsu",
+            IntOp::MulHighUnsignedWord => "mulhu",
+            IntOp::DivWord => "divw",
+            IntOp::DivUnsignedWord => "divuw",
+            IntOp::RemWord => "remw",
+            IntOp::RemUnsignedWord => "remuw",
         }
     }
 
-    pub fn accesses_memory(&self) -> bool {
-        self.is_load()
+    /// The mnemonic of the form taking an immediate. There's no `subi`, since `addi`
+    /// takes negative immediates.
+    const fn immediate_mnemonic(&self) -> Option<&'static str> {
+        match self {
+            IntOp::Add => Some("addi"),
+            IntOp::ShiftLeft => Some("slli"),
+            IntOp::SetLessThan => Some("slti"),
+            IntOp::SetLessThanUnsigned => Some("sltiu"),
+            IntOp::Xor => Some("xori"),
+            IntOp::ShiftRightLogical => Some("srli"),
+            IntOp::ShiftRightArithmetic => Some("srai"),
+            IntOp::Or => Some("ori"),
+            IntOp::And => Some("andi"),
+            IntOp::AddWord => Some("addiw"),
+            IntOp::ShiftLeftWord => Some("slliw"),
+            IntOp::ShiftRightLogicalWord => Some("srliw"),
+            IntOp::ShiftRightArithmeticWord => Some("sraiw"),
+            _ => None,
+        }
     }
 
-    pub fn writes_back(&self) -> bool {
-        self.is_alu() || self.is_fp() || self.is_load() && !self.is_branch()
-    }
-
-    pub fn parse(line: &str) -> Self {
-        // Get the string until the ` ` character
-        let mut split_by_space = line.split_whitespace();
-        let op = split_by_space.next().unwrap();
-        // Now get all the arguments separated by `,`
-        let dst = split_by_space.next().unwrap().split(',').next().unwrap();
-
-        let mut args = line.split(',');
-        args.next();
-        // Get the destination
-        // Get the first argument
-        let arg1 = args.next().unwrap();
-        // Get the second argument
-        let arg2 = args.next();
-
-        // Get optional address denoted with colon
-        let mut addr = line.split(':');
-        addr.next();
-        let addr = addr.next().unwrap_or("0");
-        let addr = if let Ok(addr) = addr.parse::<u64>() {
-            addr
-        } else {
-            panic!("Could not parse address \"{addr}\"");
-        };
+    /// The largest immediate a shift can take, or `None` if this isn't a shift
+    const fn max_shift(&self) -> Option<i64> {
+        match self {
+            IntOp::ShiftLeft | IntOp::ShiftRightLogical | IntOp::ShiftRightArithmetic => Some(63),
+            IntOp::ShiftLeftWord
+            | IntOp::ShiftRightLogicalWord
+            | IntOp::ShiftRightArithmeticWord => Some(31),
+            _ => None,
+        }
+    }
 
-        match op {
-            "add" => RiscVOp::Add(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "sub" => RiscVOp::Sub(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "lw" => RiscVOp::LoadWord(Operand::parse(dst), Operand::parse(arg1), addr),
-            "sw" => RiscVOp::StoreWord(Operand::parse(dst), Operand::parse(arg1), addr),
-            "beq" => RiscVOp::BranchEqual(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "bne" => RiscVOp::BranchNotEqual(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "flw" => RiscVOp::LoadFloat(Operand::parse(dst), Operand::parse(arg1), addr),
-            "fsw" => RiscVOp::StoreFloat(Operand::parse(dst), Operand::parse(arg1), addr),
-            "fadd" => RiscVOp::FloatAdd(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "fsub" => RiscVOp::FloatSub(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "fmul" => RiscVOp::FloatMul(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "fdiv" => RiscVOp::FloatDiv(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "fadd.s" => RiscVOp::FloatAdd(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "fsub.s" => RiscVOp::FloatSub(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "fmul.s" => RiscVOp::FloatMul(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            "fdiv.s" => RiscVOp::FloatDiv(
-                Operand::parse(dst),
-                Operand::parse(arg1),
-                Operand::parse(arg2.unwrap()),
-            ),
-            _ => unimplemented!(),
+    /// The group of operations this shares a default latency with
+    const fn class(&self) -> OpClass {
+        match self {
+            IntOp::Mul
+            | IntOp::MulHigh
+            | IntOp::MulHighSignedUnsigned
+            | IntOp::MulHighUnsigned
+            | IntOp::MulWord
+            | IntOp::MulHighWord
+            | IntOp::MulHighSignedUnsignedWord
+            | IntOp::MulHighUnsignedWord => OpClass::IntMul,
+            IntOp::Div
+            | IntOp::DivUnsigned
+            | IntOp::Rem
+            | IntOp::RemUnsigned
+            | IntOp::DivWord
+            | IntOp::DivUnsignedWord
+            | IntOp::RemWord
+            | IntOp::RemUnsignedWord => OpClass::IntDiv,
+            _ => OpClass::Int,
         }
     }
 
-    pub const fn is_load(&self) -> bool {
-        matches!(
-            self,
-            RiscVOp::LoadWord(_, _, _) | RiscVOp::LoadFloat(_, _, _)
-        )
+    /// Whether this runs on the multiply/divide unit, rather than the ALU
+    const fn is_mul_div(&self) -> bool {
+        matches!(self.class(), OpClass::IntMul | OpClass::IntDiv)
     }
 
-    pub const fn is_store(&self) -> bool {
-        matches!(
-            self,
-            RiscVOp::StoreWord(_, _, _) | RiscVOp::StoreFloat(_, _, _)
-        )
+    /// Division by zero and overflow don't trap, but give the results the M extension defines
+    fn apply(&self, a: u64, b: u64) -> u64 {
+        // Registers are 64 bits wide, so shifts use the low 6 bits of the amount,
+        // and word shifts the low 5
+        let shift = b & 0x3f;
+        let shift_word = b & 0x1f;
+        let word = |value: u32| value as i32 as i64 as u64;
+        match self {
+            IntOp::Add => a.wrapping_add(b),
+            IntOp::Sub => a.wrapping_sub(b),
+            IntOp::ShiftLeft => a << shift,
+            IntOp::SetLessThan => ((a as i64) < (b as i64)) as u64,
+            IntOp::SetLessThanUnsigned => (a < b) as u64,
+            IntOp::Xor => a ^ b,
+            IntOp::ShiftRightLogical => a >> shift,
+            IntOp::ShiftRightArithmetic => ((a as i64) >> shift) as u64,
+            IntOp::Or => a | b,
+            IntOp::And => a & b,
+            IntOp::AddWord => word((a as u32).wrapping_add(b as u32)),
+            IntOp::SubWord => word((a as u32).wrapping_sub(b as u32)),
+            IntOp::ShiftLeftWord => word((a as u32) << shift_word),
+            IntOp::ShiftRightLogicalWord => word((a as u32) >> shift_word),
+            IntOp::ShiftRightArithmeticWord => word(((a as i32) >> shift_word) as u32),
+            IntOp::Mul => a.wrapping_mul(b),
+            IntOp::MulHigh => ((a as i64 as i128 * b as i64 as i128) >> 64) as u64,
+            IntOp::MulHighSignedUnsigned => {
+                ((a as i64 as i128).wrapping_mul(b as i128) >> 64) as u64
+            }
+            IntOp::MulHighUnsigned => ((a as u128 * b as u128) >> 64) as u64,
+            IntOp::Div if b == 0 => u64::MAX,
+            IntOp::Div => (a as i64).wrapping_div(b as i64) as u64,
+            IntOp::DivUnsigned => a.checked_div(b).unwrap_or(u64::MAX),
+            IntOp::Rem if b == 0 => a,
+            IntOp::Rem => (a as i64).wrapping_rem(b as i64) as u64,
+            IntOp::RemUnsigned => a.checked_rem(b).unwrap_or(a),
+            IntOp::MulWord => word((a as u32).wrapping_mul(b as u32)),
+            IntOp::MulHighWord => word(((a as i32 as i64 * b as i32 as i64) >> 32) as u32),
+            IntOp::MulHighSignedUnsignedWord => {
+                word(((a as i32 as i64 * b as u32 as i64) >> 32) as u32)
+            }
+            IntOp::MulHighUnsignedWord => word(((a as u32 as u64 * b as u32 as u64) >> 32) as u32),
+            IntOp::DivWord if b as u32 == 0 => u64::MAX,
+            IntOp::DivWord => word((a as i32).wrapping_div(b as i32) as u32),
+            IntOp::DivUnsignedWord => match (a as u32).checked_div(b as u32) {
+                Some(quotient) => word(quotient),
+                None => u64::MAX,
+            },
+            IntOp::RemWord if b as u32 == 0 => word(a as u32),
+            IntOp::RemWord => word((a as i32).wrapping_rem(b as i32) as u32),
+            IntOp::RemUnsignedWord => word((a as u32).checked_rem(b as u32).unwrap_or(a as u32)),
+        }
     }
+}
 
-    pub const fn is_data_transfer(&self) -> bool {
-        matches!(
-            self,
-            RiscVOp::LoadWord(_, _, _)
-                | RiscVOp::StoreWord(_, _, _)
-                | RiscVOp::LoadFloat(_, _, _)
-                | RiscVOp::StoreFloat(_, _, _)
-        )
+/// The kinds of access a `fence` orders, written as some of the letters `iorw`, for
+/// device input and output and memory reads and writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FenceSet(u8);
+
+impl FenceSet {
+    const LETTERS: [char; 4] = ['i', 'o', 'r', 'w'];
+
+    /// Every kind of access, which a `fence` without operands orders
+    pub const ALL: FenceSet = FenceSet(0xf);
+
+    /// A set from the bits of a `fence` instruction's `pred` or `succ` field
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & 0xf)
     }
 
-    pub const fn is_branch(&self) -> bool {
-        matches!(
-            self,
-            RiscVOp::BranchEqual(_, _, _) | RiscVOp::BranchNotEqual(_, _, _)
-        )
+    /// Parse the letters of a set, such as `rw`, which must be in order, or `0` for
+    /// none, as in the `fence w,0` of `pause`
+    fn parse(text: &str) -> Option<Self> {
+        if text == "0" {
+            return Some(Self(0));
+        }
+        let mut bits = 0;
+        let mut letters = Self::LETTERS.iter().enumerate();
+        for c in text.chars() {
+            let (i, _) = letters.find(|(_, letter)| **letter == c)?;
+            bits |= 8 >> i;
+        }
+        (bits != 0).then_some(Self(bits))
     }
+}
 
-    pub const fn is_alu(&self) -> bool {
-        matches!(self, RiscVOp::Add(_, _, _) | RiscVOp::Sub(_, _, _))
+impl Display for FenceSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "0");
+        }
+        for (i, letter) in Self::LETTERS.iter().enumerate() {
+            if self.0 & 8 >> i != 0 {
+                write!(f, "{letter}")?;
+            }
+        }
+        Ok(())
     }
+}
 
-    pub const fn is_fp(&self) -> bool {
-        matches!(
-            self,
-            RiscVOp::FloatAdd(_, _, _)
-                | RiscVOp::FloatSub(_, _, _)
-                | RiscVOp::FloatMul(_, _, _)
-                | RiscVOp::FloatDiv(_, _, _)
-        )
+/// What a conditional branch compares its two registers for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterEqual,
+    LessThanUnsigned,
+    GreaterEqualUnsigned,
+}
+
+impl Condition {
+    pub const ALL: [Condition; 6] = [
+        Condition::Equal,
+        Condition::NotEqual,
+        Condition::LessThan,
+        Condition::GreaterEqual,
+        Condition::LessThanUnsigned,
+        Condition::GreaterEqualUnsigned,
+    ];
+
+    const fn mnemonic(&self) -> &'static str {
+        match self {
+            Condition::Equal => "beq",
+            Condition::NotEqual => "bne",
+            Condition::LessThan => "blt",
+            Condition::GreaterEqual => "bge",
+            Condition::LessThanUnsigned => "bltu",
+            Condition::GreaterEqualUnsigned => "bgeu",
+        }
     }
 
-    pub const fn is_fp_add(&self) -> bool {
-        matches!(
-            self,
-            RiscVOp::FloatAdd(_, _, _) | RiscVOp::FloatSub(_, _, _)
-        )
+    fn holds(&self, a: u64, b: u64) -> bool {
+        match self {
+            Condition::Equal => a == b,
+            Condition::NotEqual => a != b,
+            Condition::LessThan => (a as i64) < (b as i64),
+            Condition::GreaterEqual => (a as i64) >= (b as i64),
+            Condition::LessThanUnsigned => a < b,
+            Condition::GreaterEqualUnsigned => a >= b,
+        }
     }
+}
 
-    pub const fn is_fp_mul(&self) -> bool {
-        matches!(
-            self,
-            RiscVOp::FloatMul(_, _, _) | RiscVOp::FloatDiv(_, _, _)
-        )
+/// The precision of a floating point operation, from the F or D extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Single,
+    Double,
+}
+
+impl Precision {
+    pub const ALL: [Precision; 2] = [Precision::Single, Precision::Double];
+
+    /// Where this precision's mnemonic is in the pairs the operations give
+    const fn index(&self) -> usize {
+        *self as usize
     }
-    pub const fn is_fp_div(&self) -> bool {
-        matches!(self, RiscVOp::FloatDiv(_, _, _))
+
+    /// Read a register as a value of this precision. Single precision values are
+    /// widened, which is exact, so the operations can all be done in double precision.
+    fn read(&self, regs: &RegisterFile, op: Operand) -> f64 {
+        match self {
+            Precision::Single => regs.f32(op) as f64,
+            Precision::Double => f64::from_bits(regs.get(op)),
+        }
     }
 
-    pub fn addr(&self) -> Option<u64> {
+    /// Write a value to a register, rounding it to this precision
+    fn write(&self, regs: &mut RegisterFile, op: Operand, value: f64) {
         match self {
-            RiscVOp::LoadWord(_, _, addr) => Some(*addr),
-            RiscVOp::StoreWord(_, _, addr) => Some(*addr),
-            RiscVOp::LoadFloat(_, _, addr) => Some(*addr),
-            RiscVOp::StoreFloat(_, _, addr) => Some(*addr),
-            _ => None,
+            Precision::Single => regs.set_f32(op, value as f32),
+            Precision::Double => regs.set(op, value.to_bits()),
         }
     }
 
-    pub fn dst(&self) -> Option<Operand> {
-        Some(match self {
-            RiscVOp::LoadWord(dst, _, _) => *dst,
-            RiscVOp::StoreWord(_, _, _) => None?,
-            RiscVOp::LoadFloat(dst, _, _) => *dst,
-            RiscVOp::StoreFloat(_, _, _) => None?,
-            RiscVOp::Add(dst, _, _) => *dst,
-            RiscVOp::Sub(dst, _, _) => *dst,
-            // RiscVOp::BranchEqual(dst, _, _) => *dst,
-            // RiscVOp::BranchNotEqual(dst, _, _) => *dst,
-            RiscVOp::BranchEqual(_, _, _) => None?,
-            RiscVOp::BranchNotEqual(_, _, _) => None?,
-
-            RiscVOp::FloatAdd(dst, _, _) => *dst,
-            RiscVOp::FloatSub(dst, _, _) => *dst,
-            RiscVOp::FloatMul(dst, _, _) => *dst,
-            RiscVOp::FloatDiv(dst, _, _) => *dst,
-        })
+    /// `a * b + c` with a single rounding
+    fn mul_add(&self, a: f64, b: f64, c: f64) -> f64 {
+        match self {
+            Precision::Single => (a as f32).mul_add(b as f32, c as f32) as f64,
+            Precision::Double => a.mul_add(b, c),
+        }
+    }
+
+    /// The `fclass` mask for the bits of a value: one bit set for
+    /// -inf, -normal, -subnormal, -0, +0, +subnormal, +normal, +inf,
+    /// signaling NaN or quiet NaN, from bit 0 up
+    fn classify(&self, bits: u64) -> u64 {
+        let (category, negative, quiet) = match self {
+            Precision::Single => {
+                let value = f32::from_bits(bits as u32);
+                (
+                    value.classify(),
+                    value.is_sign_negative(),
+                    bits & 1 << 22 != 0,
+                )
+            }
+            Precision::Double => {
+                let value = f64::from_bits(bits);
+                (
+                    value.classify(),
+                    value.is_sign_negative(),
+                    bits & 1 << 51 != 0,
+                )
+            }
+        };
+        let bit = match (category, negative) {
+            (FpCategory::Infinite, true) => 0,
+            (FpCategory::Normal, true) => 1,
+            (FpCategory::Subnormal, true) => 2,
+            (FpCategory::Zero, true) => 3,
+            (FpCategory::Zero, false) => 4,
+            (FpCategory::Subnormal, false) => 5,
+            (FpCategory::Normal, false) => 6,
+            (FpCategory::Infinite, false) => 7,
+            (FpCategory::Nan, _) if quiet => 9,
+            (FpCategory::Nan, _) => 8,
+        };
+        1 << bit
     }
+}
+
+/// The floating point operations which take two registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Min,
+    Max,
+    /// The first value with the sign of the second
+    SignInject,
+    /// The first value with the opposite sign to the second
+    SignInjectNegate,
+    /// The first value with the exclusive or of both signs
+    SignInjectXor,
+}
+
+impl FloatOp {
+    pub const ALL: [FloatOp; 9] = [
+        FloatOp::Add,
+        FloatOp::Sub,
+        FloatOp::Mul,
+        FloatOp::Div,
+        FloatOp::Min,
+        FloatOp::Max,
+        FloatOp::SignInject,
+        FloatOp::SignInjectNegate,
+        FloatOp::SignInjectXor,
+    ];
 
-    pub fn src1(&self) -> Operand {
+    /// The single and double precision mnemonics
+    const fn mnemonics(&self) -> [&'static str; 2] {
         match self {
-            RiscVOp::LoadWord(_, src, _) => *src,
-            RiscVOp::StoreWord(src, _, _) => *src,
-            RiscVOp::LoadFloat(_, src, _) => *src,
-            RiscVOp::StoreFloat(src, _, _) => *src,
-            RiscVOp::Add(_, src1, _) => *src1,
-            RiscVOp::Sub(_, src1, _) => *src1,
-            RiscVOp::BranchEqual(src1, _, _) => *src1,
-            RiscVOp::BranchNotEqual(src1, _, _) => *src1,
-            RiscVOp::FloatAdd(_, src1, _) => *src1,
-            RiscVOp::FloatSub(_, src1, _) => *src1,
-            RiscVOp::FloatMul(_, src1, _) => *src1,
-            RiscVOp::FloatDiv(_, src1, _) => *src1,
+            FloatOp::Add => ["fadd.s", "fadd.d"],
+            FloatOp::Sub => ["fsub.s", "fsub.d"],
+            FloatOp::Mul => ["fmul.s", "fmul.d"],
+            FloatOp::Div => ["fdiv.s", "fdiv.d"],
+            FloatOp::Min => ["fmin.s", "fmin.d"],
+            FloatOp::Max => ["fmax.s", "fmax.d"],
+            FloatOp::SignInject => ["fsgnj.s", "fsgnj.d"],
+            FloatOp::SignInjectNegate => ["fsgnjn.s", "fsgnjn.d"],
+            FloatOp::SignInjectXor => ["fsgnjx.s", "fsgnjx.d"],
         }
     }
 
-    pub fn src2(&self) -> Operand {
-        use Operand::*;
+    const fn class(&self) -> OpClass {
         match self {
-            RiscVOp::LoadWord(_, _, _) => None,
-            RiscVOp::StoreWord(_, src2, _) => *src2,
-            RiscVOp::LoadFloat(_, _, _) => None,
-            RiscVOp::StoreFloat(_, src2, _) => *src2,
-            RiscVOp::Add(_, _, src2) => *src2,
-            RiscVOp::Sub(_, _, src2) => *src2,
-            RiscVOp::BranchEqual(_, src2, _) => *src2,
-            RiscVOp::BranchNotEqual(_, src2, _j) => *src2,
-            RiscVOp::FloatAdd(_, _, src2) => *src2,
-            RiscVOp::FloatSub(_, _, src2) => *src2,
-            RiscVOp::FloatMul(_, _, src2) => *src2,
-            RiscVOp::FloatDiv(_, _, src2) => *src2,
+            FloatOp::Add => OpClass::FPAdd,
+            FloatOp::Sub => OpClass::FPSub,
+            FloatOp::Mul => OpClass::FPMul,
+            FloatOp::Div => OpClass::FPDiv,
+            FloatOp::Min | FloatOp::Max => OpClass::FPCompare,
+            FloatOp::SignInject | FloatOp::SignInjectNegate | FloatOp::SignInjectXor => {
+                OpClass::FPMove
+            }
         }
     }
-}
 
-impl Display for RiscVOp {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    fn apply(&self, a: f64, b: f64) -> f64 {
         match self {
-            RiscVOp::LoadWord(dst, src, addr) => write!(f, "lw     {},{}:{addr}", dst, src),
-            RiscVOp::StoreWord(dst, src, addr) => write!(f, "sw     {},{}:{addr}", dst, src),
-            RiscVOp::Add(dst, src1, src2) => write!(f, "add    {},{},{}", dst, src1, src2),
-            RiscVOp::Sub(dst, src1, src2) => write!(f, "sub    {},{},{}", dst, src1, src2),
-            RiscVOp::BranchEqual(dst, src1, src2) => write!(f, "beq    {},{},{}", dst, src1, src2),
-            RiscVOp::BranchNotEqual(dst, src1, src2) => {
-                write!(f, "bne    {},{},{}", dst, src1, src2)
+            FloatOp::Add => a + b,
+            FloatOp::Sub => a - b,
+            FloatOp::Mul => a * b,
+            FloatOp::Div => a / b,
+            FloatOp::Min | FloatOp::Max => {
+                // A NaN loses to any number, and -0 is less than +0
+                let max = *self == FloatOp::Max;
+                if a.is_nan() {
+                    b
+                } else if b.is_nan() || (a == b && max != a.is_sign_negative()) {
+                    a
+                } else if a == b {
+                    b
+                } else if (a < b) != max {
+                    a
+                } else {
+                    b
+                }
             }
-            RiscVOp::LoadFloat(dst, src, addr) => write!(f, "flw    {},{}:{addr}", dst, src),
-            RiscVOp::StoreFloat(dst, src, addr) => write!(f, "fsw    {},{}:{addr}", dst, src),
-            RiscVOp::FloatAdd(dst, src1, src2) => write!(f, "fadd.s {},{},{}", dst, src1, src2),
-            RiscVOp::FloatSub(dst, src1, src2) => write!(f, "fsub.s {},{},{}", dst, src1, src2),
-            RiscVOp::FloatMul(dst, src1, src2) => write!(f, "fmul.s {},{},{}", dst, src1, src2),
-            RiscVOp::FloatDiv(dst, src1, src2) => write!(f, "fdiv.s {},{},{}", dst, src1, src2),
+            FloatOp::SignInject => a.copysign(b),
+            FloatOp::SignInjectNegate => a.copysign(-b),
+            FloatOp::SignInjectXor if b.is_sign_negative() => -a,
+            FloatOp::SignInjectXor => a,
         }
     }
 }
 
-impl Debug for RiscVOp {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "{}", self)
+/// The fused multiply-adds, which take three registers and round once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FusedOp {
+    /// `a * b + c`
+    MulAdd,
+    /// `a * b - c`
+    MulSub,
+    /// `-(a * b) + c`
+    NegMulSub,
+    /// `-(a * b) - c`
+    NegMulAdd,
+}
+
+impl FusedOp {
+    pub const ALL: [FusedOp; 4] = [
+        FusedOp::MulAdd,
+        FusedOp::MulSub,
+        FusedOp::NegMulSub,
+        FusedOp::NegMulAdd,
+    ];
+
+    const fn mnemonics(&self) -> [&'static str; 2] {
+        match self {
+            FusedOp::MulAdd => ["fmadd.s", "fmadd.d"],
+            FusedOp::MulSub => ["fmsub.s", "fmsub.d"],
+            FusedOp::NegMulSub => ["fnmsub.s", "fnmsub.d"],
+            FusedOp::NegMulAdd => ["fnmadd.s", "fnmadd.d"],
+        }
+    }
+
+    fn apply(&self, precision: Precision, a: f64, b: f64, c: f64) -> f64 {
+        match self {
+            FusedOp::MulAdd => precision.mul_add(a, b, c),
+            FusedOp::MulSub => precision.mul_add(a, b, -c),
+            FusedOp::NegMulSub => precision.mul_add(-a, b, c),
+            FusedOp::NegMulAdd => precision.mul_add(-a, b, -c),
+        }
+    }
+}
+
+/// The floating point operations which take one register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatUnaryOp {
+    Sqrt,
+    /// Which kind of number a value is, as a mask in an integer register
+    Classify,
+    /// Copy the bits of a floating point register to an integer register
+    MoveToInt,
+    /// Copy the bits of an integer register to a floating point register
+    MoveFromInt,
+}
+
+impl FloatUnaryOp {
+    pub const ALL: [FloatUnaryOp; 4] = [
+        FloatUnaryOp::Sqrt,
+        FloatUnaryOp::Classify,
+        FloatUnaryOp::MoveToInt,
+        FloatUnaryOp::MoveFromInt,
+    ];
+
+    const fn mnemonics(&self) -> [&'static str; 2] {
+        match self {
+            FloatUnaryOp::Sqrt => ["fsqrt.s", "fsqrt.d"],
+            FloatUnaryOp::Classify => ["fclass.s", "fclass.d"],
+            FloatUnaryOp::MoveToInt => ["fmv.x.w", "fmv.x.d"],
+            FloatUnaryOp::MoveFromInt => ["fmv.w.x", "fmv.d.x"],
+        }
+    }
+
+    const fn class(&self) -> OpClass {
+        match self {
+            FloatUnaryOp::Sqrt => OpClass::FPSqrt,
+            FloatUnaryOp::Classify => OpClass::FPCompare,
+            FloatUnaryOp::MoveToInt | FloatUnaryOp::MoveFromInt => OpClass::FPMove,
+        }
+    }
+
+    /// The register files of the destination and the source
+    const fn register_classes(&self) -> (RegisterClass, RegisterClass) {
+        match self {
+            FloatUnaryOp::Sqrt => (RegisterClass::FP, RegisterClass::FP),
+            FloatUnaryOp::Classify | FloatUnaryOp::MoveToInt => {
+                (RegisterClass::GP, RegisterClass::FP)
+            }
+            FloatUnaryOp::MoveFromInt => (RegisterClass::FP, RegisterClass::GP),
+        }
+    }
+}
+
+/// How a floating point comparison compares its two registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    LessThan,
+    LessEqual,
+}
+
+impl Comparison {
+    pub const ALL: [Comparison; 3] = [
+        Comparison::Equal,
+        Comparison::LessThan,
+        Comparison::LessEqual,
+    ];
+
+    const fn mnemonics(&self) -> [&'static str; 2] {
+        match self {
+            Comparison::Equal => ["feq.s", "feq.d"],
+            Comparison::LessThan => ["flt.s", "flt.d"],
+            Comparison::LessEqual => ["fle.s", "fle.d"],
+        }
+    }
+
+    /// Comparisons with a NaN are always false
+    fn holds(&self, a: f64, b: f64) -> bool {
+        match self {
+            Comparison::Equal => a == b,
+            Comparison::LessThan => a < b,
+            Comparison::LessEqual => a <= b,
+        }
+    }
+}
+
+/// The formats `fcvt` converts between, named by their suffix
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// `w`, a signed 32 bit integer
+    Word,
+    /// `wu`
+    WordUnsigned,
+    /// `l`, a signed 64 bit integer
+    Long,
+    /// `lu`
+    LongUnsigned,
+    /// `s`
+    Single,
+    /// `d`
+    Double,
+}
+
+impl FloatFormat {
+    pub const ALL: [FloatFormat; 6] = [
+        FloatFormat::Word,
+        FloatFormat::WordUnsigned,
+        FloatFormat::Long,
+        FloatFormat::LongUnsigned,
+        FloatFormat::Single,
+        FloatFormat::Double,
+    ];
+
+    const fn suffix(&self) -> &'static str {
+        match self {
+            FloatFormat::Word => "w",
+            FloatFormat::WordUnsigned => "wu",
+            FloatFormat::Long => "l",
+            FloatFormat::LongUnsigned => "lu",
+            FloatFormat::Single => "s",
+            FloatFormat::Double => "d",
+        }
+    }
+
+    fn from_suffix(suffix: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.suffix() == suffix)
+    }
+
+    /// The precision of a floating point format, or `None` for an integer
+    const fn precision(&self) -> Option<Precision> {
+        match self {
+            FloatFormat::Single => Some(Precision::Single),
+            FloatFormat::Double => Some(Precision::Double),
+            _ => None,
+        }
+    }
+
+    const fn register_class(&self) -> RegisterClass {
+        match self.precision() {
+            Some(_) => RegisterClass::FP,
+            None => RegisterClass::GP,
+        }
+    }
+
+    /// The mnemonic for converting to one format from another. Every pair has one,
+    /// except for integer to integer and a format to itself, which aren't instructions.
+    const fn conversion_mnemonic(to: FloatFormat, from: FloatFormat) -> Option<&'static str> {
+        use FloatFormat::*;
+        Some(match (to, from) {
+            (Word, Single) => "fcvt.w.s",
+            (WordUnsigned, Single) => "fcvt.wu.s",
+            (Long, Single) => "fcvt.l.s",
+            (LongUnsigned, Single) => "fcvt.lu.s",
+            (Single, Word) => "fcvt.s.w",
+            (Single, WordUnsigned) => "fcvt.s.wu",
+            (Single, Long) => "fcvt.s.l",
+            (Single, LongUnsigned) => "fcvt.s.lu",
+            (Word, Double) => "fcvt.w.d",
+            (WordUnsigned, Double) => "fcvt.wu.d",
+            (Long, Double) => "fcvt.l.d",
+            (LongUnsigned, Double) => "fcvt.lu.d",
+            (Double, Word) => "fcvt.d.w",
+            (Double, WordUnsigned) => "fcvt.d.wu",
+            (Double, Long) => "fcvt.d.l",
+            (Double, LongUnsigned) => "fcvt.d.lu",
+            (Single, Double) => "fcvt.s.d",
+            (Double, Single) => "fcvt.d.s",
+            _ => return None,
+        })
+    }
+
+    /// Convert the bits of a value in one format to another. Integers which don't fit
+    /// saturate, and NaN converts to the largest integer.
+    fn convert(to: FloatFormat, from: FloatFormat, value: u64, rounding: Rounding) -> u64 {
+        let float = match from.precision() {
+            Some(Precision::Single) => f32::from_bits(value as u32) as f64,
+            Some(Precision::Double) => f64::from_bits(value),
+            None => {
+                // Integers go through i128, so they're only rounded once
+                let integer = match from {
+                    FloatFormat::Word => value as i32 as i128,
+                    FloatFormat::WordUnsigned => value as u32 as i128,
+                    FloatFormat::Long => value as i64 as i128,
+                    _ => value as i128,
+                };
+                return match to {
+                    FloatFormat::Single => nan_box((integer as f32).to_bits()),
+                    _ => (integer as f64).to_bits(),
+                };
+            }
+        };
+        let integer = if float.is_nan() {
+            f64::INFINITY
+        } else {
+            rounding.round(float)
+        };
+        match to {
+            FloatFormat::Word => integer as i32 as i64 as u64,
+            // 32 bit results are sign extended, even unsigned ones
+            FloatFormat::WordUnsigned => integer as u32 as i32 as i64 as u64,
+            FloatFormat::Long => integer as i64 as u64,
+            FloatFormat::LongUnsigned => integer as u64,
+            FloatFormat::Single => nan_box((float as f32).to_bits()),
+            FloatFormat::Double => float.to_bits(),
+        }
+    }
+}
+
+/// How a conversion to an integer rounds, given as an optional last operand to `fcvt`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// To the nearest, with ties to even
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    /// To the nearest, with ties away from zero
+    NearestMaxMagnitude,
+    /// The rounding mode in `frm`, which is always to the nearest even
+    Dynamic,
+}
+
+impl Rounding {
+    pub const ALL: [Rounding; 6] = [
+        Rounding::NearestEven,
+        Rounding::TowardZero,
+        Rounding::Down,
+        Rounding::Up,
+        Rounding::NearestMaxMagnitude,
+        Rounding::Dynamic,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Rounding::NearestEven => "rne",
+            Rounding::TowardZero => "rtz",
+            Rounding::Down => "rdn",
+            Rounding::Up => "rup",
+            Rounding::NearestMaxMagnitude => "rmm",
+            Rounding::Dynamic => "dyn",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|rounding| rounding.name() == name)
+    }
+
+    fn round(&self, value: f64) -> f64 {
+        match self {
+            Rounding::NearestEven | Rounding::Dynamic => value.round_ties_even(),
+            Rounding::TowardZero => value.trunc(),
+            Rounding::Down => value.floor(),
+            Rounding::Up => value.ceil(),
+            Rounding::NearestMaxMagnitude => value.round(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum RiscVOp {
+    /// `lw rd,offset(rs1)`, or a load of another width. Loads and stores record the
+    /// address they access, if the trace gives it with `:addr` or it's been computed
+    /// from their base register.
+    Load(Width, Operand, Operand, Option<u64>),
+    /// `sw rs2,offset(rs1)`, or a store of another width
+    Store(Width, Operand, Operand, Option<u64>),
+    /// `add rd,rs1,rs2`
+    Int(IntOp, Operand, Operand, Operand),
+    /// `addi rd,rs1,imm`
+    IntImmediate(IntOp, Operand, Operand, Operand),
+    /// `lui rd,imm`, which puts a 20 bit immediate in bits 12 to 31
+    LoadUpperImmediate(Operand, Operand),
+    /// `auipc rd,imm`, which adds the upper immediate to the instruction's address
+    AddUpperImmediateToPc(Operand, Operand),
+    /// `beq rs1,rs2,label`, or another condition. Branches record whether they
+    /// were taken, if the trace says.
+    Branch(Condition, Operand, Operand, Label, Option<bool>),
+    /// `jal rd,label`
+    JumpAndLink(Operand, Label),
+    /// `jalr rd,offset(rs1)`
+    JumpAndLinkRegister(Operand, Operand),
+    /// `fadd.s rd,rs1,rs2`, or another operation or precision
+    Float(FloatOp, Precision, Operand, Operand, Operand),
+    /// `fmadd.s rd,rs1,rs2,rs3`, which has a third source
+    FusedMulAdd(FusedOp, Precision, Operand, Operand, Operand, Operand),
+    /// `fsqrt.s rd,rs1`, or another operation with one source
+    FloatUnary(FloatUnaryOp, Precision, Operand, Operand),
+    /// `feq.s rd,rs1,rs2`, which writes 1 to an integer register if it holds, or else 0
+    FloatCompare(Comparison, Precision, Operand, Operand, Operand),
+    /// `fcvt.w.s rd,rs1,rtz`, to the first format from the second, with an optional
+    /// rounding mode
+    Convert(FloatFormat, FloatFormat, Operand, Operand, Option<Rounding>),
+    /// `fence iorw,iorw`, which orders the accesses before it of the first kinds with
+    /// those after it of the second. There's only one hart and no devices, so it has
+    /// nothing to order.
+    Fence(FenceSet, FenceSet),
+    /// `ecall`, a call to the environment, whose effects aren't modelled
+    EnvironmentCall,
+    /// `ebreak`, a breakpoint for a debugger
+    Breakpoint,
+}
+
+impl RiscVOp {
+    /// The canonical mnemonic of every supported operation, with its class
+    pub const OPCODES: &'static [(&'static str, OpClass)] = &[
+        ("lb", OpClass::Load),
+        ("lh", OpClass::Load),
+        ("lw", OpClass::Load),
+        ("ld", OpClass::Load),
+        ("lbu", OpClass::Load),
+        ("lhu", OpClass::Load),
+        ("lwu", OpClass::Load),
+        ("sb", OpClass::Store),
+        ("sh", OpClass::Store),
+        ("sw", OpClass::Store),
+        ("sd", OpClass::Store),
+        ("flw", OpClass::Load),
+        ("fsw", OpClass::Store),
+        ("add", OpClass::Int),
+        ("sub", OpClass::Int),
+        ("sll", OpClass::Int),
+        ("slt", OpClass::Int),
+        ("sltu", OpClass::Int),
+        ("xor", OpClass::Int),
+        ("srl", OpClass::Int),
+        ("sra", OpClass::Int),
+        ("or", OpClass::Int),
+        ("and", OpClass::Int),
+        ("addi", OpClass::Int),
+        ("slli", OpClass::Int),
+        ("slti", OpClass::Int),
+        ("sltiu", OpClass::Int),
+        ("xori", OpClass::Int),
+        ("srli", OpClass::Int),
+        ("srai", OpClass::Int),
+        ("ori", OpClass::Int),
+        ("andi", OpClass::Int),
+        ("addw", OpClass::Int),
+        ("subw", OpClass::Int),
+        ("sllw", OpClass::Int),
+        ("srlw", OpClass::Int),
+        ("sraw", OpClass::Int),
+        ("addiw", OpClass::Int),
+        ("slliw", OpClass::Int),
+        ("srliw", OpClass::Int),
+        ("sraiw", OpClass::Int),
+        ("lui", OpClass::Int),
+        ("auipc", OpClass::Int),
+        ("mul", OpClass::IntMul),
+        ("mulh", OpClass::IntMul),
+        ("mulhsu", OpClass::IntMul),
+        ("mulhu", OpClass::IntMul),
+        ("mulw", OpClass::IntMul),
+        ("div", OpClass::IntDiv),
+        ("divu", OpClass::IntDiv),
+        ("rem", OpClass::IntDiv),
+        ("remu", OpClass::IntDiv),
+        ("divw", OpClass::IntDiv),
+        ("divuw", OpClass::IntDiv),
+        ("remw", OpClass::IntDiv),
+        ("remuw", OpClass::IntDiv),
+        ("beq", OpClass::Branch),
+        ("bne", OpClass::Branch),
+        ("blt", OpClass::Branch),
+        ("bge", OpClass::Branch),
+        ("bltu", OpClass::Branch),
+        ("bgeu", OpClass::Branch),
+        ("jal", OpClass::Branch),
+        ("jalr", OpClass::Branch),
+        ("fld", OpClass::Load),
+        ("fsd", OpClass::Store),
+        ("fadd.s", OpClass::FPAdd),
+        ("fsub.s", OpClass::FPSub),
+        ("fmul.s", OpClass::FPMul),
+        ("fdiv.s", OpClass::FPDiv),
+        ("fmin.s", OpClass::FPCompare),
+        ("fmax.s", OpClass::FPCompare),
+        ("fsgnj.s", OpClass::FPMove),
+        ("fsgnjn.s", OpClass::FPMove),
+        ("fsgnjx.s", OpClass::FPMove),
+        ("fmadd.s", OpClass::FPFma),
+        ("fmsub.s", OpClass::FPFma),
+        ("fnmsub.s", OpClass::FPFma),
+        ("fnmadd.s", OpClass::FPFma),
+        ("fsqrt.s", OpClass::FPSqrt),
+        ("fclass.s", OpClass::FPCompare),
+        ("fmv.x.w", OpClass::FPMove),
+        ("fmv.w.x", OpClass::FPMove),
+        ("feq.s", OpClass::FPCompare),
+        ("flt.s", OpClass::FPCompare),
+        ("fle.s", OpClass::FPCompare),
+        ("fcvt.w.s", OpClass::FPConvert),
+        ("fcvt.wu.s", OpClass::FPConvert),
+        ("fcvt.l.s", OpClass::FPConvert),
+        ("fcvt.lu.s", OpClass::FPConvert),
+        ("fcvt.s.w", OpClass::FPConvert),
+        ("fcvt.s.wu", OpClass::FPConvert),
+        ("fcvt.s.l", OpClass::FPConvert),
+        ("fcvt.s.lu", OpClass::FPConvert),
+        ("fadd.d", OpClass::FPAdd),
+        ("fsub.d", OpClass::FPSub),
+        ("fmul.d", OpClass::FPMul),
+        ("fdiv.d", OpClass::FPDiv),
+        ("fmin.d", OpClass::FPCompare),
+        ("fmax.d", OpClass::FPCompare),
+        ("fsgnj.d", OpClass::FPMove),
+        ("fsgnjn.d", OpClass::FPMove),
+        ("fsgnjx.d", OpClass::FPMove),
+        ("fmadd.d", OpClass::FPFma),
+        ("fmsub.d", OpClass::FPFma),
+        ("fnmsub.d", OpClass::FPFma),
+        ("fnmadd.d", OpClass::FPFma),
+        ("fsqrt.d", OpClass::FPSqrt),
+        ("fclass.d", OpClass::FPCompare),
+        ("fmv.x.d", OpClass::FPMove),
+        ("fmv.d.x", OpClass::FPMove),
+        ("feq.d", OpClass::FPCompare),
+        ("flt.d", OpClass::FPCompare),
+        ("fle.d", OpClass::FPCompare),
+        ("fcvt.w.d", OpClass::FPConvert),
+        ("fcvt.wu.d", OpClass::FPConvert),
+        ("fcvt.l.d", OpClass::FPConvert),
+        ("fcvt.lu.d", OpClass::FPConvert),
+        ("fcvt.d.w", OpClass::FPConvert),
+        ("fcvt.d.wu", OpClass::FPConvert),
+        ("fcvt.d.l", OpClass::FPConvert),
+        ("fcvt.d.lu", OpClass::FPConvert),
+        ("fcvt.s.d", OpClass::FPConvert),
+        ("fcvt.d.s", OpClass::FPConvert),
+        ("fence", OpClass::Int),
+        ("ecall", OpClass::Int),
+        ("ebreak", OpClass::Int),
+    ];
+
+    /// The canonical mnemonic for this operation
+    pub const fn mnemonic(&self) -> &'static str {
+        match self {
+            RiscVOp::Load(width, _, _, _) => width.load_mnemonic(),
+            RiscVOp::Store(width, _, _, _) => width.store_mnemonic(),
+            RiscVOp::Int(op, _, _, _) => op.mnemonic(),
+            RiscVOp::IntImmediate(op, _, _, _) => match op.immediate_mnemonic() {
+                Some(mnemonic) => mnemonic,
+                None => op.mnemonic(),
+            },
+            RiscVOp::LoadUpperImmediate(_, _) => "lui",
+            RiscVOp::AddUpperImmediateToPc(_, _) => "auipc",
+            RiscVOp::Branch(condition, _, _, _, _) => condition.mnemonic(),
+            RiscVOp::JumpAndLink(_, _) => "jal",
+            RiscVOp::JumpAndLinkRegister(_, _) => "jalr",
+            RiscVOp::Float(op, precision, _, _, _) => op.mnemonics()[precision.index()],
+            RiscVOp::FusedMulAdd(op, precision, _, _, _, _) => op.mnemonics()[precision.index()],
+            RiscVOp::FloatUnary(op, precision, _, _) => op.mnemonics()[precision.index()],
+            RiscVOp::FloatCompare(comparison, precision, _, _, _) => {
+                comparison.mnemonics()[precision.index()]
+            }
+            RiscVOp::Convert(to, from, _, _, _) => {
+                match FloatFormat::conversion_mnemonic(*to, *from) {
+                    Some(mnemonic) => mnemonic,
+                    None => "fcvt",
+                }
+            }
+            RiscVOp::Fence(_, _) => "fence",
+            RiscVOp::EnvironmentCall => "ecall",
+            RiscVOp::Breakpoint => "ebreak",
+        }
+    }
+
+    /// The group of operations this shares a default latency with
+    pub const fn class(&self) -> OpClass {
+        match self {
+            RiscVOp::Load(_, _, _, _) => OpClass::Load,
+            RiscVOp::Store(_, _, _, _) => OpClass::Store,
+            RiscVOp::Int(op, _, _, _) | RiscVOp::IntImmediate(op, _, _, _) => op.class(),
+            RiscVOp::LoadUpperImmediate(_, _) | RiscVOp::AddUpperImmediateToPc(_, _) => {
+                OpClass::Int
+            }
+            RiscVOp::Branch(_, _, _, _, _)
+            | RiscVOp::JumpAndLink(_, _)
+            | RiscVOp::JumpAndLinkRegister(_, _) => OpClass::Branch,
+            RiscVOp::Float(op, _, _, _, _) => op.class(),
+            RiscVOp::FusedMulAdd(_, _, _, _, _, _) => OpClass::FPFma,
+            RiscVOp::FloatUnary(op, _, _, _) => op.class(),
+            RiscVOp::FloatCompare(_, _, _, _, _) => OpClass::FPCompare,
+            RiscVOp::Convert(_, _, _, _, _) => OpClass::FPConvert,
+            RiscVOp::Fence(_, _) | RiscVOp::EnvironmentCall | RiscVOp::Breakpoint => OpClass::Int,
+        }
+    }
+
+    pub fn functional_unit(&self) -> FunctionalUnit {
+        if self.is_branch() || self.is_jump() {
+            FunctionalUnit::EffectAddr
+        } else if self.is_alu() {
+            FunctionalUnit::ALU
+        } else if self.is_mul_div() {
+            FunctionalUnit::IntMulDiv
+        } else if self.is_fp_add() {
+            FunctionalUnit::FPUAdd
+        } else if self.is_fp_mul() {
+            FunctionalUnit::FPUMul
+        } else if self.is_data_transfer() {
+            FunctionalUnit::EffectAddr
+        } else {
+            panic!("Unknown functional unit for operation \"{self}\"");
+        }
+    }
+
+    pub fn accesses_memory(&self) -> bool {
+        self.is_load()
+    }
+
+    pub fn writes_back(&self) -> bool {
+        self.is_alu() || self.is_mul_div() || self.is_fp() || self.is_load() || self.is_jump()
+    }
+
+    /// Parse a single line of a trace, such as `flw f6,32(x2):0`.
+    ///
+    /// Comments starting with `;` or `#` are ignored. Errors are reported
+    /// against line 1; the trace loader fills in the real line number.
+    pub fn parse(line: &str) -> Result<Self, TraceError> {
+        let line = match line.find([';', '#']) {
+            Some(end) => &line[..end],
+            None => line,
+        };
+
+        // Split off the optional address, or branch outcome, denoted with a colon
+        let (body, mut addr) = match line.find(':') {
+            Some(colon) => (
+                &line[..colon],
+                Some(Token::new(line, colon + 1, line.len())),
+            ),
+            None => (line, None),
+        };
+
+        let mnemonic = Token::new(body, 0, body.len()).first_word();
+        if mnemonic.text.is_empty() {
+            return Err(mnemonic.error(TraceErrorKind::MissingMnemonic));
+        }
+        let args = Operands::split(body, mnemonic);
+
+        let width = |name: fn(&Width) -> &'static str| {
+            Width::ALL
+                .into_iter()
+                .find(|width| name(width) == mnemonic.text)
+        };
+        let int_op = IntOp::ALL
+            .into_iter()
+            .find(|op| op.mnemonic() == mnemonic.text);
+        let int_immediate_op = IntOp::ALL
+            .into_iter()
+            .find(|op| op.immediate_mnemonic() == Some(mnemonic.text));
+        let condition = Condition::ALL
+            .into_iter()
+            .find(|condition| condition.mnemonic() == mnemonic.text);
+
+        let zero = Operand::Register(Register::GP(0));
+        let return_address = Operand::Register(Register::GP(1));
+
+        let op = if let Some(op) = int_op {
+            args.expect(3)?;
+            let dst = args.register(0, RegisterClass::GP)?;
+            let src1 = args.register(1, RegisterClass::GP)?;
+            let src2 = args.register(2, RegisterClass::GP)?;
+            RiscVOp::Int(op, dst, src1, src2)
+        } else if let Some(op) = int_immediate_op {
+            args.expect(3)?;
+            let dst = args.register(0, RegisterClass::GP)?;
+            let src1 = args.register(1, RegisterClass::GP)?;
+            // Shift amounts can't be negative, and can shift out a whole register at most
+            let imm = match op.max_shift() {
+                Some(max) => args.immediate(2, 0, max)?,
+                None => args.immediate(2, -2048, 2047)?,
+            };
+            RiscVOp::IntImmediate(op, dst, src1, imm)
+        } else if let Some(condition) = condition {
+            args.expect(3)?;
+            let src1 = args.register(0, RegisterClass::GP)?;
+            let src2 = args.register(1, RegisterClass::GP)?;
+            let label = args.label(2)?;
+            let taken = match addr.take() {
+                Some(outcome) => Some(outcome.outcome()?),
+                None => None,
+            };
+            RiscVOp::Branch(condition, src1, src2, label, taken)
+        } else if let Some(width) = width(Width::load_mnemonic).or(width(Width::store_mnemonic)) {
+            args.expect(2)?;
+            let class = if width.is_fp() {
+                RegisterClass::FP
+            } else {
+                RegisterClass::GP
+            };
+            let reg = args.register(0, class)?;
+            let mem = args.indirect(1)?;
+            let addr = match addr.take() {
+                Some(addr) => Some(addr.address()?),
+                None => None,
+            };
+            if width.load_mnemonic() == mnemonic.text {
+                RiscVOp::Load(width, reg, mem, addr)
+            } else {
+                RiscVOp::Store(width, reg, mem, addr)
+            }
+        } else {
+            match mnemonic.text {
+                "lui" | "auipc" => {
+                    args.expect(2)?;
+                    let dst = args.register(0, RegisterClass::GP)?;
+                    let imm = args.immediate(1, 0, 0xfffff)?;
+                    if mnemonic.text == "lui" {
+                        RiscVOp::LoadUpperImmediate(dst, imm)
+                    } else {
+                        RiscVOp::AddUpperImmediateToPc(dst, imm)
+                    }
+                }
+                "jal" => {
+                    args.expect(2)?;
+                    let dst = args.register(0, RegisterClass::GP)?;
+                    let label = args.label(1)?;
+                    RiscVOp::JumpAndLink(dst, label)
+                }
+                "jalr" => {
+                    args.expect(2)?;
+                    let dst = args.register(0, RegisterClass::GP)?;
+                    let target = args.indirect(1)?;
+                    RiscVOp::JumpAndLinkRegister(dst, target)
+                }
+                "fence" if args.len() == 0 => RiscVOp::Fence(FenceSet::ALL, FenceSet::ALL),
+                "fence" => {
+                    args.expect(2)?;
+                    RiscVOp::Fence(args.fence_set(0)?, args.fence_set(1)?)
+                }
+                "ecall" => {
+                    args.expect(0)?;
+                    RiscVOp::EnvironmentCall
+                }
+                "ebreak" => {
+                    args.expect(0)?;
+                    RiscVOp::Breakpoint
+                }
+                // The pseudo-instructions which stand for a single instruction
+                "nop" => {
+                    args.expect(0)?;
+                    RiscVOp::IntImmediate(IntOp::Add, zero, zero, Operand::Immediate(0))
+                }
+                "li" => {
+                    args.expect(2)?;
+                    let dst = args.register(0, RegisterClass::GP)?;
+                    let imm = args.immediate(1, -2048, 2047)?;
+                    RiscVOp::IntImmediate(IntOp::Add, dst, zero, imm)
+                }
+                "mv" | "not" | "neg" => {
+                    args.expect(2)?;
+                    let dst = args.register(0, RegisterClass::GP)?;
+                    let src = args.register(1, RegisterClass::GP)?;
+                    match mnemonic.text {
+                        "mv" => RiscVOp::IntImmediate(IntOp::Add, dst, src, Operand::Immediate(0)),
+                        "not" => {
+                            RiscVOp::IntImmediate(IntOp::Xor, dst, src, Operand::Immediate(!0))
+                        }
+                        _ => RiscVOp::Int(IntOp::Sub, dst, zero, src),
+                    }
+                }
+                "beqz" | "bnez" | "bltz" | "bgez" | "blez" | "bgtz" => {
+                    args.expect(2)?;
+                    let src = args.register(0, RegisterClass::GP)?;
+                    let label = args.label(1)?;
+                    let taken = match addr.take() {
+                        Some(outcome) => Some(outcome.outcome()?),
+                        None => None,
+                    };
+                    // `blez` and `bgtz` compare zero with the register, rather than the other way
+                    let (condition, src1, src2) = match mnemonic.text {
+                        "beqz" => (Condition::Equal, src, zero),
+                        "bnez" => (Condition::NotEqual, src, zero),
+                        "bltz" => (Condition::LessThan, src, zero),
+                        "bgez" => (Condition::GreaterEqual, src, zero),
+                        "blez" => (Condition::GreaterEqual, zero, src),
+                        _ => (Condition::LessThan, zero, src),
+                    };
+                    RiscVOp::Branch(condition, src1, src2, label, taken)
+                }
+                // A call or tail call is `auipc` and `jalr` to reach anywhere, but
+                // a label is always in reach of `jal`
+                "j" | "tail" => {
+                    args.expect(1)?;
+                    RiscVOp::JumpAndLink(zero, args.label(0)?)
+                }
+                "call" => {
+                    args.expect(1)?;
+                    RiscVOp::JumpAndLink(return_address, args.label(0)?)
+                }
+                "jr" => {
+                    args.expect(1)?;
+                    let arg = &args.args[0];
+                    let src = Register::parse_gp(arg.text)
+                        .ok_or_else(|| arg.error(TraceErrorKind::BadRegister))?;
+                    RiscVOp::JumpAndLinkRegister(zero, Operand::Indirect(src, 0))
+                }
+                "ret" => {
+                    args.expect(0)?;
+                    RiscVOp::JumpAndLinkRegister(zero, Operand::Indirect(Register::GP(1), 0))
+                }
+                _ => match Self::parse_float(mnemonic.text, &args)? {
+                    Some(op) => op,
+                    None => return Err(mnemonic.error(TraceErrorKind::UnknownMnemonic)),
+                },
+            }
+        };
+
+        if let Some(addr) = addr {
+            if !op.is_data_transfer() {
+                return Err(addr.error(TraceErrorKind::UnexpectedAddress));
+            }
+        }
+        Ok(op)
+    }
+
+    /// Parse a line of an assembly program, like [`RiscVOp::parse`], except that `li` can
+    /// load any 32 bit value. One too wide for `addi` is loaded with a `lui` of its upper
+    /// 20 bits and an `addiw` of the lower 12, as an assembler would.
+    pub fn parse_expanded(line: &str) -> Result<Vec<Self>, TraceError> {
+        let code = match line.find([';', '#']) {
+            Some(end) => &line[..end],
+            None => line,
+        };
+        let mnemonic = Token::new(code, 0, code.len()).first_word();
+        let args = Operands::split(code, mnemonic);
+
+        // Anything else, including a malformed `li`, is a single instruction
+        let wide = |value: &i64| !(-2048..=2047).contains(value) && i32::try_from(*value).is_ok();
+        let is_li = mnemonic.text == "li" && args.len() == 2 && !code.contains(':');
+        let value = match args.args.get(1).and_then(Token::signed) {
+            Some(value) if is_li && wide(&value) => value,
+            _ => return Self::parse(line).map(|op| vec![op]),
+        };
+        let dst = args.register(0, RegisterClass::GP)?;
+
+        // The lower 12 bits are added signed, so the upper 20 are rounded to make up for it
+        let low = ((value & 0xfff) ^ 0x800) - 0x800;
+        let high = ((value - low) >> 12) & 0xfffff;
+        let mut ops = vec![RiscVOp::LoadUpperImmediate(
+            dst,
+            Operand::Immediate(high as u64),
+        )];
+        if low != 0 {
+            let low = Operand::Immediate(low as u64);
+            ops.push(RiscVOp::IntImmediate(IntOp::AddWord, dst, dst, low));
+        }
+        Ok(ops)
+    }
+
+    /// Parse the operands of a floating point operation, other than a load or store,
+    /// or return `None` if the mnemonic isn't one
+    fn parse_float(mnemonic: &str, args: &Operands) -> Result<Option<Self>, TraceError> {
+        // The single precision mnemonics without a suffix, and the old names for `fmv`
+        let mnemonic = match mnemonic {
+            "fadd" | "fsub" | "fmul" | "fdiv" => &format!("{mnemonic}.s"),
+            "fmv.x.s" => "fmv.x.w",
+            "fmv.s.x" => "fmv.w.x",
+            mnemonic => mnemonic,
+        };
+        let find = |mnemonics: [&'static str; 2]| {
+            Precision::ALL
+                .into_iter()
+                .find(|precision| mnemonics[precision.index()] == mnemonic)
+        };
+
+        for op in FloatOp::ALL {
+            if let Some(precision) = find(op.mnemonics()) {
+                args.expect(3)?;
+                let dst = args.register(0, RegisterClass::FP)?;
+                let src1 = args.register(1, RegisterClass::FP)?;
+                let src2 = args.register(2, RegisterClass::FP)?;
+                return Ok(Some(RiscVOp::Float(op, precision, dst, src1, src2)));
+            }
+        }
+        for op in FusedOp::ALL {
+            if let Some(precision) = find(op.mnemonics()) {
+                args.expect(4)?;
+                let dst = args.register(0, RegisterClass::FP)?;
+                let src1 = args.register(1, RegisterClass::FP)?;
+                let src2 = args.register(2, RegisterClass::FP)?;
+                let src3 = args.register(3, RegisterClass::FP)?;
+                return Ok(Some(RiscVOp::FusedMulAdd(
+                    op, precision, dst, src1, src2, src3,
+                )));
+            }
+        }
+        for op in FloatUnaryOp::ALL {
+            if let Some(precision) = find(op.mnemonics()) {
+                args.expect(2)?;
+                let (dst_class, src_class) = op.register_classes();
+                let dst = args.register(0, dst_class)?;
+                let src = args.register(1, src_class)?;
+                return Ok(Some(RiscVOp::FloatUnary(op, precision, dst, src)));
+            }
+        }
+        for comparison in Comparison::ALL {
+            if let Some(precision) = find(comparison.mnemonics()) {
+                args.expect(3)?;
+                let dst = args.register(0, RegisterClass::GP)?;
+                let src1 = args.register(1, RegisterClass::FP)?;
+                let src2 = args.register(2, RegisterClass::FP)?;
+                return Ok(Some(RiscVOp::FloatCompare(
+                    comparison, precision, dst, src1, src2,
+                )));
+            }
+        }
+
+        // The pseudo-instructions for sign injection, which repeat the source
+        let pseudo = match mnemonic {
+            "fmv.s" => Some((FloatOp::SignInject, Precision::Single)),
+            "fneg.s" => Some((FloatOp::SignInjectNegate, Precision::Single)),
+            "fabs.s" => Some((FloatOp::SignInjectXor, Precision::Single)),
+            "fmv.d" => Some((FloatOp::SignInject, Precision::Double)),
+            "fneg.d" => Some((FloatOp::SignInjectNegate, Precision::Double)),
+            "fabs.d" => Some((FloatOp::SignInjectXor, Precision::Double)),
+            _ => None,
+        };
+        if let Some((op, precision)) = pseudo {
+            args.expect(2)?;
+            let dst = args.register(0, RegisterClass::FP)?;
+            let src = args.register(1, RegisterClass::FP)?;
+            return Ok(Some(RiscVOp::Float(op, precision, dst, src, src)));
+        }
+
+        let conversion = mnemonic
+            .strip_prefix("fcvt.")
+            .and_then(|formats| formats.split_once('.'))
+            .and_then(|(to, from)| {
+                Some((
+                    FloatFormat::from_suffix(to)?,
+                    FloatFormat::from_suffix(from)?,
+                ))
+            })
+            .filter(|(to, from)| FloatFormat::conversion_mnemonic(*to, *from).is_some());
+        if let Some((to, from)) = conversion {
+            // The rounding mode is optional
+            args.expect(args.len().clamp(2, 3))?;
+            let dst = args.register(0, to.register_class())?;
+            let src = args.register(1, from.register_class())?;
+            let rounding = match args.len() {
+                3 => Some(args.rounding(2)?),
+                _ => None,
+            };
+            return Ok(Some(RiscVOp::Convert(to, from, dst, src, rounding)));
+        }
+        Ok(None)
+    }
+
+    pub const fn is_load(&self) -> bool {
+        matches!(self, RiscVOp::Load(_, _, _, _))
+    }
+
+    pub const fn is_store(&self) -> bool {
+        matches!(self, RiscVOp::Store(_, _, _, _))
+    }
+
+    pub const fn is_data_transfer(&self) -> bool {
+        self.is_load() || self.is_store()
+    }
+
+    pub const fn is_branch(&self) -> bool {
+        matches!(self, RiscVOp::Branch(_, _, _, _, _))
+    }
+
+    /// Unconditional jumps, which always write a return address
+    pub const fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            RiscVOp::JumpAndLink(_, _) | RiscVOp::JumpAndLinkRegister(_, _)
+        )
+    }
+
+    /// Jumps whose target comes from a register, so it isn't known until they execute
+    pub const fn is_indirect(&self) -> bool {
+        matches!(self, RiscVOp::JumpAndLinkRegister(_, _))
+    }
+
+    /// Jumps which save a return address in `ra` or `t0`, by the calling convention
+    pub fn is_call(&self) -> bool {
+        match self {
+            RiscVOp::JumpAndLink(dst, _) | RiscVOp::JumpAndLinkRegister(dst, _) => {
+                is_link_register(dst.dep_reg())
+            }
+            _ => false,
+        }
+    }
+
+    /// Jumps to the return address in `ra` or `t0`, without saving a new one
+    pub fn is_return(&self) -> bool {
+        match self {
+            RiscVOp::JumpAndLinkRegister(dst, target) => {
+                dst.dep_reg() == Some(Register::GP(0)) && is_link_register(target.dep_reg())
+            }
+            _ => false,
+        }
+    }
+
+    /// Integer operations other than multiplies and divides, and the system instructions,
+    /// which pass through the ALU without doing anything
+    pub const fn is_alu(&self) -> bool {
+        match self {
+            RiscVOp::Int(op, _, _, _) | RiscVOp::IntImmediate(op, _, _, _) => !op.is_mul_div(),
+            RiscVOp::LoadUpperImmediate(_, _) | RiscVOp::AddUpperImmediateToPc(_, _) => true,
+            RiscVOp::Fence(_, _) | RiscVOp::EnvironmentCall | RiscVOp::Breakpoint => true,
+            _ => false,
+        }
+    }
+
+    /// Integer multiplies, divides and remainders, from the M extension
+    pub const fn is_mul_div(&self) -> bool {
+        match self {
+            RiscVOp::Int(op, _, _, _) => op.is_mul_div(),
+            _ => false,
+        }
+    }
+
+    /// Floating point operations other than loads and stores, including those which
+    /// write an integer register
+    pub const fn is_fp(&self) -> bool {
+        matches!(
+            self,
+            RiscVOp::Float(_, _, _, _, _)
+                | RiscVOp::FusedMulAdd(_, _, _, _, _, _)
+                | RiscVOp::FloatUnary(_, _, _, _)
+                | RiscVOp::FloatCompare(_, _, _, _, _)
+                | RiscVOp::Convert(_, _, _, _, _)
+        )
+    }
+
+    /// The floating point operations which run on the FP adders
+    pub const fn is_fp_add(&self) -> bool {
+        self.is_fp()
+            && matches!(
+                self.class(),
+                OpClass::FPAdd
+                    | OpClass::FPSub
+                    | OpClass::FPCompare
+                    | OpClass::FPMove
+                    | OpClass::FPConvert
+            )
+    }
+
+    /// The floating point operations which run on the FP multipliers
+    pub const fn is_fp_mul(&self) -> bool {
+        self.is_fp()
+            && matches!(
+                self.class(),
+                OpClass::FPMul | OpClass::FPDiv | OpClass::FPFma | OpClass::FPSqrt
+            )
+    }
+
+    /// The floating point divisions, single or double precision
+    pub const fn is_fp_div(&self) -> bool {
+        matches!(self.class(), OpClass::FPDiv)
+    }
+
+    /// The address a load or store accesses, which is 0 if it isn't known
+    pub fn addr(&self) -> Option<u64> {
+        self.is_data_transfer()
+            .then(|| self.known_addr().unwrap_or_default())
+    }
+
+    /// The address a load or store accesses, if the trace gave it or it's been computed
+    pub fn known_addr(&self) -> Option<u64> {
+        match self {
+            RiscVOp::Load(_, _, _, addr) | RiscVOp::Store(_, _, _, addr) => *addr,
+            _ => None,
+        }
+    }
+
+    /// The address a load or store's base register and offset add up to
+    pub fn effective_addr(&self, registers: &RegisterFile) -> Option<u64> {
+        match self {
+            RiscVOp::Load(_, _, mem, _) | RiscVOp::Store(_, _, mem, _) => {
+                Some(registers.address(*mem))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a branch was taken, if the trace says. Jumps are always taken.
+    pub fn taken(&self) -> Option<bool> {
+        match self {
+            RiscVOp::Branch(_, _, _, _, taken) => *taken,
+            RiscVOp::JumpAndLink(_, _) | RiscVOp::JumpAndLinkRegister(_, _) => Some(true),
+            _ => None,
+        }
+    }
+
+    /// The registers this reads, in the order they're written. The `offset(rs1)` operand
+    /// of a load, store or jump through a register reads its base register, and a store
+    /// reads the register holding its data first. Immediates and labels don't read
+    /// anything. None of the supported instructions read a register they don't name.
+    pub fn sources(&self) -> OperandList {
+        match self {
+            RiscVOp::Load(_, _, src, _) => OperandList::registers(&[*src]),
+            RiscVOp::Store(_, src, mem, _) => OperandList::registers(&[*src, *mem]),
+            RiscVOp::Int(_, _, src1, src2) | RiscVOp::IntImmediate(_, _, src1, src2) => {
+                OperandList::registers(&[*src1, *src2])
+            }
+            RiscVOp::LoadUpperImmediate(_, _)
+            | RiscVOp::AddUpperImmediateToPc(_, _)
+            | RiscVOp::Fence(_, _)
+            | RiscVOp::EnvironmentCall
+            | RiscVOp::Breakpoint => OperandList::registers(&[]),
+            RiscVOp::Branch(_, src1, src2, _, _) => OperandList::registers(&[*src1, *src2]),
+            RiscVOp::JumpAndLink(_, _) => OperandList::registers(&[]),
+            RiscVOp::JumpAndLinkRegister(_, target) => OperandList::registers(&[*target]),
+            RiscVOp::Float(_, _, _, src1, src2) | RiscVOp::FloatCompare(_, _, _, src1, src2) => {
+                OperandList::registers(&[*src1, *src2])
+            }
+            RiscVOp::FusedMulAdd(_, _, _, src1, src2, src3) => {
+                OperandList::registers(&[*src1, *src2, *src3])
+            }
+            RiscVOp::FloatUnary(_, _, _, src) | RiscVOp::Convert(_, _, _, src, _) => {
+                OperandList::registers(&[*src])
+            }
+        }
+    }
+
+    /// The registers this writes. Stores, branches and the system instructions don't
+    /// write any, and jumps write their return address, even to `x0`.
+    pub fn destinations(&self) -> OperandList {
+        match self {
+            RiscVOp::Store(_, _, _, _)
+            | RiscVOp::Branch(_, _, _, _, _)
+            | RiscVOp::Fence(_, _)
+            | RiscVOp::EnvironmentCall
+            | RiscVOp::Breakpoint => OperandList::registers(&[]),
+            RiscVOp::Load(_, dst, _, _)
+            | RiscVOp::Int(_, dst, _, _)
+            | RiscVOp::IntImmediate(_, dst, _, _)
+            | RiscVOp::LoadUpperImmediate(dst, _)
+            | RiscVOp::AddUpperImmediateToPc(dst, _)
+            | RiscVOp::JumpAndLink(dst, _)
+            | RiscVOp::JumpAndLinkRegister(dst, _)
+            | RiscVOp::Float(_, _, dst, _, _)
+            | RiscVOp::FusedMulAdd(_, _, dst, _, _, _)
+            | RiscVOp::FloatUnary(_, _, dst, _)
+            | RiscVOp::FloatCompare(_, _, dst, _, _)
+            | RiscVOp::Convert(_, _, dst, _, _) => OperandList::registers(&[*dst]),
+        }
+    }
+}
+
+impl Display for RiscVOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if matches!(self, RiscVOp::EnvironmentCall | RiscVOp::Breakpoint) {
+            return write!(f, "{}", self.mnemonic());
+        }
+        write!(f, "{:<6} ", self.mnemonic())?;
+        match self {
+            RiscVOp::Load(_, reg, mem, addr) | RiscVOp::Store(_, reg, mem, addr) => {
+                write!(f, "{},{}", reg, mem)?;
+                write_addr(f, *addr)
+            }
+            RiscVOp::Int(_, dst, src1, src2) | RiscVOp::IntImmediate(_, dst, src1, src2) => {
+                write!(f, "{},{},{}", dst, src1, src2)
+            }
+            RiscVOp::LoadUpperImmediate(dst, imm) | RiscVOp::AddUpperImmediateToPc(dst, imm) => {
+                write!(f, "{},{}", dst, imm)
+            }
+            RiscVOp::Branch(_, src1, src2, label, taken) => {
+                write!(f, "{},{},{}", src1, src2, label)?;
+                write_outcome(f, *taken)
+            }
+            RiscVOp::JumpAndLink(dst, label) => write!(f, "{},{}", dst, label),
+            RiscVOp::JumpAndLinkRegister(dst, target) => write!(f, "{},{}", dst, target),
+            RiscVOp::Float(_, _, dst, src1, src2)
+            | RiscVOp::FloatCompare(_, _, dst, src1, src2) => {
+                write!(f, "{},{},{}", dst, src1, src2)
+            }
+            RiscVOp::FusedMulAdd(_, _, dst, src1, src2, src3) => {
+                write!(f, "{},{},{},{}", dst, src1, src2, src3)
+            }
+            RiscVOp::FloatUnary(_, _, dst, src) => write!(f, "{},{}", dst, src),
+            RiscVOp::Convert(_, _, dst, src, rounding) => {
+                write!(f, "{},{}", dst, src)?;
+                match rounding {
+                    Some(rounding) => write!(f, ",{}", rounding.name()),
+                    None => Ok(()),
+                }
+            }
+            RiscVOp::Fence(predecessor, successor) => write!(f, "{},{}", predecessor, successor),
+            RiscVOp::EnvironmentCall | RiscVOp::Breakpoint => unreachable!(),
+        }
+    }
+}
+
+/// What executing an instruction found out, which the trace doesn't always say
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Execution {
+    /// The address a load or store accessed
+    pub addr: Option<u64>,
+    /// Whether a branch was taken. Jumps are always taken.
+    pub taken: Option<bool>,
+    /// Where a jump through a register went
+    pub target: Option<u64>,
+}
+
+impl RiscVOp {
+    /// Execute the operation at `pc` against the architectural state, updating its
+    /// registers and memory. Loads and stores access the address they record, if any,
+    /// and otherwise the one their base register and offset add up to. The state's `pc`
+    /// is left for the caller to move on, since the targets of labels aren't known here.
+    ///
+    /// Integer registers are 64 bits wide, so 32 bit results are sign extended to fill
+    /// them, as RV64I does.
+    pub fn execute(&self, pc: u64, state: &mut ArchState) -> Execution {
+        let mut result = Execution::default();
+        let regs = &mut state.registers;
+        match *self {
+            RiscVOp::Load(width, dst, src, addr) => {
+                let addr = addr.unwrap_or_else(|| regs.address(src));
+                let value = state.memory.read(addr, width.bytes());
+                regs.set(dst, width.extend(value));
+                result.addr = Some(addr);
+            }
+            RiscVOp::Store(width, src, mem, addr) => {
+                let addr = addr.unwrap_or_else(|| regs.address(mem));
+                state.memory.write(addr, width.bytes(), regs.get(src));
+                result.addr = Some(addr);
+            }
+            RiscVOp::Int(op, dst, src1, src2) | RiscVOp::IntImmediate(op, dst, src1, src2) => {
+                regs.set(dst, op.apply(regs.get(src1), regs.get(src2)));
+            }
+            RiscVOp::LoadUpperImmediate(dst, imm) => {
+                regs.set(dst, upper_immediate(regs.get(imm)));
+            }
+            RiscVOp::AddUpperImmediateToPc(dst, imm) => {
+                regs.set(dst, pc.wrapping_add(upper_immediate(regs.get(imm))));
+            }
+            RiscVOp::Branch(condition, src1, src2, _, _) => {
+                result.taken = Some(condition.holds(regs.get(src1), regs.get(src2)));
+            }
+            RiscVOp::JumpAndLink(dst, _) => {
+                regs.set(dst, pc.wrapping_add(4));
+                result.taken = Some(true);
+            }
+            RiscVOp::JumpAndLinkRegister(dst, target) => {
+                // Read the target before writing the link, in case they're the same register
+                let target = regs.address(target) & !1;
+                regs.set(dst, pc.wrapping_add(4));
+                result.taken = Some(true);
+                result.target = Some(target);
+            }
+            RiscVOp::Float(op, precision, dst, src1, src2) => {
+                let value = op.apply(precision.read(regs, src1), precision.read(regs, src2));
+                precision.write(regs, dst, value);
+            }
+            RiscVOp::FusedMulAdd(op, precision, dst, src1, src2, src3) => {
+                let a = precision.read(regs, src1);
+                let b = precision.read(regs, src2);
+                let c = precision.read(regs, src3);
+                precision.write(regs, dst, op.apply(precision, a, b, c));
+            }
+            RiscVOp::FloatUnary(op, precision, dst, src) => {
+                let bits = match precision {
+                    Precision::Single => regs.get(src) & 0xffff_ffff,
+                    Precision::Double => regs.get(src),
+                };
+                match op {
+                    FloatUnaryOp::Sqrt => {
+                        precision.write(regs, dst, precision.read(regs, src).sqrt())
+                    }
+                    FloatUnaryOp::Classify => regs.set(dst, precision.classify(bits)),
+                    FloatUnaryOp::MoveToInt => match precision {
+                        Precision::Single => regs.set(dst, bits as u32 as i32 as i64 as u64),
+                        Precision::Double => regs.set(dst, bits),
+                    },
+                    FloatUnaryOp::MoveFromInt => match precision {
+                        Precision::Single => regs.set(dst, nan_box(bits as u32)),
+                        Precision::Double => regs.set(dst, bits),
+                    },
+                }
+            }
+            RiscVOp::FloatCompare(comparison, precision, dst, src1, src2) => {
+                let holds =
+                    comparison.holds(precision.read(regs, src1), precision.read(regs, src2));
+                regs.set(dst, holds as u64);
+            }
+            RiscVOp::Convert(to, from, dst, src, rounding) => {
+                let rounding = rounding.unwrap_or(Rounding::Dynamic);
+                regs.set(dst, FloatFormat::convert(to, from, regs.get(src), rounding));
+            }
+            RiscVOp::Fence(_, _) | RiscVOp::EnvironmentCall | RiscVOp::Breakpoint => {}
+        }
+        result
+    }
+
+    /// The same operation accessing a different address
+    pub fn with_addr(self, addr: u64) -> Self {
+        match self {
+            RiscVOp::Load(width, dst, src, _) => RiscVOp::Load(width, dst, src, Some(addr)),
+            RiscVOp::Store(width, src, mem, _) => RiscVOp::Store(width, src, mem, Some(addr)),
+            op => op,
+        }
+    }
+
+    /// The label a branch or `jal` goes to
+    pub fn label(&self) -> Option<Label> {
+        match self {
+            RiscVOp::Branch(_, _, _, label, _) | RiscVOp::JumpAndLink(_, label) => Some(*label),
+            _ => None,
+        }
+    }
+
+    /// The same branch or `jal`, going to the instruction at `target`
+    pub fn with_target(self, target: usize) -> Self {
+        let resolve = |label: Label| Label {
+            target: Some(target),
+            ..label
+        };
+        match self {
+            RiscVOp::Branch(condition, src1, src2, label, taken) => {
+                RiscVOp::Branch(condition, src1, src2, resolve(label), taken)
+            }
+            RiscVOp::JumpAndLink(dst, label) => RiscVOp::JumpAndLink(dst, resolve(label)),
+            op => op,
+        }
+    }
+
+    /// The same branch with a different outcome
+    pub fn with_taken(self, taken: bool) -> Self {
+        match self {
+            RiscVOp::Branch(condition, src1, src2, label, _) => {
+                RiscVOp::Branch(condition, src1, src2, label, Some(taken))
+            }
+            op => op,
+        }
+    }
+}
+
+/// The value of a `lui` immediate, moved up to bits 12 to 31 and sign extended
+fn upper_immediate(imm: u64) -> u64 {
+    (imm << 12) as u32 as i32 as i64 as u64
+}
+
+/// Put a single precision value in the low half of a double precision register,
+/// with the upper half all ones, as the F extension requires
+fn nan_box(bits: u32) -> u64 {
+    0xffff_ffff_0000_0000 | bits as u64
+}
+
+/// The integer and floating point registers. `x0` always reads 0, and floating point
+/// registers hold the raw bits of their values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisterFile {
+    gp: [u64; REGISTER_COUNT as usize],
+    fp: [u64; REGISTER_COUNT as usize],
+}
+
+impl RegisterFile {
+    /// Registers holding the given values, and 0 everywhere else
+    pub fn new(values: &BTreeMap<Register, u64>) -> Self {
+        let mut result = Self::default();
+        for (reg, value) in values {
+            result.write(*reg, *value);
+        }
+        result
+    }
+}
+
+impl Default for RegisterFile {
+    fn default() -> Self {
+        Self {
+            gp: [0; REGISTER_COUNT as usize],
+            fp: [0; REGISTER_COUNT as usize],
+        }
+    }
+}
+
+impl RegisterFile {
+    pub fn read(&self, reg: Register) -> u64 {
+        match reg {
+            Register::GP(r) => self.gp[r as usize],
+            Register::FP(r) => self.fp[r as usize],
+        }
+    }
+
+    /// Write a register. Writes to `x0` are thrown away.
+    pub fn write(&mut self, reg: Register, value: u64) {
+        match reg {
+            Register::GP(0) => {}
+            Register::GP(r) => self.gp[r as usize] = value,
+            Register::FP(r) => self.fp[r as usize] = value,
+        }
+    }
+
+    fn get(&self, op: Operand) -> u64 {
+        match op {
+            Operand::Immediate(imm) => imm,
+            op => op.dep_reg().map_or(0, |reg| self.read(reg)),
+        }
+    }
+
+    fn set(&mut self, op: Operand, value: u64) {
+        if let Some(reg) = op.dep_reg() {
+            self.write(reg, value);
+        }
+    }
+
+    fn f32(&self, op: Operand) -> f32 {
+        f32::from_bits(self.get(op) as u32)
+    }
+
+    fn set_f32(&mut self, op: Operand, value: f32) {
+        self.set(op, nan_box(value.to_bits()));
+    }
+
+    /// The address of an `offset(reg)` operand
+    fn address(&self, op: Operand) -> u64 {
+        match op {
+            Operand::Indirect(reg, offset) => self.read(reg).wrapping_add(offset),
+            op => self.get(op),
+        }
+    }
+}
+
+/// A little endian memory which only stores the bytes which have been written,
+/// and reads 0 everywhere else
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SparseMemory {
+    bytes: BTreeMap<u64, u8>,
+}
+
+impl SparseMemory {
+    /// Memory holding the given words, and 0 everywhere else
+    pub fn new(words: &BTreeMap<u64, u32>) -> Self {
+        let mut result = Self::default();
+        for (addr, word) in words {
+            result.write(*addr, 4, *word as u64);
+        }
+        result
+    }
+
+    /// Read `size` bytes, up to 8, starting at `addr`
+    pub fn read(&self, addr: u64, size: u64) -> u64 {
+        (0..size).rev().fold(0, |value, i| {
+            let byte = self.bytes.get(&addr.wrapping_add(i)).copied();
+            value << 8 | byte.unwrap_or_default() as u64
+        })
+    }
+
+    /// Write the low `size` bytes of a value, up to 8, starting at `addr`
+    pub fn write(&mut self, addr: u64, size: u64, value: u64) {
+        for i in 0..size {
+            self.bytes
+                .insert(addr.wrapping_add(i), (value >> (8 * i)) as u8);
+        }
+    }
+
+    /// The 4 byte aligned words which have been written, by address
+    pub fn words(&self) -> BTreeMap<u64, u32> {
+        let mut words = BTreeMap::new();
+        for addr in self.bytes.keys() {
+            let word = addr - addr % 4;
+            words.insert(word, self.read(word, 4) as u32);
+        }
+        words
+    }
+}
+
+/// The registers and memory of the machine the trace ran on, as a program would see them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchState {
+    /// The address of the next instruction
+    pub pc: u64,
+    pub registers: RegisterFile,
+    pub memory: SparseMemory,
+}
+
+impl Display for ArchState {
+    /// Every register, four to a line, followed by each word of memory which was written
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pc  {:#018x}", self.pc)?;
+        for (prefix, file) in [("x", &self.registers.gp), ("f", &self.registers.fp)] {
+            for (row, values) in file.chunks(4).enumerate() {
+                let line = values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        let name = format!("{prefix}{}", row * 4 + i);
+                        format!("{name:<3} {value:#018x}")
+                    })
+                    .collect::<Vec<_>>();
+                writeln!(f, "{}", line.join("  "))?;
+            }
+        }
+        for (addr, word) in self.memory.words() {
+            writeln!(f, "{addr:#018x}: {word:#010x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Is this `ra` or `t0`, which hold return addresses by the calling convention?
+fn is_link_register(reg: Option<Register>) -> bool {
+    matches!(reg, Some(Register::GP(1)) | Some(Register::GP(5)))
+}
+
+/// Write a load or store's address in the same `:addr` form as the trace, if it's known
+fn write_addr(f: &mut Formatter<'_>, addr: Option<u64>) -> fmt::Result {
+    match addr {
+        Some(addr) => write!(f, ":{addr}"),
+        None => Ok(()),
+    }
+}
+
+/// Write a branch outcome in the same `:T` or `:N` form as the trace
+fn write_outcome(f: &mut Formatter<'_>, taken: Option<bool>) -> fmt::Result {
+    match taken {
+        Some(true) => write!(f, ":T"),
+        Some(false) => write!(f, ":N"),
+        None => Ok(()),
+    }
+}
+
+impl Debug for RiscVOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Which register file an operand must name
+#[derive(Clone, Copy)]
+enum RegisterClass {
+    GP,
+    FP,
+}
+
+/// A piece of a trace line, along with the column it starts at
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    /// Get the trimmed text of `line[start..end]`
+    fn new(line: &'a str, start: usize, end: usize) -> Self {
+        let text = &line[start..end];
+        let trimmed = text.trim_start();
+        Self {
+            text: trimmed.trim_end(),
+            column: start + text.len() - trimmed.len() + 1,
+        }
+    }
+
+    /// Get the token up to the first whitespace character
+    fn first_word(self) -> Self {
+        match self.text.find(char::is_whitespace) {
+            Some(end) => Self {
+                text: &self.text[..end],
+                column: self.column,
+            },
+            None => self,
+        }
+    }
+
+    fn error(&self, kind: TraceErrorKind) -> TraceError {
+        TraceError {
+            line: 1,
+            column: self.column,
+            token: self.text.to_string(),
+            kind,
+        }
+    }
+
+    fn integer(&self) -> Option<u64> {
+        if let Some(hex) = self.text.strip_prefix("0x") {
+            u64::from_str_radix(hex, 16).ok()
+        } else {
+            self.text.parse::<u64>().ok()
+        }
+    }
+
+    /// An integer which may be negative, such as an offset or an immediate,
+    /// as its two's complement bits
+    fn signed(&self) -> Option<i64> {
+        match self.text.strip_prefix('-') {
+            Some(magnitude) => {
+                let magnitude = Token {
+                    text: magnitude,
+                    column: self.column,
+                };
+                i64::try_from(magnitude.integer()?).ok().map(|n| -n)
+            }
+            None => self.integer().and_then(|n| i64::try_from(n).ok()),
+        }
+    }
+
+    fn address(&self) -> Result<u64, TraceError> {
+        self.integer()
+            .ok_or_else(|| self.error(TraceErrorKind::BadAddress))
+    }
+
+    /// Whether a branch was taken, from `T` or `N`
+    fn outcome(&self) -> Result<bool, TraceError> {
+        match self.text {
+            "T" => Ok(true),
+            "N" => Ok(false),
+            _ => Err(self.error(TraceErrorKind::BadOutcome)),
+        }
+    }
+}
+
+/// The comma separated operands following a mnemonic
+struct Operands<'a> {
+    args: Vec<Token<'a>>,
+    /// The column just past the last operand, for reporting missing ones
+    end: usize,
+}
+
+impl<'a> Operands<'a> {
+    fn split(body: &'a str, mnemonic: Token<'a>) -> Self {
+        let mut start = mnemonic.column - 1 + mnemonic.text.len();
+        let mut args = Vec::new();
+        if !body[start..].trim().is_empty() {
+            for arg in body[start..].split(',') {
+                args.push(Token::new(body, start, start + arg.len()));
+                start += arg.len() + 1;
+            }
+        }
+        Self {
+            args,
+            end: body.trim_end().len() + 1,
+        }
+    }
+
+    /// Confirm there are exactly `count` non-empty operands
+    fn expect(&self, count: usize) -> Result<(), TraceError> {
+        if let Some(empty) = self.args.iter().find(|arg| arg.text.is_empty()) {
+            return Err(empty.error(TraceErrorKind::MissingOperand));
+        }
+        if self.args.len() < count {
+            return Err(TraceError {
+                line: 1,
+                column: self.end,
+                token: String::new(),
+                kind: TraceErrorKind::MissingOperand,
+            });
+        }
+        if self.args.len() > count {
+            return Err(self.args[count].error(TraceErrorKind::UnexpectedOperand));
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    /// Parse a rounding mode, such as `rtz`
+    fn rounding(&self, i: usize) -> Result<Rounding, TraceError> {
+        let arg = &self.args[i];
+        Rounding::from_name(arg.text).ok_or_else(|| arg.error(TraceErrorKind::BadOperand))
+    }
+
+    fn register(&self, i: usize, class: RegisterClass) -> Result<Operand, TraceError> {
+        let arg = &self.args[i];
+        let reg = match class {
+            RegisterClass::GP => Register::parse_gp(arg.text),
+            RegisterClass::FP => Register::parse_fp(arg.text),
+        };
+        reg.map(Operand::Register)
+            .ok_or_else(|| arg.error(TraceErrorKind::BadRegister))
+    }
+
+    /// Parse an indirect address such as `32(x2)`
+    fn indirect(&self, i: usize) -> Result<Operand, TraceError> {
+        let arg = &self.args[i];
+        let (offset, reg) = arg
+            .text
+            .strip_suffix(')')
+            .and_then(|arg| arg.split_once('('))
+            .ok_or_else(|| arg.error(TraceErrorKind::BadOperand))?;
+
+        let offset = Token {
+            text: offset.trim(),
+            column: arg.column,
+        };
+        let offset = offset
+            .signed()
+            .ok_or_else(|| offset.error(TraceErrorKind::BadOperand))?;
+
+        let reg = Token {
+            text: reg.trim(),
+            column: arg.column + arg.text.find('(').unwrap_or(0) + 1,
+        };
+        let reg =
+            Register::parse_gp(reg.text).ok_or_else(|| reg.error(TraceErrorKind::BadRegister))?;
+
+        Ok(Operand::Indirect(reg, offset as u64))
+    }
+
+    /// Parse an immediate, which must be between `min` and `max`
+    fn immediate(&self, i: usize, min: i64, max: i64) -> Result<Operand, TraceError> {
+        let arg = &self.args[i];
+        arg.signed()
+            .filter(|imm| (min..=max).contains(imm))
+            .map(|imm| Operand::Immediate(imm as u64))
+            .ok_or_else(|| arg.error(TraceErrorKind::BadOperand))
+    }
+
+    /// Parse the kinds of access a `fence` orders, such as `rw`
+    fn fence_set(&self, i: usize) -> Result<FenceSet, TraceError> {
+        let arg = &self.args[i];
+        FenceSet::parse(arg.text).ok_or_else(|| arg.error(TraceErrorKind::BadOperand))
+    }
+
+    fn label(&self, i: usize) -> Result<Label, TraceError> {
+        let arg = &self.args[i];
+        Label::parse(arg.text).ok_or_else(|| arg.error(TraceErrorKind::BadOperand))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_instructions_display_as_they_parse() {
+        for line in [
+            "fence  iorw,iorw",
+            "fence  rw,w",
+            "fence  w,0",
+            "ecall",
+            "ebreak",
+        ] {
+            let op = RiscVOp::parse(line).unwrap();
+            assert_eq!(op.to_string(), line);
+        }
+        assert_eq!(
+            RiscVOp::parse("fence").unwrap().to_string(),
+            "fence  iorw,iorw"
+        );
+    }
+
+    #[test]
+    fn bad_fence_sets_are_rejected() {
+        for line in [
+            "fence rx,w",
+            "fence wr,r",
+            "fence r",
+            "fence ,w",
+            "ecall x1",
+        ] {
+            assert!(RiscVOp::parse(line).is_err(), "{line} parsed");
+        }
+    }
+
+    #[test]
+    fn system_instructions_use_an_alu_without_registers() {
+        for line in ["fence r,rw", "ecall", "ebreak"] {
+            let op = RiscVOp::parse(line).unwrap();
+            assert!(op.is_alu());
+            assert_eq!(op.class(), OpClass::Int);
+            assert!(op.sources().is_empty());
+            assert!(op.destinations().is_empty());
+        }
+    }
+
+    #[test]
+    fn rv32_upper_products() {
+        let (a, b) = (-7i64 as u64, 0xffff_ffffu32 as i32 as u64);
+        assert_eq!(IntOp::MulHighWord.apply(a, b), 0);
+        assert_eq!(IntOp::MulHighSignedUnsignedWord.apply(a, b), -7i64 as u64);
+        assert_eq!(IntOp::MulHighUnsignedWord.apply(a, b), -8i64 as u64);
+        for line in ["mulhw x1,x2,x3", "mulhsuw x1,x2,x3", "mulhuw x1,x2,x3"] {
+            assert!(RiscVOp::parse(line).is_err(), "{line} parsed");
+        }
+        let op = RiscVOp::parse("mulh x1,x2,x3").unwrap();
+        assert!(matches!(op, RiscVOp::Int(IntOp::MulHigh, _, _, _)));
+    }
+
+    #[test]
+    fn pseudo_instructions_are_the_instructions_they_stand_for() {
+        for (pseudo, op) in [
+            ("nop", "addi   x0,x0,0"),
+            ("li x5,-2048", "addi   x5,x0,-2048"),
+            ("mv x5,x6", "addi   x5,x6,0"),
+            ("not x5,x6", "xori   x5,x6,-1"),
+            ("neg x5,x6", "sub    x5,x0,x6"),
+            ("beqz x5,Loop:T", "beq    x5,x0,Loop:T"),
+            ("bnez x5,Loop", "bne    x5,x0,Loop"),
+            ("blez x5,Loop:N", "bge    x0,x5,Loop:N"),
+            ("bgtz x5,Loop", "blt    x0,x5,Loop"),
+            ("j Loop", "jal    x0,Loop"),
+            ("call f", "jal    x1,f"),
+            ("jr x5", "jalr   x0,0(x5)"),
+            ("ret", "jalr   x0,0(x1)"),
+        ] {
+            assert_eq!(RiscVOp::parse(pseudo).unwrap().to_string(), op);
+        }
+        for line in ["li x5,4096", "ret x1", "jr 0(x1)", "mv x5,f1", "j Loop:T"] {
+            assert!(RiscVOp::parse(line).is_err(), "{line} parsed");
+        }
+    }
+
+    #[test]
+    fn wide_li_is_lui_and_addiw() {
+        let expand = |line| {
+            RiscVOp::parse_expanded(line)
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(expand("li x5,100"), ["addi   x5,x0,100"]);
+        assert_eq!(expand("li x5,0x12345000"), ["lui    x5,74565"]);
+        assert_eq!(
+            expand("li x5,0x12345fff"),
+            ["lui    x5,74566", "addiw  x5,x5,-1"]
+        );
+        assert_eq!(
+            expand("li x5,0x7fffffff"),
+            ["lui    x5,524288", "addiw  x5,x5,-1"]
+        );
+        assert_eq!(expand("li x5,-4096"), ["lui    x5,1048575"]);
+        assert_eq!(expand("add x5,x6,x7"), ["add    x5,x6,x7"]);
+        assert!(RiscVOp::parse_expanded("li x5,0x80000000").is_err());
+        assert!(RiscVOp::parse_expanded("li x5,4096:0").is_err());
+    }
+
+    #[test]
+    fn addresses_are_the_base_register_plus_the_offset() {
+        let registers = RegisterFile::new(&BTreeMap::from([
+            (Register::GP(2), 0x1000),
+            (Register::GP(3), 4),
+        ]));
+        for (line, addr) in [
+            ("lw x1,8(x2)", 0x1008),
+            ("sd x1,-8(x2)", 0xff8),
+            ("flw f1,0(x2)", 0x1000),
+            ("lbu x1,-8(x3)", u64::MAX - 3),
+            ("sw x1,12(x0)", 12),
+        ] {
+            let op = RiscVOp::parse(line).unwrap();
+            assert_eq!(op.known_addr(), None, "{line}");
+            assert_eq!(op.effective_addr(&registers), Some(addr), "{line}");
+        }
+        assert_eq!(
+            RiscVOp::parse("addi x1,x2,8")
+                .unwrap()
+                .effective_addr(&registers),
+            None
+        );
+
+        // An address from the trace is what the load executes with
+        let mut state = ArchState {
+            registers,
+            ..ArchState::default()
+        };
+        state.memory.write(0x40, 4, 7);
+        let op = RiscVOp::parse("lw x1,8(x2):64").unwrap();
+        assert_eq!(op.known_addr(), Some(64));
+        assert_eq!(op.execute(0, &mut state).addr, Some(64));
+        assert_eq!(state.registers.read(Register::GP(1)), 7);
+    }
+
+    #[test]
+    fn word_operations_sign_extend_the_low_32_bits() {
+        let minus = |value: i64| value as u64;
+        for (op, a, b, result) in [
+            (IntOp::AddWord, 0x7fff_ffff, 1, minus(-0x8000_0000)),
+            (IntOp::AddWord, 0x1_0000_0005, 0x2_0000_0003, 8),
+            (IntOp::SubWord, 0, 1, u64::MAX),
+            (IntOp::ShiftLeftWord, 1, 31, minus(-0x8000_0000)),
+            (IntOp::ShiftLeftWord, 1, 33, 2),
+            (IntOp::ShiftRightLogicalWord, 0xffff_ffff_8000_0000, 31, 1),
+            (IntOp::ShiftRightArithmeticWord, 0x8000_0000, 31, u64::MAX),
+            (IntOp::MulWord, 0x1_0001, 0x1_0000, 0x1_0000),
+            (IntOp::MulWord, 0x8000_0000, 1, minus(-0x8000_0000)),
+            (IntOp::DivWord, minus(-7), 2, minus(-3)),
+            (IntOp::DivUnsignedWord, 0xffff_fffe, 2, 0x7fff_ffff),
+            (IntOp::RemWord, minus(-7), 2, minus(-1)),
+            (IntOp::RemUnsignedWord, 0x1_0000_0007, 4, 3),
+            // The full width operations use all 64 bits
+            (IntOp::Add, 0x7fff_ffff, 1, 0x8000_0000),
+            (IntOp::ShiftLeft, 1, 33, 0x2_0000_0000),
+            (IntOp::ShiftLeft, 1, 65, 2),
+        ] {
+            assert_eq!(op.apply(a, b), result, "{op:?} {a:#x} {b:#x}");
+        }
+    }
+
+    #[test]
+    fn division_by_zero_and_overflow_dont_trap() {
+        let minus = |value: i64| value as u64;
+        let min_word = minus(i32::MIN as i64);
+        for (op, a, b, result) in [
+            (IntOp::Div, 7, 0, u64::MAX),
+            (IntOp::DivUnsigned, 7, 0, u64::MAX),
+            (IntOp::Rem, 7, 0, 7),
+            (IntOp::RemUnsigned, 7, 0, 7),
+            (IntOp::Div, minus(i64::MIN), u64::MAX, minus(i64::MIN)),
+            (IntOp::Rem, minus(i64::MIN), u64::MAX, 0),
+            (IntOp::DivWord, 7, 0x1_0000_0000, u64::MAX),
+            (IntOp::DivUnsignedWord, 7, 0, u64::MAX),
+            (IntOp::RemWord, 0x1_8000_0000, 0, min_word),
+            (IntOp::RemUnsignedWord, 0x1_8000_0000, 0, min_word),
+            (IntOp::DivWord, min_word, u64::MAX, min_word),
+            (IntOp::RemWord, min_word, u64::MAX, 0),
+        ] {
+            assert_eq!(op.apply(a, b), result, "{op:?} {a:#x} {b:#x}");
+        }
+    }
+
+    #[test]
+    fn word_instructions_execute_on_registers_and_immediates() {
+        let mut state = ArchState::default();
+        for line in [
+            "addi  x1,x0,-1",
+            "addiw x2,x1,0",
+            "srliw x3,x1,4",
+            "slliw x4,x1,31",
+            "addi  x5,x0,3",
+            "divw  x6,x1,x5",
+            "remuw x7,x1,x5",
+        ] {
+            RiscVOp::parse(line).unwrap().execute(0, &mut state);
+        }
+        let read = |r| state.registers.read(Register::GP(r));
+        assert_eq!(read(2), u64::MAX);
+        assert_eq!(read(3), 0x0fff_ffff);
+        assert_eq!(read(4), 0xffff_ffff_8000_0000);
+        assert_eq!(read(6), 0);
+        assert_eq!(read(7), 0);
+        assert_eq!(
+            RiscVOp::parse("divw x6,x1,x5").unwrap().functional_unit(),
+            RiscVOp::parse("div x6,x1,x5").unwrap().functional_unit()
+        );
+    }
+
+    /// Execute each line, starting from registers holding the given values, and return
+    /// what's left in the register the last one writes
+    fn run(registers: &[(Register, u64)], lines: &[&str]) -> u64 {
+        let mut state = ArchState {
+            registers: RegisterFile::new(&registers.iter().copied().collect()),
+            ..ArchState::default()
+        };
+        let mut last = None;
+        for line in lines {
+            let op = RiscVOp::parse(line).unwrap();
+            last = op.destinations().first().and_then(Operand::dep_reg);
+            op.execute(0, &mut state);
+        }
+        state.registers.read(last.unwrap())
+    }
+
+    #[test]
+    fn conversions_to_integers_round_as_asked() {
+        let single = |value: f32| (Register::FP(1), nan_box(value.to_bits()));
+        for (value, rounding, result) in [
+            (2.5, "rne", 2),
+            (3.5, "rne", 4),
+            (-2.5, "rne", -2),
+            (2.5, "dyn", 2),
+            (2.7, "rtz", 2),
+            (-2.7, "rtz", -2),
+            (-2.5, "rdn", -3),
+            (2.1, "rup", 3),
+            (2.5, "rmm", 3),
+            (-2.5, "rmm", -3),
+            (1e10, "rne", i32::MAX as i64),
+            (-1e10, "rne", i32::MIN as i64),
+            (f32::NAN, "rne", i32::MAX as i64),
+        ] {
+            let line = format!("fcvt.w.s x1,f1,{rounding}");
+            assert_eq!(
+                run(&[single(value)], &[&line]),
+                result as u64,
+                "{value} {rounding}"
+            );
+        }
+        // Unsigned words saturate at 0, and are sign extended like any other word
+        assert_eq!(run(&[single(-1.0)], &["fcvt.wu.s x1,f1"]), 0);
+        assert_eq!(
+            run(&[single(3e9)], &["fcvt.wu.s x1,f1"]),
+            3_000_000_000u32 as i32 as u64
+        );
+        assert_eq!(run(&[single(1e20)], &["fcvt.lu.s x1,f1,rtz"]), u64::MAX);
+    }
+
+    #[test]
+    fn single_precision_results_are_nan_boxed() {
+        let x = |value: i64| (Register::GP(1), value as u64);
+        assert_eq!(
+            run(&[x(-3)], &["fcvt.s.w f1,x1"]),
+            0xffff_ffff_0000_0000 | (-3.0f32).to_bits() as u64
+        );
+        assert_eq!(
+            run(&[x(0x1234_5678_3f80_0000)], &["fmv.w.x f1,x1"]),
+            0xffff_ffff_3f80_0000
+        );
+        // Moving a single back only takes its low 32 bits, sign extended
+        assert_eq!(
+            run(&[x(0xbf80_0000)], &["fmv.w.x f1,x1", "fmv.x.w x2,f1"]),
+            0xffff_ffff_bf80_0000
+        );
+        assert_eq!(
+            run(&[x(1)], &["fcvt.s.w f1,x1", "fadd.s f2,f1,f1"]),
+            0xffff_ffff_0000_0000 | 2.0f32.to_bits() as u64
+        );
+        // Doubles take the whole register
+        assert_eq!(
+            run(&[x(1)], &["fcvt.d.w f1,x1", "fcvt.s.d f2,f1"]),
+            0xffff_ffff_0000_0000 | 1.0f32.to_bits() as u64
+        );
+        assert_eq!(run(&[x(1)], &["fcvt.d.w f1,x1"]), 1.0f64.to_bits());
+    }
+
+    #[test]
+    fn fused_multiply_add_rounds_once() {
+        // (1 + 2^-12)^2 is 1 + 2^-11 + 2^-24, whose last term is lost if the product is
+        // rounded to single precision before the add, and when the sum is near 2
+        let single = |r, value: f32| (Register::FP(r), nan_box(value.to_bits()));
+        let a = single(1, 1.0 + 2f32.powi(-12));
+        let c = single(3, 1.0 + 2f32.powi(-11));
+        let result = |line| f32::from_bits(run(&[a, c], &[line]) as u32);
+        assert_eq!(result("fmsub.s f4,f1,f1,f3"), 2f32.powi(-24));
+        assert_eq!(result("fnmadd.s f4,f1,f1,f3"), -(2.0 + 2f32.powi(-10)));
+        assert_eq!(
+            run(&[a, c], &["fmul.s f4,f1,f1", "fsub.s f4,f4,f3"]),
+            nan_box(0)
+        );
+
+        let double = |r, value: f64| (Register::FP(r), value.to_bits());
+        let a = double(1, 1.0 + 2f64.powi(-30));
+        let c = double(3, -(1.0 + 2f64.powi(-29)));
+        assert_eq!(
+            f64::from_bits(run(&[a, c], &["fmadd.d f4,f1,f1,f3"])),
+            2f64.powi(-60)
+        );
+        assert_eq!(
+            f64::from_bits(run(&[a, c], &["fnmsub.d f4,f1,f1,f3"])),
+            -(2.0 + 2f64.powi(-28) + 2f64.powi(-60))
+        );
+    }
+
+    #[test]
+    fn sources_and_destinations_of_each_shape() {
+        let names = |operands: OperandList| {
+            operands
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        for (line, sources, destinations) in [
+            ("lw x1,8(x2)", "8(x2)", "x1"),
+            ("fld f1,0(x2)", "0(x2)", "f1"),
+            ("sw x1,-4(x2)", "x1 -4(x2)", ""),
+            ("fsd f3,0(x2)", "f3 0(x2)", ""),
+            ("add x1,x2,x3", "x2 x3", "x1"),
+            ("addi x1,x2,5", "x2", "x1"),
+            ("lui x1,5", "", "x1"),
+            ("auipc x1,5", "", "x1"),
+            ("beq x1,x2,Loop:T", "x1 x2", ""),
+            ("jal x1,Loop", "", "x1"),
+            ("jal x0,Loop", "", "x0"),
+            ("jalr x1,8(x5)", "8(x5)", "x1"),
+            ("fadd.d f1,f2,f3", "f2 f3", "f1"),
+            ("feq.s x1,f2,f3", "f2 f3", "x1"),
+            ("fmadd.s f1,f2,f3,f4", "f2 f3 f4", "f1"),
+            ("fsqrt.d f1,f2", "f2", "f1"),
+            ("fmv.x.w x1,f2", "f2", "x1"),
+            ("fcvt.s.w f1,x2", "x2", "f1"),
+            ("fcvt.w.d x1,f2,rtz", "f2", "x1"),
+            ("fence", "", ""),
+            ("ecall", "", ""),
+            ("ebreak", "", ""),
+        ] {
+            let op = RiscVOp::parse(line).unwrap();
+            assert_eq!(names(op.sources()), sources, "{line}");
+            assert_eq!(names(op.destinations()), destinations, "{line}");
+        }
     }
 }
@@ -1,30 +1,160 @@
 use super::*;
 use log::*;
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::BTreeMap,
     fmt::{Display, Formatter},
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Stage {
     Issue,
-    Execute(u64), // u64 is the cycles left to execute
-    MemAccess,
+    Execute(u64),   // u64 is the cycles left to execute
+    MemAccess(u64), // u64 is the cycles left to access memory
     WriteBack,
     WaitingToCommit,
     Commit,
 }
 
+/// The number of cycles instructions spent stalled, by cause
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Delays {
+    pub reorder_buffer: u64,
+    pub reservation_station: u64,
+    /// Issue slots lost because the load/store queue was full
+    pub load_store_queue: u64,
+    /// Cycles a load spent waiting for the data of an older store to the same address
+    pub data_memory_conflict: u64,
+    /// Cycles a load spent in the memory stage waiting for a line missing from the L1
+    pub cache_miss: u64,
+    /// Cycles a load which missed waited for an MSHR to be free
+    pub mshr_full: u64,
+    pub true_dependence: u64,
+    /// Cycles an instruction was ready to execute, but every unit had
+    /// already started an instruction that cycle
+    pub functional_unit: u64,
+    /// Cycles an instruction was ready to execute, but a unit which isn't
+    /// fully pipelined was still busy with an earlier instruction
+    pub unpipelined_unit: u64,
+    /// Cycles an instruction was ready to write its result, but every
+    /// common data bus was taken by an older instruction
+    pub cdb_conflict: u64,
+    /// Issue slots lost while the front end was redirected after a mispredicted branch
+    pub branch_mispredict: u64,
+    /// Issue slots lost while the front end was redirected to replay a load
+    pub memory_replay: u64,
+    /// Issue slots lost while the front end was redirected to the target of a branch
+    /// which was predicted taken, but missed in the branch target buffer
+    pub front_end_redirect: u64,
+}
+
+/// How well branches were predicted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    /// The branches and jumps which committed
+    pub branches: u64,
+    /// The branches and jumps which went somewhere other than predicted
+    pub mispredictions: u64,
+    /// The instructions issued after a mispredicted branch, which were thrown away
+    pub squashed: u64,
+    pub btb_hits: u64,
+    pub btb_misses: u64,
+    /// Returns whose target was predicted by the return address stack, correctly or not
+    pub ras_hits: u64,
+    pub ras_misses: u64,
+}
+
+/// How well the branch or jump at one address was predicted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BranchPcStats {
+    /// The times it committed
+    pub executed: u64,
+    pub btb_hits: u64,
+    pub btb_misses: u64,
+    pub mispredictions: u64,
+}
+
+/// What happened to the loads and stores
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// The loads which committed
+    pub loads: u64,
+    /// The stores which committed
+    pub stores: u64,
+    /// The loads which took their value from an older store, instead of reading memory
+    pub forwarded_loads: u64,
+    /// The loads which were found to have gone ahead of an older store to the same address
+    pub violations: u64,
+    /// The times loads were replayed, with the instructions after them
+    pub replays: u64,
+    /// The instructions thrown away to replay loads
+    pub replayed: u64,
+}
+
+/// Why an instruction couldn't be issued
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueStall {
+    /// Every reorder buffer entry is in use
+    ReorderBuffer,
+    /// Every reservation station for the instruction's unit is in use
+    ReservationStation,
+    /// Every load/store queue entry is in use
+    LoadStoreQueue,
+    /// The front end is being redirected after a load which read memory too early was replayed
+    MemoryReplay,
+    /// The front end is being redirected after a mispredicted branch
+    BranchMispredict,
+    /// The front end is being redirected to the target of a branch which missed in the BTB
+    FrontEndRedirect,
+}
+
+/// Where to restart issuing from after instructions were squashed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Redirect {
+    /// The first instruction which was squashed
+    pub next: usize,
+    /// Why they were squashed, which the issue slots lost redirecting are counted as
+    pub stall: IssueStall,
+}
+
+/// An instruction in the reorder buffer, along with its reservation station
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    /// The order the instruction was issued in, which is also the tag
+    /// its result is broadcast with
+    tag: usize,
+    pc: u64,
+    op: RiscVOp,
+    stage: Stage,
+    /// The tags of the instructions producing each of the source operands,
+    /// until they broadcast their results
+    waiting_for: [Option<usize>; MAX_OPERANDS],
+    /// The address of the instruction which ran after this one
+    next_pc: u64,
+    /// Whether this is a branch whose outcome was mispredicted when it was issued
+    mispredicted: bool,
+    /// Whether this is a load which took its value from an older store
+    forwarded: bool,
+    /// Whether a load or store found its line in the L1, once it has accessed it
+    served_by: Option<ServedBy>,
+    /// Whether a branch's target was found in the BTB, if it was looked up
+    btb_hit: Option<bool>,
+    /// Whether a return's target was correctly predicted by the return address stack,
+    /// if it was used
+    ras_hit: Option<bool>,
+}
+
 pub struct ReorderBuffer {
-    register_mapping: BTreeMap<Register, u64>,
-    addresses_stored: BTreeSet<u64>,
-    addresses_loaded: BTreeSet<u64>,
+    /// The tag of the latest instruction in flight which writes each register
+    register_mapping: BTreeMap<Register, usize>,
+    lsq: LoadStoreQueue,
 
     available_reservation_stations: BTreeMap<FunctionalUnit, usize>,
+    /// The cycles until each execution unit can start another instruction, by kind
+    busy_units: BTreeMap<FunctionalUnit, Vec<u64>>,
 
     // The instructions in the reorder buffer are stored in a circular buffer.
     // Entries must be in order, but the head and tail can be anywhere.
-    entries: Vec<Option<(usize, RiscVOp, Stage)>>,
+    entries: Vec<Option<Entry>>,
     issue_count: usize,
 
     head: usize,
@@ -32,11 +162,28 @@ pub struct ReorderBuffer {
     size: usize,
     entries_used: usize,
     entries_committed: usize,
-    
-    reorder_buffer_delays: u64,
-    reservation_station_delays: u64,
-    data_memory_conflict_delays: u64,
-    true_dependence_delays: u64
+
+    delays: Delays,
+    memory: MemoryStats,
+    /// The tags of the loads which took their value from a store in the last cycle
+    forwarded: Vec<usize>,
+    caches: Option<MemoryHierarchy>,
+    /// The tags of the loads and stores which accessed the caches in the last cycle,
+    /// and where they found their line
+    cache_accesses: Vec<(usize, ServedBy)>,
+
+    predictor: Box<dyn BranchPredictor>,
+    btb: BranchTargetBuffer,
+    ras: ReturnAddressStack,
+    /// The return address stack before each branch and jump in flight was issued,
+    /// by tag, to restore if it's squashed
+    ras_checkpoints: BTreeMap<usize, ReturnAddressStack>,
+    branches: BranchStats,
+    branch_pcs: BTreeMap<u64, BranchPcStats>,
+    /// Where to restart issuing from, after instructions were squashed
+    redirect: Option<Redirect>,
+    /// Whether the last instruction issued was a branch whose target wasn't known
+    bubble: bool,
 }
 
 impl From<&Config> for ReorderBuffer {
@@ -46,8 +193,51 @@ impl From<&Config> for ReorderBuffer {
 }
 
 impl ReorderBuffer {
-    pub fn get_delays(&self) -> (u64, u64, u64, u64) {
-        (self.reorder_buffer_delays, self.reservation_station_delays, self.data_memory_conflict_delays, self.true_dependence_delays)
+    pub fn get_delays(&self) -> Delays {
+        self.delays
+    }
+
+    pub fn get_memory_stats(&self) -> MemoryStats {
+        self.memory
+    }
+
+    /// The tags of the loads which took their value from an older store in the last cycle
+    pub fn take_forwarded(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.forwarded)
+    }
+
+    /// The tags of the loads and stores which accessed the caches in the last cycle,
+    /// and where they found their line
+    pub fn take_cache_accesses(&mut self) -> Vec<(usize, ServedBy)> {
+        std::mem::take(&mut self.cache_accesses)
+    }
+
+    /// The hits, misses and MSHR stalls of each cache level, from the L1 down
+    pub fn get_cache_stats(&self) -> Vec<CacheStats> {
+        self.caches
+            .as_ref()
+            .map(|caches| caches.stats())
+            .unwrap_or_default()
+    }
+
+    pub fn get_branch_stats(&self) -> BranchStats {
+        self.branches
+    }
+
+    /// How well each branch and jump was predicted, by address
+    pub fn get_branch_pcs(&self) -> BTreeMap<u64, BranchPcStats> {
+        self.branch_pcs.clone()
+    }
+
+    /// Whether the last instruction issued was a branch predicted taken whose target wasn't
+    /// in the BTB, so nothing else can be issued until it's decoded
+    pub fn take_bubble(&mut self) -> bool {
+        std::mem::take(&mut self.bubble)
+    }
+
+    /// If instructions were squashed in the last cycle, where issuing must restart from
+    pub fn take_redirect(&mut self) -> Option<Redirect> {
+        self.redirect.take()
     }
 
     pub fn new(config: &Config) -> Self {
@@ -55,27 +245,19 @@ impl ReorderBuffer {
         let mut entries = Vec::with_capacity(size);
         entries.resize_with(size, || None);
 
-        let mut available_reservation_stations = BTreeMap::new();
-        available_reservation_stations
-            .insert(FunctionalUnit::ALU, config.int_buffer_entries as usize);
-        available_reservation_stations.insert(
-            FunctionalUnit::FPUAdd,
-            config.fp_add_buffer_entries as usize,
-        );
-        available_reservation_stations.insert(
-            FunctionalUnit::FPUMul,
-            config.fp_mul_buffer_entries as usize,
-        );
-        available_reservation_stations.insert(
-            FunctionalUnit::EffectAddr,
-            config.eff_addr_buffer_entries as usize,
-        );
+        let available_reservation_stations = FunctionalUnit::ALL
+            .iter()
+            .map(|unit| (*unit, config.reservation_stations(*unit) as usize))
+            .collect();
 
         Self {
             register_mapping: BTreeMap::new(),
-            addresses_loaded: BTreeSet::new(),
-            addresses_stored: BTreeSet::new(),
+            lsq: LoadStoreQueue::new(config),
             available_reservation_stations,
+            busy_units: FunctionalUnit::ALL
+                .iter()
+                .map(|unit| (*unit, vec![0; config.functional_units(*unit) as usize]))
+                .collect(),
 
             entries,
             issue_count: 0,
@@ -85,10 +267,23 @@ impl ReorderBuffer {
             entries_used: 0,
             entries_committed: 0,
 
-            reorder_buffer_delays: 0,
-            reservation_station_delays: 0,
-            data_memory_conflict_delays: 0,
-            true_dependence_delays: 0
+            delays: Delays::default(),
+            memory: MemoryStats::default(),
+            forwarded: Vec::new(),
+            caches: MemoryHierarchy::new(config),
+            cache_accesses: Vec::new(),
+
+            predictor: predictor(config),
+            btb: BranchTargetBuffer::new(
+                config.btb_entries as usize,
+                config.btb_associativity as usize,
+            ),
+            ras: ReturnAddressStack::new(config.ras_entries as usize),
+            ras_checkpoints: BTreeMap::new(),
+            branches: BranchStats::default(),
+            branch_pcs: BTreeMap::new(),
+            redirect: None,
+            bubble: false,
         }
     }
 
@@ -97,17 +292,29 @@ impl ReorderBuffer {
     pub fn get_stages(&self) -> Vec<(usize, RiscVOp, Stage)> {
         let mut result = Vec::new();
         for i in self.tail..self.tail + self.entries_used {
-            if let Some((issued, op, s)) = &self.entries[i % self.size] {
-                result.push((*issued, *op, *s));
+            if let Some(entry) = &self.entries[i % self.size] {
+                result.push((entry.tag, entry.op, entry.stage));
             }
         }
         result
     }
 
-    pub fn add(&mut self, op: RiscVOp) -> Result<(), ()> {
+    /// Count issue slots which went unused this cycle, because of a stall
+    pub fn stall_issue(&mut self, stall: IssueStall, slots: u64) {
+        match stall {
+            IssueStall::ReorderBuffer => self.delays.reorder_buffer += slots,
+            IssueStall::ReservationStation => self.delays.reservation_station += slots,
+            IssueStall::LoadStoreQueue => self.delays.load_store_queue += slots,
+            IssueStall::MemoryReplay => self.delays.memory_replay += slots,
+            IssueStall::BranchMispredict => self.delays.branch_mispredict += slots,
+            IssueStall::FrontEndRedirect => self.delays.front_end_redirect += slots,
+        }
+    }
+
+    pub fn add(&mut self, instruction: Instruction) -> Result<(), IssueStall> {
+        let op = instruction.op;
         if self.entries_used >= self.size {
-            self.reorder_buffer_delays += 1;
-            return Err(());
+            return Err(IssueStall::ReorderBuffer);
         }
 
         // Check if the reservation station is available
@@ -116,70 +323,63 @@ impl ReorderBuffer {
             .get_mut(&op.functional_unit())
         {
             if *available == 0 {
-                self.reservation_station_delays += 1;
-                return Err(());
+                return Err(IssueStall::ReservationStation);
             }
         } else {
-            self.reservation_station_delays += 1;
-            return Err(());
+            return Err(IssueStall::ReservationStation);
         }
 
-        if let Some(addr) = op.addr() {
-            if self.addresses_loaded.contains(&addr) {
-                self.data_memory_conflict_delays += 1;
-                return Err(());
-            }
-            if self.addresses_stored.contains(&addr) {
-                self.data_memory_conflict_delays += 1;
-                return Err(());
-            }
+        if op.is_data_transfer() && self.lsq.is_full() {
+            return Err(IssueStall::LoadStoreQueue);
         }
 
-
-        // // Check if any of the source registers are in the register mapping
-        // if let Some(src1) = op.src1().dep_reg() {
-        //     // Check if the source register is the destination of this instruction
-        //     if let Some(dst) = op.dst() {
-        //         if src1 != dst.as_reg() && self.register_mapping.contains_key(&src1) {
-        //             self.true_dependence_delays += 1;
-        //             return Err(());
-        //         }
-        //     // } else {
-        //     //     if self.register_mapping.contains_key(&src1) {
-        //     //         self.true_dependence_delays += 1;
-        //     //         return Err(());
-        //     //     }
-        //     }
-        // }
-
-        // if let Some(src2) = op.src2().dep_reg() {
-        //     // Check if the source register is the destination of this instruction
-        //     if let Some(dst) = op.dst() {
-        //         if src2 != dst.as_reg() && self.register_mapping.contains_key(&src2) {
-        //             self.true_dependence_delays += 1;
-        //             return Err(());
-        //         }
-        //     // } else {
-        //     //     if self.register_mapping.contains_key(&src2) {
-        //     //         self.true_dependence_delays += 1;
-        //     //         return Err(());
-        //     //     }
-        //     }
-        // }
-
         // Get the reservation station for the op
         debug!("Adding {} to the reservation station", op);
         self.available_reservation_stations
             .entry(op.functional_unit())
             .and_modify(|e| *e -= 1);
 
-        // Add the register mapping
-        if let Some(dst) = op.dst() {
+        // Read the source operands, or the tags of the instructions producing them,
+        // before renaming the destination, so `add x1,x1,x2` waits for the old x1
+        let mut waiting_for = [None; MAX_OPERANDS];
+        for (tag, src) in waiting_for.iter_mut().zip(op.sources().iter()) {
+            *tag = src
+                .dep_reg()
+                .and_then(|reg| self.register_mapping.get(&reg).copied());
+        }
+        let mut entry = Entry {
+            tag: self.issue_count,
+            pc: instruction.pc,
+            op,
+            stage: Stage::Issue,
+            waiting_for,
+            next_pc: instruction.next_pc,
+            mispredicted: false,
+            forwarded: false,
+            served_by: None,
+            btb_hit: None,
+            ras_hit: None,
+        };
+        // Predict branches, and remember which are wrong so they can be squashed when they resolve
+        if op.is_branch() || op.is_jump() {
+            self.ras_checkpoints.insert(entry.tag, self.ras.clone());
+            self.predict(&mut entry);
+        }
+
+        if let Some(addr) = op.addr() {
+            self.lsq.push(entry.tag, entry.pc, op.is_store(), addr);
+        }
+
+        // Add the register mapping. x0 is always zero, so nothing waits for it.
+        for dst in op
+            .destinations()
+            .iter()
+            .filter(|dst| dst.as_reg() != Register::GP(0))
+        {
             debug!("Adding {} to the register mapping", dst);
-            let dst_reg = dst.as_reg();
-            self.register_mapping.insert(dst_reg, self.head as u64);
+            self.register_mapping.insert(dst.as_reg(), entry.tag);
         }
-        self.entries[self.head] = Some((self.issue_count, op, Stage::Issue));
+        self.entries[self.head] = Some(entry);
         self.head = self.head.wrapping_add(1) % self.size;
         self.entries_used += 1;
         self.issue_count += 1;
@@ -189,9 +389,9 @@ impl ReorderBuffer {
     pub fn get_all_in_stage(&self, stage: Stage) -> Vec<(usize, RiscVOp)> {
         let mut result = Vec::new();
         for i in self.head..self.head + self.size {
-            if let Some((_, op, s)) = &self.entries[i % self.size] {
-                if s == &stage {
-                    result.push((i % self.size, *op));
+            if let Some(entry) = &self.entries[i % self.size] {
+                if entry.stage == stage {
+                    result.push((i % self.size, entry.op));
                 }
             }
         }
@@ -202,9 +402,22 @@ impl ReorderBuffer {
         let mut result = Vec::new();
 
         for i in self.head..self.head + self.size {
-            if let Some((_, op, s)) = &self.entries[i % self.size] {
-                if let Stage::Execute(_) = s {
-                    result.push((i % self.size, *op));
+            if let Some(entry) = &self.entries[i % self.size] {
+                if matches!(entry.stage, Stage::Execute(_)) {
+                    result.push((i % self.size, entry.op));
+                }
+            }
+        }
+        result
+    }
+
+    pub fn get_all_in_mem(&self) -> Vec<(usize, RiscVOp)> {
+        let mut result = Vec::new();
+
+        for i in self.head..self.head + self.size {
+            if let Some(entry) = &self.entries[i % self.size] {
+                if matches!(entry.stage, Stage::MemAccess(_)) {
+                    result.push((i % self.size, entry.op));
                 }
             }
         }
@@ -212,25 +425,13 @@ impl ReorderBuffer {
     }
 
     pub fn write_to_cdb(&mut self, i: usize) -> bool {
-        if let Some((_, _op, s)) = &self.entries[i] {
-            if s == &Stage::WriteBack {
+        if let Some(entry) = &self.entries[i] {
+            if entry.stage == Stage::WriteBack {
                 // Confirm all the operations before this one are committed
-                let mut all_committed = true;
-                for j in self.tail..self.tail + self.size {
-                    if i == j {
-                        break;
-                    }
-                    if let Some((_, _, s)) = &self.entries[j % self.size] {
-                        if s != &Stage::Commit {
-                            all_committed = false;
-                            break;
-                        }
-                    }
-                }
-                if all_committed {
-                    self.entries[i].as_mut().unwrap().2 = Stage::Commit;
+                if self.older_committed(i) {
+                    self.entries[i].as_mut().unwrap().stage = Stage::Commit;
                 } else {
-                    self.entries[i].as_mut().unwrap().2 = Stage::WaitingToCommit;
+                    self.entries[i].as_mut().unwrap().stage = Stage::WaitingToCommit;
                 }
                 // Free up the reservation station
                 return true;
@@ -263,20 +464,256 @@ impl ReorderBuffer {
         false
     }
 
+    /// Predict where a branch or jump goes, from the direction predictor and either the
+    /// return address stack or the BTB, and whether that's wrong
+    fn predict(&mut self, entry: &mut Entry) {
+        let op = entry.op;
+        let fallthrough = entry.pc.wrapping_add(4);
+        let taken = !op.is_branch() || self.predictor.predict(entry.pc);
+        // Where a taken branch really went, if the trace says. Traces without addresses
+        // only say which way it went, and leave `next_pc` just after it.
+        let actual_target = Some(entry.next_pc)
+            .filter(|next_pc| op.taken() == Some(true) && *next_pc != fallthrough);
+
+        let return_address = if op.is_return() { self.ras.pop() } else { None };
+        if op.is_call() {
+            self.ras.push(fallthrough);
+        }
+
+        // The address issuing continues from, or `None` for the target of a direct branch,
+        // which is known once it's decoded
+        let predicted = if let Some(address) = return_address {
+            entry.ras_hit = Some(actual_target.is_none_or(|actual| actual == address));
+            Some(address)
+        } else {
+            let target = self.btb.lookup(entry.pc);
+            entry.btb_hit = Some(target.is_some());
+            match target {
+                _ if !taken => Some(fallthrough),
+                Some(target) => Some(target),
+                None if op.is_indirect() => Some(fallthrough),
+                None => {
+                    self.bubble = true;
+                    None
+                }
+            }
+        };
+
+        // The direction is wrong if issuing carries on from the next instruction when the
+        // branch was taken, or the other way round. If both say taken, the target can
+        // only be wrong if the trace says where it went.
+        let predicted_taken = predicted != Some(fallthrough);
+        entry.mispredicted = match op.taken() {
+            None => false,
+            Some(taken) if taken != predicted_taken => true,
+            Some(false) => false,
+            Some(true) => match (predicted, actual_target) {
+                (Some(predicted), Some(actual)) => predicted != actual,
+                _ => false,
+            },
+        };
+    }
+
+    /// Train the predictor and the BTB with the outcome of the branch in entry i.
+    /// Returns whether it was mispredicted.
+    fn resolve_branch(&mut self, i: usize) -> bool {
+        let entry = self.entries[i].unwrap();
+        match entry.op.taken() {
+            Some(taken) if entry.op.is_branch() => self.predictor.update(entry.pc, taken),
+            _ => {}
+        }
+        // Only a target the trace gives is worth remembering
+        if entry.op.taken() == Some(true) && entry.next_pc != entry.pc.wrapping_add(4) {
+            self.btb.insert(entry.pc, entry.next_pc);
+        }
+        entry.mispredicted
+    }
+
+    /// Count how well a committed branch or jump was predicted
+    fn retire_branch(&mut self, entry: &Entry) {
+        self.ras_checkpoints.remove(&entry.tag);
+
+        let stats = self.branch_pcs.entry(entry.pc).or_default();
+        stats.executed += 1;
+        self.branches.branches += 1;
+        if entry.mispredicted {
+            stats.mispredictions += 1;
+            self.branches.mispredictions += 1;
+        }
+        match entry.btb_hit {
+            Some(true) => {
+                stats.btb_hits += 1;
+                self.branches.btb_hits += 1;
+            }
+            Some(false) => {
+                stats.btb_misses += 1;
+                self.branches.btb_misses += 1;
+            }
+            None => {}
+        }
+        match entry.ras_hit {
+            Some(true) => self.branches.ras_hits += 1,
+            Some(false) => self.branches.ras_misses += 1,
+            None => {}
+        }
+    }
+
+    /// Count a committed load or store, which can leave the load/store queue.
+    /// Stores write the caches as they commit.
+    fn retire_data_transfer(&mut self, entry: &Entry) {
+        self.lsq.remove(entry.tag);
+        if entry.op.is_store() {
+            self.memory.stores += 1;
+            if let (Some(caches), Some(addr)) = (&mut self.caches, entry.op.addr()) {
+                self.cache_accesses.push((entry.tag, caches.store(addr)));
+            }
+        } else {
+            self.memory.loads += 1;
+        }
+        if entry.forwarded {
+            self.memory.forwarded_loads += 1;
+        }
+    }
+
+    /// Throw away every instruction issued after the one with this tag,
+    /// and restart issuing after it
+    fn squash_after(&mut self, tag: usize, reason: IssueStall) {
+        debug!(
+            "Squashing the instructions after tag {} ({:?})",
+            tag, reason
+        );
+        let i = (self.tail..self.tail + self.entries_used)
+            .map(|i| i % self.size)
+            .find(|i| {
+                self.entries[*i]
+                    .as_ref()
+                    .is_some_and(|entry| entry.tag == tag)
+            })
+            .unwrap();
+
+        let mut j = (i + 1) % self.size;
+        while j != self.head {
+            if let Some(entry) = self.entries[j].take() {
+                // Instructions free their reservation station when they finish executing
+                if matches!(entry.stage, Stage::Issue | Stage::Execute(_)) {
+                    self.release_unit(&entry.op);
+                }
+                self.entries_used -= 1;
+                match reason {
+                    IssueStall::MemoryReplay => self.memory.replayed += 1,
+                    _ => self.branches.squashed += 1,
+                }
+            }
+            j = (j + 1) % self.size;
+        }
+        self.head = (i + 1) % self.size;
+        self.issue_count = tag + 1;
+
+        // The stack as it was before the oldest branch thrown away changed it
+        if let Some((_, ras)) = self.ras_checkpoints.range(tag + 1..).next() {
+            self.ras = ras.clone();
+        }
+        self.ras_checkpoints
+            .retain(|checkpoint, _| *checkpoint <= tag);
+        self.lsq.squash_after(tag);
+
+        // Rebuild the register mapping from the instructions left
+        self.register_mapping.clear();
+        for j in self.tail..self.tail + self.entries_used {
+            let entry = self.entries[j % self.size].as_ref().unwrap();
+            for dst in entry
+                .op
+                .destinations()
+                .iter()
+                .filter(|dst| dst.as_reg() != Register::GP(0))
+            {
+                if matches!(entry.stage, Stage::WaitingToCommit | Stage::Commit) {
+                    // The result has already been broadcast
+                    self.register_mapping.remove(&dst.as_reg());
+                } else {
+                    self.register_mapping.insert(dst.as_reg(), entry.tag);
+                }
+            }
+        }
+        if reason == IssueStall::MemoryReplay {
+            self.memory.replays += 1;
+        }
+        self.redirect = Some(Redirect {
+            next: tag + 1,
+            stall: reason,
+        });
+    }
+
+    /// Broadcast the result of entry i on a common data bus, waking up the
+    /// reservation stations waiting for it
+    fn broadcast(&mut self, i: usize, op: &RiscVOp) {
+        let tag = self.entries[i].as_ref().unwrap().tag;
+        for entry in self.entries.iter_mut().flatten() {
+            for waiting_for in entry.waiting_for.iter_mut() {
+                if *waiting_for == Some(tag) {
+                    *waiting_for = None;
+                }
+            }
+        }
+
+        // Later reads of the register get the committed value, unless a younger
+        // instruction has renamed it again
+        for dst in op.destinations().iter() {
+            let dst_reg = dst.as_reg();
+            if self.register_mapping.get(&dst_reg) == Some(&tag) {
+                debug!("Removing {} from the register mapping", dst);
+                self.register_mapping.remove(&dst_reg);
+            }
+        }
+    }
+
+    /// Commit the instructions waiting to commit, in order, while there's room
+    fn commit_waiting(&mut self, commits: &mut u64, config: &Config) {
+        self.get_all_in_stage(Stage::WaitingToCommit)
+            .iter()
+            .for_each(|(i, _op)| {
+                // Check if all the instructions before this one are committed
+                if self.older_committed(*i) && *commits < config.commit_width {
+                    self.entries[*i].as_mut().unwrap().stage = Stage::Commit;
+                    *commits += 1;
+                }
+            });
+    }
+
+    /// Have all the instructions issued before entry i been committed?
+    fn older_committed(&self, i: usize) -> bool {
+        (self.tail..self.tail + self.size)
+            .map(|j| j % self.size)
+            .take_while(|j| *j != i)
+            .all(|j| match &self.entries[j] {
+                Some(entry) => entry.stage == Stage::Commit,
+                None => true,
+            })
+    }
+
+    /// Free the reservation station held by an instruction that has finished executing
+    fn release_unit(&mut self, op: &RiscVOp) {
+        trace!("Freeing up reservation station for {}", op);
+        self.available_reservation_stations
+            .entry(op.functional_unit())
+            .and_modify(|e| *e += 1);
+    }
+
     pub fn tick(&mut self, config: &Config) {
-        let mut already_committed = false;
+        let mut commits = 0;
 
         // Check the commit stage
         // self.available_reservation_stations.entry(op.functional_unit()).and_modify(|e| *e += 1);
         self.get_all_in_stage(Stage::Commit)
             .iter()
             .for_each(|(i, op)| {
-                if let Some(addr) = op.addr() {
-                    if op.is_load() {
-                        self.addresses_loaded.remove(&addr);
-                    } else {
-                        self.addresses_stored.remove(&addr);
-                    }
+                if op.is_branch() || op.is_jump() {
+                    let entry = self.entries[*i].unwrap();
+                    self.retire_branch(&entry);
+                }
+                if op.is_data_transfer() {
+                    let entry = self.entries[*i].unwrap();
+                    self.retire_data_transfer(&entry);
                 }
                 self.entries_committed += 1;
                 self.entries[*i] = None;
@@ -285,231 +722,199 @@ impl ReorderBuffer {
             });
 
         // Check the commit stage
-        self.get_all_in_stage(Stage::WaitingToCommit)
-            .iter()
-            .for_each(|(i, _op)| {
-                // Check if all the instructions before this one are committed
-                let mut all_committed = true;
-                for j in self.tail..self.tail + self.size {
-                    if *i == j {
-                        break;
-                    }
-                    if let Some((_, _, s)) = &self.entries[j % self.size] {
-                        if s != &Stage::Commit {
-                            all_committed = false;
-                            break;
-                        }
-                    }
-                }
-                if all_committed && !already_committed {
-                    // if let Some(addr) = op.addr() {
-                    //     self.addresses_in_use.remove(&addr);
-                    // }
-                    self.entries[*i].as_mut().unwrap().2 = Stage::Commit;
-                    already_committed = true;
-                } else {
-                }
-            });
+        self.commit_waiting(&mut commits, config);
 
         // Check the WB stage
-        let mut removed_registers = Vec::new();
-        let mut wrote_to_cdb = false;
+        // Results are broadcast oldest first, one per common data bus
+        let mut broadcasts = 0;
 
         self.get_all_in_stage(Stage::WriteBack)
             .iter()
             .for_each(|(i, op)| {
-                if wrote_to_cdb {
+                if broadcasts >= config.common_data_buses {
+                    self.delays.cdb_conflict += 1;
                     return;
                 }
                 // Check if all the instructions before this one are committed
-                let mut all_committed = true;
-                for j in self.tail..self.tail + self.size {
-                    if *i == j {
-                        break;
-                    }
-                    if let Some((_, _, s)) = &self.entries[j % self.size] {
-                        if s != &Stage::Commit {
-                            all_committed = false;
-                            break;
-                        }
-                    }
-                }
-                if all_committed && !already_committed {
-                    // if let Some(addr) = op.addr() {
-                    //     self.addresses_in_use.remove(&addr);
-                    // }
-                    self.entries[*i].as_mut().unwrap().2 = Stage::Commit;
-                    already_committed = true;
+                if self.older_committed(*i) && commits < config.commit_width {
+                    self.entries[*i].as_mut().unwrap().stage = Stage::Commit;
+                    commits += 1;
                 } else {
-                    self.entries[*i].as_mut().unwrap().2 = Stage::WaitingToCommit;
+                    self.entries[*i].as_mut().unwrap().stage = Stage::WaitingToCommit;
                 }
 
-                if let Some(dst) = op.dst() {
-                    debug!("Removing {} from the register mapping", dst);
-                    let dst_reg = dst.as_reg();
-                    self.register_mapping.remove(&dst_reg);
-                    removed_registers.push(dst_reg);
-                }
-                wrote_to_cdb = true;
+                self.broadcast(*i, op);
+                broadcasts += 1;
             });
 
+        // Instructions waiting behind one which just committed can commit with it
+        self.commit_waiting(&mut commits, config);
+
         // Check the MEM stage
-        // Get the first issued instruction thats in the MEM stage.
-        // If it's a load, check if the address is ready.
-        // If it is, then write the result to the CDB.
+        // A load whose address matches an older store in flight takes the store's data
+        // once it's ready, and otherwise reads memory, one load per cycle. The
+        // disambiguation policy decides which older stores it has to wait for.
+        // With caches, a load which misses has to get an MSHR before it can start
+        // fetching its line, but other loads can go ahead of it in the meantime.
         let mut already_accessed = false;
-        self.get_all_in_stage(Stage::MemAccess)
-            .iter()
-            .for_each(|(i, op)| {
-                if let Some(addr) = op.addr() {
-                    if self.addresses_stored.contains(&addr) {
-                        self.data_memory_conflict_delays += 1;
-                        return;
-                    }
-                    if op.is_load() {
-                        self.addresses_loaded.insert(addr);
-                    } else {
-                        self.addresses_stored.insert(addr);
+        if let Some(caches) = &mut self.caches {
+            caches.tick();
+        }
+        self.get_all_in_mem().iter().for_each(|(i, op)| {
+            let entry = self.entries[*i].as_mut().unwrap();
+            match self.lsq.load_source(entry.tag) {
+                LoadSource::Memory => {
+                    if let Some(caches) = &mut self.caches {
+                        // Look the line up when the load first gets to the cache
+                        if entry.served_by.is_none() {
+                            match caches.load(op.addr().unwrap_or_default()) {
+                                Ok(access) => {
+                                    entry.stage = Stage::MemAccess(access.latency);
+                                    entry.served_by = Some(access.served_by);
+                                    self.cache_accesses.push((entry.tag, access.served_by));
+                                }
+                                Err(level) => {
+                                    debug!(
+                                        "The load with tag {} is waiting for an L{} MSHR",
+                                        entry.tag,
+                                        level + 1
+                                    );
+                                    self.delays.mshr_full += 1;
+                                    return;
+                                }
+                            }
+                        }
+                        if let (Stage::MemAccess(cycles), Some(served_by)) =
+                            (entry.stage, entry.served_by)
+                        {
+                            if !served_by.is_l1_hit() && cycles > caches.hit_latency() {
+                                self.delays.cache_miss += 1;
+                            }
+                        }
                     }
                 }
-                if !already_accessed {
-                    self.entries[*i].as_mut().unwrap().2 = Stage::WriteBack;
-                    already_accessed = true;
+                LoadSource::Forward(store) => {
+                    debug!(
+                        "Forwarding tag {} to the load with tag {}",
+                        store, entry.tag
+                    );
+                    entry.stage = Stage::WriteBack;
+                    entry.forwarded = true;
+                    self.forwarded.push(entry.tag);
+                    return;
                 }
-            });
-
-        self.get_all_in_stage(Stage::WriteBack)
-            .iter()
-            .for_each(|(_i, op)| {
-                // Only write to the CDB if we haven't already
-                if wrote_to_cdb {
+                LoadSource::Wait(_) => {
+                    self.delays.data_memory_conflict += 1;
                     return;
                 }
-
-                // Write the result to the CDB
-                if op.addr().is_none() {
-                    if let Some(dst) = op.dst() {
-                        debug!("Removing {} from the register mapping", dst);
-                        let dst_reg = dst.as_reg();
-                        self.register_mapping.remove(&dst_reg);
-                        removed_registers.push(dst_reg);
-                    }
+            }
+            if let Stage::MemAccess(cycles) = &mut self.entries[*i].as_mut().unwrap().stage {
+                if *cycles > 1 {
+                    *cycles -= 1;
+                } else if !already_accessed {
+                    self.entries[*i].as_mut().unwrap().stage = Stage::WriteBack;
+                    already_accessed = true;
                 }
-            });
+            }
+        });
 
         // Check the EX stage
         // Go through and decrement the cycles left to execute.
         // If it's 0, then move it to the MEM stage.
-        let _wrote_back = false;
+        // The tag of the last instruction to keep, if a mispredicted branch or a load which
+        // read memory out of order means the instructions after it have to be thrown away
+        let mut squash: Option<(usize, IssueStall)> = None;
+        let mut squash_after = |tag: usize, reason: IssueStall| {
+            // Only the oldest squash matters, since it throws away any younger ones
+            if squash.is_none_or(|(older, _)| tag < older) {
+                squash = Some((tag, reason));
+            }
+        };
+        for busy in self.busy_units.values_mut().flatten() {
+            *busy = busy.saturating_sub(1);
+        }
         self.get_all_in_ex().iter().for_each(|(i, op)| {
-            if let Stage::Execute(cycles) = &mut self.entries[*i].as_mut().unwrap().2 {
+            if let Stage::Execute(cycles) = &mut self.entries[*i].as_mut().unwrap().stage {
                 if *cycles > 0 {
                     *cycles -= 1;
                 }
-                if *cycles <= 0 {
-                    // if *cycles <= 0 && !wrote_back {
-                    // wrote_back = true;
+                if *cycles == 0 {
+                    let tag = self.entries[*i].as_ref().unwrap().tag;
+                    if op.is_data_transfer() {
+                        let violations = self.lsq.executed(tag);
+                        if let Some(load) = violations.first() {
+                            debug!("The store with tag {tag} replays the load with tag {load}");
+                            self.memory.violations += violations.len() as u64;
+                            squash_after(load - 1, IssueStall::MemoryReplay);
+                        }
+                    }
                     if op.accesses_memory() {
-                        self.entries[*i].as_mut().unwrap().2 = Stage::MemAccess;
-                        trace!("Freeing up reservation station for {}", op);
-                        self.available_reservation_stations
-                            .entry(op.functional_unit())
-                            .and_modify(|e| *e += 1);
+                        self.entries[*i].as_mut().unwrap().stage =
+                            Stage::MemAccess(config.memory_latencies.get(op));
+                        self.release_unit(op);
                     } else if op.writes_back() {
-                        if !wrote_to_cdb {
-                            self.entries[*i].as_mut().unwrap().2 = Stage::WriteBack;
-                            trace!("Freeing up reservation station for {}", op);
-                            self.available_reservation_stations
-                                .entry(op.functional_unit())
-                                .and_modify(|e| *e += 1);
+                        if broadcasts < config.common_data_buses {
+                            self.entries[*i].as_mut().unwrap().stage = Stage::WriteBack;
+                            self.release_unit(op);
+                            // Jumps resolve here, since they write back the return address
+                            if op.is_jump() && self.resolve_branch(*i) {
+                                squash_after(tag, IssueStall::BranchMispredict);
+                            }
+                        } else {
+                            self.delays.cdb_conflict += 1;
                         }
                     } else {
                         // Confirm all the operations before this one are committed
-                        let mut all_committed = true;
-                        for j in self.tail..self.tail + self.size {
-                            if *i == j {
-                                break;
-                            }
-                            if let Some((_, _op, s)) = &self.entries[j % self.size] {
-                                if s != &Stage::Commit {
-                                    all_committed = false;
-                                    break;
-                                }
-                            }
-                        }
-                        if all_committed && !already_committed {
-                            // if let Some(addr) = op.addr() {
-                            //     self.addresses_in_use.remove(&addr);
-                            // }
-                            self.entries[*i].as_mut().unwrap().2 = Stage::Commit;
-                            already_committed = true;
+                        if self.older_committed(*i) && commits < config.commit_width {
+                            self.entries[*i].as_mut().unwrap().stage = Stage::Commit;
+                            commits += 1;
                         } else {
-                            self.entries[*i].as_mut().unwrap().2 = Stage::WaitingToCommit;
+                            self.entries[*i].as_mut().unwrap().stage = Stage::WaitingToCommit;
+                        }
+                        self.release_unit(op);
+                        if op.is_branch() && self.resolve_branch(*i) {
+                            squash_after(tag, IssueStall::BranchMispredict);
                         }
-                        trace!("Freeing up reservation station for {}", op);
-                        self.available_reservation_stations
-                            .entry(op.functional_unit())
-                            .and_modify(|e| *e += 1);
                     }
                 }
             }
         });
+        if let Some((tag, reason)) = squash {
+            self.squash_after(tag, reason);
+        }
+        self.commit_waiting(&mut commits, config);
+
         // Check the issue stage
+        let mut started_units = BTreeMap::new();
         self.get_all_in_stage(Stage::Issue)
             .iter()
             .for_each(|(i, op)| {
-                // Check if any of the source registers are in the register mapping
-                if let Some(src1) = op.src1().dep_reg() {
-                    // Check if the source register is the destination of this instruction
-                    if let Some(dst) = op.dst() {
-                        if src1 != dst.as_reg() && self.register_mapping.contains_key(&src1) {
-                            self.true_dependence_delays += 1;
-                            return;
-                        }
-                    }
-                }
-
-                if let Some(src2) = op.src2().dep_reg() {
-                    // Check if the source register is the destination of this instruction
-                    if let Some(dst) = op.dst() {
-                        if src2 != dst.as_reg() && self.register_mapping.contains_key(&src2) {
-                            self.true_dependence_delays += 1;
-                            return;
-                        }
-                    }
+                // Wait for the results of the instructions producing the operands
+                let entry = self.entries[*i].as_ref().unwrap();
+                if entry.waiting_for.iter().any(Option::is_some) {
+                    self.delays.true_dependence += 1;
+                    return;
                 }
 
-                // Move the instruction to the EX stage
-                self.entries[*i].as_mut().unwrap().2 = if op.is_fp_div() {
-                    Stage::Execute(config.fp_div_buffer_latency)
-                } else if op.is_fp_mul() {
-                    Stage::Execute(config.fp_mul_buffer_latency)
-                } else if op.is_fp_add() {
-                    Stage::Execute(config.fp_add_buffer_latency)
-                } else if op.is_branch() {
-                    Stage::Execute(1)
-                } else if op.is_alu() {
-                    Stage::Execute(1)
+                // Wait for a free execution unit
+                let unit = op.functional_unit();
+                let units = self.busy_units.entry(unit).or_default();
+                let started = started_units.entry(unit).or_insert(0);
+                if let Some(busy) = units.iter_mut().find(|busy| **busy == 0) {
+                    *busy = config.initiation_interval(op);
+                    *started += 1;
+                } else if *started >= units.len() {
+                    // Every unit started an instruction this cycle
+                    self.delays.functional_unit += 1;
+                    return;
                 } else {
-                    Stage::Execute(1)
-                }
-            });
-
-        self.get_all_in_stage(Stage::WriteBack)
-            .iter()
-            .for_each(|(_i, op)| {
-                // Only write to the CDB if we haven't already
-                if wrote_to_cdb {
+                    // A unit is still busy with an earlier instruction
+                    self.delays.unpipelined_unit += 1;
                     return;
                 }
 
-                // Write the result to the CDB
-                if let Some(dst) = op.dst() {
-                    debug!("Removing {} from the register mapping", dst);
-                    let dst_reg = dst.as_reg();
-                    self.register_mapping.remove(&dst_reg);
-                    removed_registers.push(dst_reg);
-                }
+                // Move the instruction to the EX stage
+                self.entries[*i].as_mut().unwrap().stage = Stage::Execute(config.latencies.get(op))
             });
     }
 }
@@ -527,14 +932,7 @@ impl Display for ReorderBuffer {
             writeln!(f, "    {:?} -> {}", fu, i)?;
         }
 
-        writeln!(f, "  Addresses stored:")?;
-        for addr in &self.addresses_stored {
-            writeln!(f, "    {}", addr)?;
-        }
-        writeln!(f, "  Addresses loaded:")?;
-        for addr in &self.addresses_loaded {
-            writeln!(f, "    {}", addr)?;
-        }
+        writeln!(f, "  Load/store queue entries: {}", self.lsq.len())?;
 
         writeln!(f, "  Head: {}", self.head)?;
         writeln!(f, "  Tail: {}", self.tail)?;
@@ -542,8 +940,16 @@ impl Display for ReorderBuffer {
         writeln!(f, "  Entries committed: {}", self.entries_committed)?;
         writeln!(f, "  Entries:")?;
         for (i, entry) in self.entries.iter().enumerate() {
-            if let Some((issued, op, s)) = entry {
-                writeln!(f, "    #{}) {} ({:?}) issued on {issued}", i, op, s)?;
+            if let Some(entry) = entry {
+                write!(
+                    f,
+                    "    #{}) {} ({:?}) tag {}",
+                    i, entry.op, entry.stage, entry.tag
+                )?;
+                for q in entry.waiting_for.iter().flatten() {
+                    write!(f, ", waiting for tag {q}")?;
+                }
+                writeln!(f)?;
             } else {
                 writeln!(f, "    #{}) None", i)?;
             }
@@ -551,3 +957,200 @@ impl Display for ReorderBuffer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Issue every instruction of a trace into a reorder buffer big enough for them
+    fn issue(trace: &str) -> ReorderBuffer {
+        let config = Config {
+            reorder_buffer_entries: 8,
+            fp_add_buffer_entries: 8,
+            fp_mul_buffer_entries: 8,
+            int_buffer_entries: 8,
+            ..Config::default()
+        };
+        let mut reorder_buffer = ReorderBuffer::new(&config);
+        for instruction in parse_trace(trace).unwrap().instructions {
+            reorder_buffer.add(instruction).unwrap();
+        }
+        reorder_buffer
+    }
+
+    /// The tags each issued instruction is waiting for the results of
+    fn waiting_for(reorder_buffer: &ReorderBuffer) -> Vec<Vec<usize>> {
+        reorder_buffer
+            .entries
+            .iter()
+            .flatten()
+            .map(|entry| entry.waiting_for.iter().flatten().copied().collect())
+            .collect()
+    }
+
+    #[test]
+    fn sources_wait_for_the_latest_writer_of_their_register() {
+        let reorder_buffer = issue(
+            "fdiv.s f1,f2,f3\n\
+             fadd.s f2,f4,f5\n\
+             fadd.s f1,f4,f5\n\
+             fmul.s f6,f1,f2\n\
+             fadd.s f1,f1,f6\n",
+        );
+        // Writing f2, which the divide reads, and writing f1 again, don't wait for it
+        let expected: [&[usize]; 5] = [&[], &[], &[], &[2, 1], &[2, 3]];
+        assert_eq!(waiting_for(&reorder_buffer), expected);
+        assert_eq!(reorder_buffer.register_mapping[&Register::FP(1)], 4);
+
+        // Nothing waits for x0
+        let reorder_buffer = issue("addi x0,x1,1\nadd x2,x0,x0\n");
+        let expected: [&[usize]; 2] = [&[], &[]];
+        assert_eq!(waiting_for(&reorder_buffer), expected);
+    }
+
+    #[test]
+    fn fused_multiply_add_waits_for_all_three_sources() {
+        let reorder_buffer = issue(
+            "fadd.s f1,f0,f0\n\
+             fadd.s f2,f0,f0\n\
+             fadd.s f3,f0,f0\n\
+             fmadd.s f4,f1,f2,f3\n\
+             fsw f4,0(x0)\n",
+        );
+        assert_eq!(waiting_for(&reorder_buffer)[3], [0, 1, 2]);
+        assert_eq!(waiting_for(&reorder_buffer)[4], [3]);
+    }
+
+    #[test]
+    fn squashing_restores_the_register_mapping() {
+        let mut reorder_buffer = issue(
+            "addi x1,x0,1\n\
+             beq x0,x0,Loop:T\n\
+             addi x1,x0,2\n\
+             addi x2,x1,3\n",
+        );
+        assert_eq!(reorder_buffer.register_mapping[&Register::GP(1)], 2);
+        reorder_buffer.squash_after(1, IssueStall::BranchMispredict);
+
+        assert_eq!(reorder_buffer.entries_used, 2);
+        assert_eq!(reorder_buffer.branches.squashed, 2);
+        assert_eq!(reorder_buffer.register_mapping[&Register::GP(1)], 0);
+        assert!(!reorder_buffer
+            .register_mapping
+            .contains_key(&Register::GP(2)));
+        // Issuing starts again from the instruction after the branch, with the next tag
+        reorder_buffer
+            .add(Instruction::new(0, RiscVOp::parse("add x3,x1,x1").unwrap()))
+            .unwrap();
+        assert_eq!(waiting_for(&reorder_buffer)[2], [0, 0]);
+        assert_eq!(reorder_buffer.register_mapping[&Register::GP(3)], 2);
+    }
+
+    /// Simulate a trace with the default configuration, which predicts branches aren't
+    /// taken, and return a branch statistic by name
+    fn branch_stat(trace: &str, name: &str) -> u64 {
+        let instructions = parse_trace(trace).unwrap().instructions;
+        let mut table = TomasuloTable::new();
+        table.run(instructions, &Config::default());
+        table
+            .branch_stats()
+            .into_iter()
+            .find(|(stat, _)| *stat == name)
+            .map(|(_, value)| value)
+            .unwrap()
+    }
+
+    #[test]
+    fn taken_branch_without_pcs_is_mispredicted() {
+        let trace = "addi x1,x0,1\nbeq x0,x0,Loop:T\naddi x2,x0,2\naddi x3,x0,3\n";
+        assert_eq!(branch_stat(trace, "mispredictions"), 1);
+        assert!(branch_stat(trace, "squashed instructions") > 0);
+    }
+
+    #[test]
+    fn not_taken_branch_without_pcs_is_predicted() {
+        let trace = "addi x1,x0,1\nbeq x0,x1,Loop:N\naddi x2,x0,2\naddi x3,x0,3\n";
+        assert_eq!(branch_stat(trace, "mispredictions"), 0);
+        assert_eq!(branch_stat(trace, "squashed instructions"), 0);
+    }
+
+    #[test]
+    fn taken_branch_with_pcs_is_mispredicted() {
+        let trace = "0x0 addi x1,x0,1\n0x4 beq x0,x0,Loop:T\n0x10 addi x2,x0,2\n";
+        assert_eq!(branch_stat(trace, "mispredictions"), 1);
+    }
+
+    #[test]
+    fn repeated_taken_branch_without_pcs_isnt_given_a_target() {
+        // The trace doesn't say where the branch went, so the BTB never learns a target
+        // which would make it look not taken
+        let trace = "beq x0,x0,Loop:T\nbeq x0,x0,Loop:T\nbeq x0,x0,Loop:T\n";
+        assert_eq!(branch_stat(trace, "mispredictions"), 3);
+        assert_eq!(branch_stat(trace, "BTB hits"), 0);
+    }
+
+    #[test]
+    fn indirect_jump_to_a_new_target_is_mispredicted() {
+        // Both BTB misses go to the next instruction, and the hit has the old target
+        let trace = "0x0 jalr x0,0(x1)\n0x40 jalr x0,0(x1)\n0x0 jalr x0,0(x1)\n0x80 addi x1,x1,1\n";
+        assert_eq!(branch_stat(trace, "mispredictions"), 3);
+        assert_eq!(branch_stat(trace, "BTB hits"), 1);
+    }
+
+    /// Simulate a trace with a disambiguation policy, and return its memory statistics
+    fn memory_stats(trace: &str, policy: DisambiguationPolicy) -> MemoryStats {
+        let instructions = parse_trace(trace).unwrap().instructions;
+        let config = Config {
+            disambiguation: policy,
+            ..Config::default()
+        };
+        let mut table = TomasuloTable::new();
+        table.run(instructions, &config);
+        let stat = |name| {
+            table
+                .memory_stats()
+                .into_iter()
+                .find(|(stat, _)| *stat == name)
+                .map(|(_, value)| value)
+                .unwrap()
+        };
+        MemoryStats {
+            forwarded_loads: stat("forwarded loads"),
+            violations: stat("ordering violations"),
+            replays: stat("replays"),
+            ..MemoryStats::default()
+        }
+    }
+
+    #[test]
+    fn loads_take_the_value_of_a_store_waiting_to_commit() {
+        // The store can't commit until the divide does, so the load to the same address
+        // takes its value, while the other load reads memory
+        let trace = "fdiv.s f4,f2,f6\nfsw f8,0(x2):64\nflw f9,0(x2):64\nflw f10,0(x2):128\n";
+        for policy in DisambiguationPolicy::ALL {
+            let stats = memory_stats(trace, policy);
+            assert_eq!(stats.forwarded_loads, 1, "{policy}");
+            assert_eq!(stats.violations, 0, "{policy}");
+        }
+    }
+
+    #[test]
+    fn loads_ahead_of_stores_are_replayed() {
+        // Each store waits for a divide to get its address, and the load after it reads
+        // the same address
+        let iteration = "0x0 fdiv.s f4,f2,f6\n0x4 fcvt.w.s x2,f4\n0x8 fsw f8,0(x2):64\n\
+                         0xc flw f9,0(x3):64\n0x10 bne x1,x0,0x0:T\n";
+        let trace = iteration.repeat(3);
+        // The store sets only let the load go ahead the first time
+        for (policy, violations) in [
+            (DisambiguationPolicy::Conservative, 0),
+            (DisambiguationPolicy::Perfect, 0),
+            (DisambiguationPolicy::Speculative, 3),
+            (DisambiguationPolicy::StoreSets, 1),
+        ] {
+            let stats = memory_stats(&trace, policy);
+            assert_eq!(stats.violations, violations, "{policy}");
+            assert_eq!(stats.replays, violations, "{policy}");
+        }
+    }
+}
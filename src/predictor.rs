@@ -0,0 +1,355 @@
+use super::*;
+use std::fmt::{self, Display, Formatter};
+
+/// The kinds of branch predictor which can be configured
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PredictorKind {
+    /// Always predict that branches aren't taken
+    #[default]
+    NotTaken,
+    /// A table of 2-bit saturating counters, indexed by the branch's PC
+    Bimodal,
+    /// A table of 2-bit saturating counters, indexed by the branch's PC
+    /// XORed with the global branch history
+    GShare,
+    /// Bimodal and gshare predictors, with a table of 2-bit counters
+    /// choosing between them for each branch
+    Tournament,
+}
+
+impl PredictorKind {
+    pub const ALL: [PredictorKind; 4] = [
+        PredictorKind::NotTaken,
+        PredictorKind::Bimodal,
+        PredictorKind::GShare,
+        PredictorKind::Tournament,
+    ];
+
+    /// The name used in configuration files
+    pub const fn name(&self) -> &'static str {
+        match self {
+            PredictorKind::NotTaken => "not_taken",
+            PredictorKind::Bimodal => "bimodal",
+            PredictorKind::GShare => "gshare",
+            PredictorKind::Tournament => "tournament",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+impl Display for PredictorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Predicts whether branches are taken when they're issued,
+/// and learns from their outcomes when they resolve
+pub trait BranchPredictor {
+    fn predict(&self, pc: u64) -> bool;
+    fn update(&mut self, pc: u64, taken: bool);
+}
+
+/// Build the predictor described by the configuration
+pub fn predictor(config: &Config) -> Box<dyn BranchPredictor> {
+    let entries = config.predictor_entries as usize;
+    let history_bits = config.history_bits as u32;
+    match config.predictor {
+        PredictorKind::NotTaken => Box::new(NotTaken),
+        PredictorKind::Bimodal => Box::new(Bimodal::new(entries)),
+        PredictorKind::GShare => Box::new(GShare::new(entries, history_bits)),
+        PredictorKind::Tournament => Box::new(Tournament::new(entries, history_bits)),
+    }
+}
+
+pub struct NotTaken;
+
+impl BranchPredictor for NotTaken {
+    fn predict(&self, _pc: u64) -> bool {
+        false
+    }
+
+    fn update(&mut self, _pc: u64, _taken: bool) {}
+}
+
+/// A table of 2-bit saturating counters, which start weakly not taken
+struct Counters(Vec<u8>);
+
+impl Counters {
+    fn new(entries: usize) -> Self {
+        Self(vec![1; entries])
+    }
+
+    fn index(&self, i: u64) -> usize {
+        (i % self.0.len() as u64) as usize
+    }
+
+    fn taken(&self, i: u64) -> bool {
+        self.0[self.index(i)] >= 2
+    }
+
+    fn train(&mut self, i: u64, taken: bool) {
+        let i = self.index(i);
+        if taken {
+            self.0[i] = (self.0[i] + 1).min(3);
+        } else {
+            self.0[i] = self.0[i].saturating_sub(1);
+        }
+    }
+}
+
+pub struct Bimodal {
+    counters: Counters,
+}
+
+impl Bimodal {
+    pub fn new(entries: usize) -> Self {
+        Self {
+            counters: Counters::new(entries),
+        }
+    }
+}
+
+impl BranchPredictor for Bimodal {
+    fn predict(&self, pc: u64) -> bool {
+        self.counters.taken(pc >> 2)
+    }
+
+    fn update(&mut self, pc: u64, taken: bool) {
+        self.counters.train(pc >> 2, taken);
+    }
+}
+
+pub struct GShare {
+    counters: Counters,
+    /// The outcomes of the most recent branches, newest in the lowest bit
+    history: u64,
+    history_bits: u32,
+}
+
+impl GShare {
+    pub fn new(entries: usize, history_bits: u32) -> Self {
+        Self {
+            counters: Counters::new(entries),
+            history: 0,
+            history_bits,
+        }
+    }
+
+    fn index(&self, pc: u64) -> u64 {
+        (pc >> 2) ^ self.history
+    }
+}
+
+impl BranchPredictor for GShare {
+    fn predict(&self, pc: u64) -> bool {
+        self.counters.taken(self.index(pc))
+    }
+
+    fn update(&mut self, pc: u64, taken: bool) {
+        self.counters.train(self.index(pc), taken);
+        let mask = (1 << self.history_bits) - 1;
+        self.history = ((self.history << 1) | taken as u64) & mask;
+    }
+}
+
+pub struct Tournament {
+    bimodal: Bimodal,
+    gshare: GShare,
+    /// Counters which are taken when gshare should be trusted over bimodal
+    chooser: Counters,
+}
+
+impl Tournament {
+    pub fn new(entries: usize, history_bits: u32) -> Self {
+        Self {
+            bimodal: Bimodal::new(entries),
+            gshare: GShare::new(entries, history_bits),
+            chooser: Counters::new(entries),
+        }
+    }
+}
+
+impl BranchPredictor for Tournament {
+    fn predict(&self, pc: u64) -> bool {
+        if self.chooser.taken(pc >> 2) {
+            self.gshare.predict(pc)
+        } else {
+            self.bimodal.predict(pc)
+        }
+    }
+
+    fn update(&mut self, pc: u64, taken: bool) {
+        let bimodal = self.bimodal.predict(pc) == taken;
+        let gshare = self.gshare.predict(pc) == taken;
+        if bimodal != gshare {
+            self.chooser.train(pc >> 2, gshare);
+        }
+        self.bimodal.update(pc, taken);
+        self.gshare.update(pc, taken);
+    }
+}
+
+/// A set associative cache of the targets of taken branches, by their address,
+/// with least recently used replacement
+pub struct BranchTargetBuffer {
+    /// Each set's branch addresses and targets, most recently used first
+    sets: Vec<Vec<(u64, u64)>>,
+    ways: usize,
+}
+
+impl BranchTargetBuffer {
+    pub fn new(entries: usize, ways: usize) -> Self {
+        Self {
+            sets: vec![Vec::with_capacity(ways); entries / ways],
+            ways,
+        }
+    }
+
+    fn set(&mut self, pc: u64) -> &mut Vec<(u64, u64)> {
+        let len = self.sets.len() as u64;
+        &mut self.sets[((pc >> 2) % len) as usize]
+    }
+
+    /// The target of the branch at an address, if it's in the buffer
+    pub fn lookup(&mut self, pc: u64) -> Option<u64> {
+        let set = self.set(pc);
+        let way = set.iter().position(|(branch, _)| *branch == pc)?;
+        let entry = set.remove(way);
+        set.insert(0, entry);
+        Some(entry.1)
+    }
+
+    /// Remember the target of a taken branch, evicting the least recently used branch in its set
+    pub fn insert(&mut self, pc: u64, target: u64) {
+        let ways = self.ways;
+        let set = self.set(pc);
+        set.retain(|(branch, _)| *branch != pc);
+        set.truncate(ways - 1);
+        set.insert(0, (pc, target));
+    }
+}
+
+/// Predicts the targets of returns from the addresses after the calls
+/// which are still in progress
+#[derive(Clone, Debug, Default)]
+pub struct ReturnAddressStack {
+    addresses: Vec<u64>,
+    capacity: usize,
+}
+
+impl ReturnAddressStack {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            addresses: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Push the return address of a call, overwriting the oldest if the stack is full
+    pub fn push(&mut self, address: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.addresses.len() == self.capacity {
+            self.addresses.remove(0);
+        }
+        self.addresses.push(address);
+    }
+
+    pub fn pop(&mut self) -> Option<u64> {
+        self.addresses.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Train a predictor on a branch's outcomes, and return what it predicted before each
+    fn predictions(predictor: &mut dyn BranchPredictor, pc: u64, outcomes: &[bool]) -> Vec<bool> {
+        outcomes
+            .iter()
+            .map(|&taken| {
+                let prediction = predictor.predict(pc);
+                predictor.update(pc, taken);
+                prediction
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bimodal_counters_saturate() {
+        let mut bimodal = Bimodal::new(16);
+        // Weakly not taken, so one taken outcome is enough to flip it
+        assert_eq!(
+            predictions(&mut bimodal, 0x40, &[true, true, true, false, false, false]),
+            [false, true, true, true, true, false]
+        );
+        // Branches with different counters don't affect each other
+        assert!(!bimodal.predict(0x44));
+        bimodal.update(0x44, true);
+        assert!(bimodal.predict(0x44));
+        assert!(!bimodal.predict(0x40));
+    }
+
+    #[test]
+    fn gshare_learns_patterns_bimodal_cant() {
+        let outcomes = [true, false].repeat(8);
+        let mut bimodal = Bimodal::new(16);
+        let mut gshare = GShare::new(16, 2);
+        let wrong = |predictions: Vec<bool>| {
+            predictions
+                .iter()
+                .zip(&outcomes)
+                .skip(8)
+                .filter(|(prediction, taken)| prediction != taken)
+                .count()
+        };
+        // Alternating outcomes keep moving the counter between weakly taken and weakly
+        // not taken, so it's always wrong
+        assert_eq!(wrong(predictions(&mut bimodal, 0x40, &outcomes)), 8);
+        assert_eq!(wrong(predictions(&mut gshare, 0x40, &outcomes)), 0);
+
+        // The tournament predictor learns to trust gshare
+        let mut tournament = Tournament::new(16, 2);
+        assert_eq!(wrong(predictions(&mut tournament, 0x40, &outcomes)), 0);
+    }
+
+    #[test]
+    fn btb_evicts_the_least_recently_used_branch_in_a_set() {
+        // Two sets of two ways, so these branches all go in the first set
+        let mut btb = BranchTargetBuffer::new(4, 2);
+        btb.insert(0x0, 0x100);
+        btb.insert(0x8, 0x200);
+        assert_eq!(btb.lookup(0x0), Some(0x100));
+        btb.insert(0x10, 0x300);
+        assert_eq!(btb.lookup(0x8), None);
+        assert_eq!(btb.lookup(0x0), Some(0x100));
+        assert_eq!(btb.lookup(0x10), Some(0x300));
+
+        // The other set is untouched, and a new target replaces the old one
+        btb.insert(0x4, 0x400);
+        btb.insert(0x4, 0x500);
+        assert_eq!(btb.lookup(0x4), Some(0x500));
+        assert_eq!(btb.lookup(0x0), Some(0x100));
+    }
+
+    #[test]
+    fn full_return_address_stack_loses_the_oldest_call() {
+        let mut ras = ReturnAddressStack::new(2);
+        for address in [0x4, 0x8, 0xc] {
+            ras.push(address);
+        }
+        assert_eq!(ras.pop(), Some(0xc));
+        assert_eq!(ras.pop(), Some(0x8));
+        assert_eq!(ras.pop(), None);
+
+        let mut ras = ReturnAddressStack::new(0);
+        ras.push(0x4);
+        assert_eq!(ras.pop(), None);
+    }
+}
@@ -1,15 +1,45 @@
 use super::*;
 use log::*;
-use std::fmt::{self, Display, Formatter};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// How to print the results of a simulation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The human readable pipeline table
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!(
+                "Unknown output format \"{s}\", expected table, json or csv"
+            )),
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct TomasuloTable {
     rows: Vec<Row>,
 
-    reorder_buffer_delays: u64,
-    reservation_station_delays: u64,
-    data_memory_conflict_delays: u64,
-    true_dependence_delays: u64
+    delays: Delays,
+    memory: MemoryStats,
+    /// The statistics of each cache level, from the L1 down
+    caches: Vec<CacheStats>,
+    branches: BranchStats,
+    branch_pcs: BTreeMap<u64, BranchPcStats>,
 }
 
 impl TomasuloTable {
@@ -17,34 +47,46 @@ impl TomasuloTable {
         Self::default()
     }
 
-    pub fn run(&mut self, instructions: Vec<RiscVOp>, config: &Config) {
+    pub fn run(&mut self, instructions: Vec<Instruction>, config: &Config) {
         let mut reorder_buffer = ReorderBuffer::from(config);
 
         let mut i = 0;
         let mut cycle = 1;
+        // The cycle issuing can restart in after the front end is redirected, and why it was
+        let mut redirected_until = 0;
+        let mut redirect_stall = IssueStall::BranchMispredict;
         loop {
             if reorder_buffer.get_finished_instructions() >= instructions.len() {
                 break;
             }
 
-            if i < instructions.len() {
-                let op = instructions[i];
-                if reorder_buffer.add(op).is_ok() {
+            if i < instructions.len() && cycle < redirected_until {
+                reorder_buffer.stall_issue(redirect_stall, config.issue_width);
+            } else if i < instructions.len() {
+                // Issue instructions in order until one stalls, which wastes the rest of the slots
+                for slot in 0..config.issue_width {
+                    let Some(instruction) = instructions.get(i).copied() else {
+                        break;
+                    };
+                    if let Err(stall) = reorder_buffer.add(instruction) {
+                        trace!("Failed to add instruction {i}: {instruction} ({stall:?})");
+                        reorder_buffer.stall_issue(stall, config.issue_width - slot);
+                        break;
+                    }
                     while self.rows.len() <= i {
-                        self.rows.push(Row {
-                            op: None,
-                            issued: None,
-                            start_ex: None,
-                            end_ex: None,
-                            mem_access: None,
-                            write_back: None,
-                            committed: None,
-                        });
+                        self.rows.push(Row::default());
                     }
                     self.rows[i].issued = Some(cycle);
                     i += 1;
-                } else {
-                    trace!("Failed to add instruction {i}: {op}");
+
+                    // A taken branch which missed in the BTB goes to its target once it's decoded
+                    if reorder_buffer.take_bubble() {
+                        let slots = config.issue_width - slot - 1;
+                        reorder_buffer.stall_issue(IssueStall::FrontEndRedirect, slots);
+                        redirected_until = cycle + 1 + config.btb_miss_penalty;
+                        redirect_stall = IssueStall::FrontEndRedirect;
+                        break;
+                    }
                 }
             } else if reorder_buffer.get_finished_instructions() >= instructions.len() {
                 info!("Stopped at instruction {}:", i);
@@ -53,15 +95,7 @@ impl TomasuloTable {
             let stages = reorder_buffer.get_stages();
             for (instruction_num, op, stage) in stages {
                 while self.rows.len() <= instruction_num {
-                    self.rows.push(Row {
-                        op: None,
-                        issued: None,
-                        start_ex: None,
-                        end_ex: None,
-                        mem_access: None,
-                        write_back: None,
-                        committed: None,
-                    });
+                    self.rows.push(Row::default());
                 }
 
                 self.rows[instruction_num].op = Some(op);
@@ -76,7 +110,7 @@ impl TomasuloTable {
                     Stage::Execute(_) if self.rows[instruction_num].start_ex.is_none() => {
                         self.rows[instruction_num].start_ex = Some(cycle)
                     }
-                    Stage::MemAccess => self.rows[instruction_num].mem_access = Some(cycle),
+                    Stage::MemAccess(_) => self.rows[instruction_num].mem_access = Some(cycle),
                     Stage::WriteBack => self.rows[instruction_num].write_back = Some(cycle),
                     Stage::Commit => self.rows[instruction_num].committed = Some(cycle),
                     _ => {}
@@ -86,19 +120,34 @@ impl TomasuloTable {
             cycle += 1;
             trace!("Cycle {}\n\n{}", cycle, reorder_buffer);
 
+            for tag in reorder_buffer.take_forwarded() {
+                self.rows[tag].forwarded = true;
+            }
+            for (tag, served_by) in reorder_buffer.take_cache_accesses() {
+                self.rows[tag].served_by = Some(served_by);
+            }
+
+            // Throw away the rows of squashed instructions, and issue them again
+            if let Some(redirect) = reorder_buffer.take_redirect() {
+                debug!(
+                    "Redirecting to instruction {} in cycle {cycle}",
+                    redirect.next
+                );
+                self.rows.truncate(redirect.next);
+                i = redirect.next;
+                redirected_until = cycle
+                    + match redirect.stall {
+                        IssueStall::MemoryReplay => config.replay_penalty,
+                        _ => config.mispredict_penalty,
+                    };
+                redirect_stall = redirect.stall;
+            }
+
             let stages = reorder_buffer.get_stages();
 
             for (instruction_num, op, stage) in stages {
                 while self.rows.len() <= instruction_num {
-                    self.rows.push(Row {
-                        op: None,
-                        issued: None,
-                        start_ex: None,
-                        end_ex: None,
-                        mem_access: None,
-                        write_back: None,
-                        committed: None,
-                    });
+                    self.rows.push(Row::default());
                 }
 
                 self.rows[instruction_num].op = Some(op);
@@ -113,7 +162,7 @@ impl TomasuloTable {
                     Stage::Execute(_) if self.rows[instruction_num].start_ex.is_none() => {
                         self.rows[instruction_num].start_ex = Some(cycle)
                     }
-                    Stage::MemAccess => self.rows[instruction_num].mem_access = Some(cycle),
+                    Stage::MemAccess(_) => self.rows[instruction_num].mem_access = Some(cycle),
                     Stage::WriteBack => self.rows[instruction_num].write_back = Some(cycle),
                     Stage::Commit => self.rows[instruction_num].committed = Some(cycle),
                     _ => {}
@@ -121,36 +170,320 @@ impl TomasuloTable {
             }
         }
 
-        let (a, b, c, d) = reorder_buffer.get_delays();
-        self.reorder_buffer_delays = a;
-        self.reservation_station_delays = b;
-        self.data_memory_conflict_delays = c;
-        self.true_dependence_delays = d;
+        self.delays = reorder_buffer.get_delays();
+        self.memory = reorder_buffer.get_memory_stats();
+        self.caches = reorder_buffer.get_cache_stats();
+        self.branches = reorder_buffer.get_branch_stats();
+        self.branch_pcs = reorder_buffer.get_branch_pcs();
+        self.mark_groups();
 
         info!("Finished simulation");
     }
+
+    /// Mark the instructions which were issued or committed in the same cycle.
+    /// Both happen in order, so each group is a run of consecutive rows.
+    fn mark_groups(&mut self) {
+        let same_cycle = |a: Option<u64>, b: Option<u64>| a.is_some() && a == b;
+        let grouped = self.rows.windows(2).any(|pair| {
+            same_cycle(pair[0].issued, pair[1].issued)
+                || same_cycle(pair[0].committed, pair[1].committed)
+        });
+        if !grouped {
+            return;
+        }
+
+        for i in 0..self.rows.len() {
+            let prev = i.checked_sub(1).map(|j| &self.rows[j]);
+            let next = self.rows.get(i + 1);
+            let row = &self.rows[i];
+            let mark = |cycle: fn(&Row) -> Option<u64>| {
+                let with_prev = prev.is_some_and(|p| same_cycle(cycle(p), cycle(row)));
+                let with_next = next.is_some_and(|n| same_cycle(cycle(n), cycle(row)));
+                match (with_prev, with_next) {
+                    (false, false) => ' ',
+                    (false, true) => '┐',
+                    (true, true) => '│',
+                    (true, false) => '┘',
+                }
+            };
+            let groups = (mark(|r| r.issued), mark(|r| r.committed));
+            self.rows[i].groups = Some(groups);
+        }
+    }
+
+    /// The number of cycles lost to each kind of delay, by name
+    pub fn delays(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("reorder buffer delays", self.delays.reorder_buffer),
+            (
+                "reservation station delays",
+                self.delays.reservation_station,
+            ),
+            ("load/store queue delays", self.delays.load_store_queue),
+            (
+                "data memory conflict delays",
+                self.delays.data_memory_conflict,
+            ),
+            ("cache miss delays", self.delays.cache_miss),
+            ("MSHR full delays", self.delays.mshr_full),
+            ("true dependence delays", self.delays.true_dependence),
+            ("functional unit delays", self.delays.functional_unit),
+            ("unpipelined unit delays", self.delays.unpipelined_unit),
+            ("CDB conflict delays", self.delays.cdb_conflict),
+            ("branch mispredict delays", self.delays.branch_mispredict),
+            ("memory replay delays", self.delays.memory_replay),
+            ("front-end redirect delays", self.delays.front_end_redirect),
+        ]
+    }
+
+    /// What happened to the loads and stores, by name
+    pub fn memory_stats(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("loads", self.memory.loads),
+            ("stores", self.memory.stores),
+            ("forwarded loads", self.memory.forwarded_loads),
+            ("ordering violations", self.memory.violations),
+            ("replays", self.memory.replays),
+            ("replayed instructions", self.memory.replayed),
+        ]
+    }
+
+    /// How well branches were predicted, by name
+    pub fn branch_stats(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("branches", self.branches.branches),
+            ("mispredictions", self.branches.mispredictions),
+            ("squashed instructions", self.branches.squashed),
+            ("BTB hits", self.branches.btb_hits),
+            ("BTB misses", self.branches.btb_misses),
+            ("RAS hits", self.branches.ras_hits),
+            ("RAS misses", self.branches.ras_misses),
+        ]
+    }
+
+    /// Print the results in the given format
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Table => self.to_string(),
+            OutputFormat::Json => self.to_json(),
+            OutputFormat::Csv => self.to_csv(),
+        }
+    }
+
+    /// One line per instruction, with an empty field for stages it never reached.
+    /// The delays are not included.
+    pub fn to_csv(&self) -> String {
+        let mut result = String::from(
+            "instruction,issued,execute_start,execute_end,memory_read,write_result,committed\n",
+        );
+        for row in &self.rows {
+            let fields = row
+                .cycles()
+                .iter()
+                .map(|(_, cycle)| cycle.map(|c| c.to_string()).unwrap_or_default())
+                .collect::<Vec<_>>();
+            result += &format!("\"{}\",{}\n", row.instruction(), fields.join(","));
+        }
+        result
+    }
+
+    /// The instructions and delays as a JSON object
+    pub fn to_json(&self) -> String {
+        let mut result = String::from("{\n  \"instructions\": [");
+        for (i, row) in self.rows.iter().enumerate() {
+            if i > 0 {
+                result.push(',');
+            }
+            result += &format!(
+                "\n    {{\"instruction\": {}",
+                json_string(&row.instruction())
+            );
+            for (name, cycle) in row.cycles() {
+                match cycle {
+                    Some(cycle) => result += &format!(", \"{name}\": {cycle}"),
+                    None => result += &format!(", \"{name}\": null"),
+                }
+            }
+            result += &format!(", \"forwarded\": {}", row.forwarded);
+            match row.served_by {
+                Some(served_by) => result += &format!(", \"cache\": \"{served_by}\"}}"),
+                None => result += ", \"cache\": null}",
+            }
+        }
+        result += "\n  ],\n  \"delays\": {";
+        for (i, (name, delays)) in self.delays().iter().enumerate() {
+            if i > 0 {
+                result.push(',');
+            }
+            result += &format!(
+                "\n    \"{}\": {delays}",
+                name.replace(' ', "_").to_lowercase()
+            );
+        }
+        result += "\n  },\n  \"memory\": {";
+        for (i, (name, count)) in self.memory_stats().iter().enumerate() {
+            if i > 0 {
+                result.push(',');
+            }
+            result += &format!(
+                "\n    \"{}\": {count}",
+                name.replace(' ', "_").to_lowercase()
+            );
+        }
+        result += "\n  },\n  \"caches\": [";
+        for (i, stats) in self.caches.iter().enumerate() {
+            if i > 0 {
+                result.push(',');
+            }
+            result += &format!(
+                "\n    {{\"level\": {}, \"hits\": {}, \"misses\": {}, \"mshr_full\": {}}}",
+                i + 1,
+                stats.hits,
+                stats.misses,
+                stats.mshr_full
+            );
+        }
+        result += "\n  ],\n  \"branches\": {";
+        for (i, (name, count)) in self.branch_stats().iter().enumerate() {
+            if i > 0 {
+                result.push(',');
+            }
+            result += &format!(
+                "\n    \"{}\": {count}",
+                name.replace(' ', "_").to_lowercase()
+            );
+        }
+        result += "\n  },\n  \"branch_pcs\": [";
+        for (i, (pc, stats)) in self.branch_pcs.iter().enumerate() {
+            if i > 0 {
+                result.push(',');
+            }
+            result += &format!(
+                "\n    {{\"pc\": {pc}, \"executed\": {}, \"btb_hits\": {}, \"btb_misses\": {}, \"mispredictions\": {}}}",
+                stats.executed, stats.btb_hits, stats.btb_misses, stats.mispredictions
+            );
+        }
+        result += "\n  ]\n}\n";
+        result
+    }
+}
+
+/// Quote and escape a string for JSON
+fn json_string(s: &str) -> String {
+    let mut result = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            c if c.is_control() => result += &format!("\\u{:04x}", c as u32),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
 }
 
 impl Display for TomasuloTable {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         writeln!(f, "                    Pipeline Simulation\n-----------------------------------------------------------")?;
-        writeln!(f, "                                      Memory Writes\n     Instruction      Issues Executes  Read  Result Commits\n--------------------- ------ -------- ------ ------ -------")?;
-        for row in &self.rows {
-            writeln!(f, "{}", row)?;
+        let (header, rule) = if self.rows.iter().any(|row| row.groups.is_some()) {
+            // Leave room for the marks showing which instructions were issued or committed together
+            (
+                "                                       Memory Writes\n     Instruction      Issues  Executes  Read  Result Commits",
+                "--------------------- ------- -------- ------ ------ --------",
+            )
+        } else {
+            (
+                "                                      Memory Writes\n     Instruction      Issues Executes  Read  Result Commits",
+                "--------------------- ------ -------- ------ ------ -------",
+            )
+        };
+        if self.rows.iter().any(|row| row.served_by.is_some()) {
+            // Add a column for where each load and store found its line
+            writeln!(f, "{header}  From\n{rule} ----")?;
+            for row in &self.rows {
+                let Some(served_by) = row.served_by else {
+                    writeln!(f, "{}", row)?;
+                    continue;
+                };
+                writeln!(
+                    f,
+                    "{:<width$} {:>4}",
+                    row.to_string(),
+                    served_by.to_string(),
+                    width = rule.len()
+                )?;
+            }
+        } else {
+            writeln!(f, "{header}\n{rule}")?;
+            for row in &self.rows {
+                writeln!(f, "{}", row)?;
+            }
         }
 
         writeln!(f)?;
         writeln!(f)?;
         writeln!(f, "Delays")?;
-        writeln!(f, "------")?;
-        writeln!(f, "reorder buffer delays: {}", self.reorder_buffer_delays)?;
-        writeln!(f, "reservation station delays: {}", self.reservation_station_delays)?;
-        writeln!(f, "data memory conflict delays: {}", self.data_memory_conflict_delays)?;
-        write!(f, "true dependence delays: {}", self.true_dependence_delays)?;
+        write!(f, "------")?;
+        for (name, delays) in self.delays() {
+            write!(f, "\n{name}: {delays}")?;
+        }
+
+        writeln!(f)?;
+        writeln!(f)?;
+        writeln!(f, "Memory")?;
+        write!(f, "------")?;
+        for (name, count) in self.memory_stats() {
+            write!(f, "\n{name}: {count}")?;
+        }
+
+        if !self.caches.is_empty() {
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "Cache     Hits   Misses MSHR Full")?;
+            write!(f, "----- -------- -------- ---------")?;
+            for (level, stats) in self.caches.iter().enumerate() {
+                write!(
+                    f,
+                    "\n{:<5} {:>8} {:>8} {:>9}",
+                    format!("L{}", level + 1),
+                    stats.hits,
+                    stats.misses,
+                    stats.mshr_full
+                )?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f)?;
+        writeln!(f, "Branches")?;
+        write!(f, "--------")?;
+        for (name, count) in self.branch_stats() {
+            write!(f, "\n{name}: {count}")?;
+        }
+
+        if !self.branch_pcs.is_empty() {
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "    PC     Executed BTB Hits BTB Misses Mispredicted")?;
+            write!(f, "---------- -------- -------- ---------- ------------")?;
+            for (pc, stats) in &self.branch_pcs {
+                write!(
+                    f,
+                    "\n{:>10} {:>8} {:>8} {:>10} {:>12}",
+                    format!("{pc:#x}"),
+                    stats.executed,
+                    stats.btb_hits,
+                    stats.btb_misses,
+                    stats.mispredictions
+                )?;
+            }
+        }
         Ok(())
     }
 }
 
+#[derive(Default)]
 struct Row {
     op: Option<RiscVOp>,
     issued: Option<u64>,
@@ -159,6 +492,39 @@ struct Row {
     mem_access: Option<u64>,
     write_back: Option<u64>,
     committed: Option<u64>,
+    /// The marks for the groups of instructions issued and committed in the same cycle,
+    /// if any instructions were
+    groups: Option<(char, char)>,
+    /// Whether this is a load which took its value from an older store
+    forwarded: bool,
+    /// Where a load or store found its line, if it accessed the caches
+    served_by: Option<ServedBy>,
+}
+
+impl Row {
+    /// The instruction with its columns of padding collapsed
+    fn instruction(&self) -> String {
+        match &self.op {
+            Some(op) => op
+                .to_string()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => String::from("?"),
+        }
+    }
+
+    /// The cycle each stage happened in, by name
+    fn cycles(&self) -> [(&'static str, Option<u64>); 6] {
+        [
+            ("issued", self.issued),
+            ("execute_start", self.start_ex),
+            ("execute_end", self.end_ex),
+            ("memory_read", self.mem_access),
+            ("write_result", self.write_back),
+            ("committed", self.committed),
+        ]
+    }
 }
 
 impl Display for Row {
@@ -174,6 +540,9 @@ impl Display for Row {
         } else {
             write!(f, "{:>6}", "?")?;
         }
+        if let Some((issued, _)) = self.groups {
+            write!(f, "{issued}")?;
+        }
 
         if let Some(start_ex) = &self.start_ex {
             write!(f, "{:>4}", start_ex)?;
@@ -190,7 +559,12 @@ impl Display for Row {
         }
 
         if let Some(mem_access) = &self.mem_access {
-            write!(f, "{:>7}", mem_access)?;
+            if self.forwarded {
+                // Marked with the store's value being forwarded, rather than read from memory
+                write!(f, "{:>6}*", mem_access)?;
+            } else {
+                write!(f, "{:>7}", mem_access)?;
+            }
         } else if let Some(op) = self.op {
             if op.accesses_memory() {
                 write!(f, "{:>7}", "?")?;
@@ -218,7 +592,219 @@ impl Display for Row {
         } else {
             write!(f, "{:>8}", "?")?;
         }
+        match self.groups {
+            Some((_, ' ')) | None => {}
+            Some((_, committed)) => write!(f, "{committed}")?,
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulate a trace with a configuration written in TOML
+    fn simulate(trace: &str, config: &str) -> TomasuloTable {
+        let instructions = parse_trace(trace).unwrap().instructions;
+        let config = Config::from_toml(config).unwrap();
+        config.validate().unwrap();
+        let mut table = TomasuloTable::new();
+        table.run(instructions, &config);
+        table
+    }
+
+    /// The cycles each instruction was issued, started and finished executing, read
+    /// memory, wrote its result and committed in
+    fn cycles(table: &TomasuloTable) -> Vec<[Option<u64>; 6]> {
+        table
+            .rows
+            .iter()
+            .map(|row| row.cycles().map(|(_, cycle)| cycle))
+            .collect()
+    }
+
+    /// The cycles lost to one kind of delay, by name
+    fn delay(table: &TomasuloTable, name: &str) -> u64 {
+        table
+            .delays()
+            .into_iter()
+            .find(|(delay, _)| *delay == name)
+            .map(|(_, cycles)| cycles)
+            .unwrap()
+    }
+
+    /// When each instruction wrote its result
+    fn writes(table: &TomasuloTable) -> Vec<Option<u64>> {
+        cycles(table).iter().map(|cycles| cycles[4]).collect()
+    }
+
+    /// When each instruction started executing
+    fn starts(table: &TomasuloTable) -> Vec<Option<u64>> {
+        cycles(table).iter().map(|cycles| cycles[1]).collect()
+    }
+
+    #[test]
+    fn opcode_latencies_override_their_class() {
+        let table = simulate(
+            "fdiv.s f1,f2,f3\nfdiv.d f4,f5,f6\nflw f7,0(x1)\n",
+            "[latencies]\nfp_div = 10\n\"fdiv.s\" = 4\n\n[latencies.memory]\nload = 3\n",
+        );
+        assert_eq!(
+            cycles(&table),
+            [
+                [Some(1), Some(2), Some(5), None, Some(6), Some(7)],
+                [Some(2), Some(3), Some(12), None, Some(13), Some(14)],
+                // Memory is read for 3 cycles after the address is worked out
+                [Some(3), Some(4), Some(4), Some(7), Some(8), Some(15)],
+            ]
+        );
+    }
+
+    #[test]
+    fn ready_instructions_wait_for_a_free_unit() {
+        // Two multiplies issue together, and there are reservation stations for both
+        let trace = "fmul.s f1,f2,f3\nfmul.s f4,f5,f6\nfmul.s f7,f8,f9\n";
+        let config = |units| {
+            format!(
+                "[units]\nfp_mul = {units}\n\n[pipelining]\nfp_mul = \"pipelined\"\n\n\
+                 [width]\nissue = 2\ncommit = 2\ncdb = 2\n"
+            )
+        };
+        let table = simulate(trace, &config(1));
+        assert_eq!(starts(&table), [Some(2), Some(3), Some(4)]);
+        assert_eq!(delay(&table, "functional unit delays"), 2);
+        assert_eq!(delay(&table, "reservation station delays"), 0);
+
+        let table = simulate(trace, &config(3));
+        assert_eq!(starts(&table), [Some(2), Some(2), Some(3)]);
+        assert_eq!(delay(&table, "functional unit delays"), 0);
+    }
+
+    #[test]
+    fn units_are_busy_until_their_next_instruction_can_start() {
+        let trace = "fmul.s f1,f2,f3\nfmul.s f4,f5,f6\nfmul.s f7,f8,f9\n";
+        let config = |pipelining| {
+            format!(
+                "[units]\nfp_mul = 1\n\n[pipelining]\nfp_mul = {pipelining}\n\n\
+                 [width]\nissue = 2\ncommit = 2\ncdb = 2\n"
+            )
+        };
+        for (pipelining, expected) in [
+            ("\"pipelined\"", [2, 3, 4]),
+            // Each multiply takes 5 cycles
+            ("\"unpipelined\"", [2, 7, 12]),
+            ("2", [2, 4, 6]),
+        ] {
+            let table = simulate(trace, &config(pipelining));
+            assert_eq!(starts(&table), expected.map(Some), "{pipelining}");
+        }
+        let table = simulate(trace, &config("\"unpipelined\""));
+        assert_eq!(delay(&table, "unpipelined unit delays"), 12);
+
+        // A divide holds up a pipelined unit if its opcode isn't pipelined
+        let trace = "fdiv.s f1,f2,f3\nfmul.s f4,f5,f6\n";
+        let config = "[units]\nfp_mul = 1\n\n[pipelining]\nfp_mul = \"pipelined\"\n\
+                      \"fdiv.s\" = \"unpipelined\"\n";
+        let table = simulate(trace, config);
+        assert_eq!(starts(&table), [Some(2), Some(12)]);
+    }
+
+    #[test]
+    fn the_oldest_result_gets_the_common_data_bus() {
+        // The add and the immediate finish executing in the same cycle
+        let trace = "fadd.s f4,f5,f6\naddi x1,x0,1\n";
+        let table = simulate(trace, "[width]\ncdb = 1\n");
+        assert_eq!(writes(&table), [Some(4), Some(5)]);
+        assert_eq!(delay(&table, "CDB conflict delays"), 1);
+        let table = simulate(trace, "[width]\ncdb = 2\n");
+        assert_eq!(writes(&table), [Some(4), Some(4)]);
+        assert_eq!(delay(&table, "CDB conflict delays"), 0);
+
+        // The last immediate has been waiting longer, but the multiply is older
+        let trace = "fmul.s f4,f5,f6\nfadd.s f1,f2,f3\naddi x1,x0,1\naddi x2,x0,1\n";
+        let table = simulate(trace, "");
+        assert_eq!(writes(&table), [Some(8), Some(5), Some(6), Some(9)]);
+    }
+
+    #[test]
+    fn issue_and_commit_widths_limit_each_cycle() {
+        let trace = "addi x1,x0,1\naddi x2,x0,2\naddi x3,x0,3\naddi x4,x0,4\naddi x5,x0,5\n";
+        let table = simulate(
+            trace,
+            "[width]\nissue = 4\ncommit = 2\ncdb = 4\n\n\
+             [buffers]\nint = 8\nreorder = 8\n\n[units]\nint = 4\n",
+        );
+        let issued: Vec<_> = cycles(&table).iter().map(|cycles| cycles[0]).collect();
+        let committed: Vec<_> = cycles(&table).iter().map(|cycles| cycles[5]).collect();
+        assert_eq!(issued, [1, 1, 1, 1, 2].map(Some));
+        assert_eq!(committed, [4, 4, 5, 5, 6].map(Some));
+        // Instructions issued or committed together are marked as a group
+        assert_eq!(table.rows[0].groups, Some(('┐', '┐')));
+        assert_eq!(table.rows[1].groups, Some(('│', '┘')));
+        assert_eq!(table.rows[4].groups, Some((' ', ' ')));
+
+        // One at a time by default
+        let table = simulate(trace, "[buffers]\nint = 8\nreorder = 8\n");
+        let issued: Vec<_> = cycles(&table).iter().map(|cycles| cycles[0]).collect();
+        assert_eq!(issued, [1, 2, 3, 4, 5].map(Some));
+    }
+
+    #[test]
+    fn renaming_only_waits_for_true_dependences() {
+        // The adds write registers the divide reads and writes, but go ahead of it, and
+        // the multiply only waits for the last add
+        let table = simulate(
+            "fdiv.s f1,f2,f3\nfadd.s f2,f4,f5\nfadd.s f1,f4,f5\nfmul.s f6,f1,f1\n",
+            "",
+        );
+        assert_eq!(starts(&table), [2, 3, 4, 8].map(Some));
+        assert_eq!(writes(&table), [12, 5, 7, 14].map(Some));
+    }
+
+    #[test]
+    fn loads_forward_from_the_youngest_older_store() {
+        // The first store waits for the divide, but the load takes the value of the second
+        // as soon as it has it, and the last load doesn't wait for the store after it
+        let table = simulate(
+            "fdiv.s f4,f2,f6\n\
+             fsw f4,0(x2):64\n\
+             fsw f1,0(x3):64\n\
+             flw f9,0(x2):64\n\
+             flw f10,0(x2):128\n\
+             fsw f1,0(x2):128\n",
+            "",
+        );
+        let memory_reads: Vec<_> = cycles(&table).iter().map(|cycles| cycles[3]).collect();
+        assert_eq!(memory_reads, [None, None, None, Some(7), Some(9), None]);
+        assert_eq!(writes(&table)[3..5], [Some(8), Some(10)]);
+        assert!(table.memory_stats().contains(&("forwarded loads", 1)));
+    }
+
+    #[test]
+    fn l1_misses_add_the_miss_latency_to_the_hit_latency() {
+        // The last two loads wait for the first one's value for their address, and by
+        // then its line is in the cache
+        let table = simulate(
+            "flw f1,0(x1):64\n\
+             fcvt.w.s x2,f1\n\
+             flw f2,4(x2):68\n\
+             flw f3,0(x2):128\n",
+            "[caches.l1]\nhit_latency = 2\nmiss_latency = 10\n",
+        );
+        let memory_reads: Vec<_> = cycles(&table).iter().map(|cycles| cycles[3]).collect();
+        assert_eq!(memory_reads, [Some(14), None, Some(21), Some(31)]);
+        let served_by: Vec<_> = table.rows.iter().map(|row| row.served_by).collect();
+        assert_eq!(
+            served_by,
+            [
+                Some(ServedBy::Memory),
+                None,
+                Some(ServedBy::Cache(0)),
+                Some(ServedBy::Memory)
+            ]
+        );
+        assert_eq!(delay(&table, "cache miss delays"), 20);
+    }
+}
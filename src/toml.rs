@@ -0,0 +1,230 @@
+//! A minimal reader for the subset of TOML used by configuration files.
+//!
+//! Supports `[section]` and `[dotted.section]` headers, `key = value` pairs with
+//! bare or quoted keys, and integer, string and boolean values. Comments start
+//! with `#`. Arrays, inline tables and dates are not supported.
+
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Integer(u64),
+    String(String),
+    Boolean(bool),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "\"{s}\""),
+            Value::Boolean(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+/// A single `key = value` pair, along with the section it was found in
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// The section header, such as `buffers` or `caches.l1`, or empty for the top level
+    pub section: String,
+    pub key: String,
+    pub value: Value,
+    /// The line number, starting at 1
+    pub line: usize,
+}
+
+impl Entry {
+    /// The full name of the key, such as `buffers.reorder`
+    pub fn name(&self) -> String {
+        if self.section.is_empty() {
+            self.key.clone()
+        } else {
+            format!("{}.{}", self.section, self.key)
+        }
+    }
+
+    pub fn integer(&self) -> Result<u64, String> {
+        match &self.value {
+            Value::Integer(n) => Ok(*n),
+            value => Err(self.error(format!("expected an integer, found {value}"))),
+        }
+    }
+
+    pub fn string(&self) -> Result<&str, String> {
+        match &self.value {
+            Value::String(s) => Ok(s),
+            value => Err(self.error(format!("expected a string, found {value}"))),
+        }
+    }
+
+    /// Describe a problem with this entry
+    pub fn error(&self, message: impl Display) -> String {
+        format!("line {}: {}: {message}", self.line, self.name())
+    }
+}
+
+/// Parse the contents of a TOML file into its entries, in order
+pub fn parse(contents: &str) -> Result<Vec<Entry>, String> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut section = String::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |message: String| format!("line {line_number}: {message}");
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error(format!("unterminated section header \"{line}\"")))?;
+            section = header
+                .split('.')
+                .map(|part| parse_key(part).map_err(&error))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(".");
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected `key = value`, found \"{line}\"")))?;
+        let key = parse_key(key).map_err(&error)?;
+        let value = parse_value(value.trim()).map_err(&error)?;
+
+        let entry = Entry {
+            section: section.clone(),
+            key,
+            value,
+            line: line_number,
+        };
+        if let Some(previous) = entries
+            .iter()
+            .find(|e| e.section == entry.section && e.key == entry.key)
+        {
+            return Err(entry.error(format!("already set on line {}", previous.line)));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Remove a trailing `#` comment, ignoring any inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+    if let Some(quoted) = key.strip_prefix('"').and_then(|k| k.strip_suffix('"')) {
+        return Ok(quoted.to_string());
+    }
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(key.to_string())
+    } else {
+        Err(format!("invalid key \"{key}\""))
+    }
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    match value {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        "" => return Err(String::from("missing value")),
+        _ => {}
+    }
+
+    if let Some(s) = value.strip_prefix('"') {
+        return s
+            .strip_suffix('"')
+            .filter(|s| !s.contains('"'))
+            .map(|s| Value::String(s.to_string()))
+            .ok_or_else(|| format!("invalid string {value}"));
+    }
+
+    let digits = value.replace('_', "");
+    let n = if let Some(hex) = digits.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        digits.parse::<u64>()
+    };
+    n.map(Value::Integer).map_err(|_| {
+        format!("invalid value \"{value}\", expected a non-negative integer, a string or a boolean")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_keep_their_section_and_line() {
+        let entries = parse(
+            "top = 1\n\
+             \n\
+             [buffers] # reservation stations\n\
+             reorder = 0x10\n\
+             load_store = 1_000\n\
+             [latencies.memory]\n\
+             \"fdiv.s\" = \"a # not a comment\"\n\
+             flag = true\n",
+        )
+        .unwrap();
+        let found: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.name(), entry.value.clone(), entry.line))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (String::from("top"), Value::Integer(1), 1),
+                (String::from("buffers.reorder"), Value::Integer(16), 4),
+                (String::from("buffers.load_store"), Value::Integer(1000), 5),
+                (
+                    String::from("latencies.memory.fdiv.s"),
+                    Value::String(String::from("a # not a comment")),
+                    7
+                ),
+                (
+                    String::from("latencies.memory.flag"),
+                    Value::Boolean(true),
+                    8
+                ),
+            ]
+        );
+        assert_eq!(
+            entries[3].integer().unwrap_err(),
+            "line 7: latencies.memory.fdiv.s: expected an integer, found \"a # not a comment\""
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        for (contents, message) in [
+            ("[buffers", "line 1: unterminated section header \"[buffers\""),
+            ("reorder", "line 1: expected `key = value`, found \"reorder\""),
+            ("a b = 1", "line 1: invalid key \"a b\""),
+            ("reorder =", "line 1: missing value"),
+            ("reorder = -1", "line 1: invalid value \"-1\", expected a non-negative integer, a string or a boolean"),
+            ("name = \"a\"b\"", "line 1: invalid string \"a\"b\""),
+            ("[width]\nissue = 1\n\nissue = 2", "line 4: width.issue: already set on line 2"),
+        ] {
+            assert_eq!(parse(contents).unwrap_err(), message, "{contents}");
+        }
+    }
+}
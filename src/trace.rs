@@ -0,0 +1,689 @@
+use super::*;
+use log::*;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// Why a line of a trace could not be parsed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceErrorKind {
+    /// The line has operands or an address, but no mnemonic
+    MissingMnemonic,
+    /// The mnemonic isn't a supported instruction
+    UnknownMnemonic,
+    /// The instruction needs more operands than were given
+    MissingOperand,
+    /// The instruction was given more operands than it takes
+    UnexpectedOperand,
+    /// A register operand is malformed or names the wrong register file
+    BadRegister,
+    /// An offset, memory operand or label is malformed
+    BadOperand,
+    /// The `:addr` suffix isn't a number
+    BadAddress,
+    /// An `:addr` suffix was given to an instruction that doesn't access memory
+    UnexpectedAddress,
+    /// A branch's suffix isn't `:T` for taken or `:N` for not taken
+    BadOutcome,
+    /// The instruction address at the start of the line isn't a number
+    BadPc,
+    /// A label was defined on an earlier line
+    DuplicateLabel,
+    /// A program branches or jumps to a label it doesn't define
+    UndefinedLabel,
+    /// The encoding of an instruction in a simulator's log isn't a number
+    BadEncoding,
+    /// The memory address in a simulator's log isn't a number
+    BadMemoryAddress,
+    /// A QEMU log runs a block of instructions it didn't log the translation of
+    UnknownBlock,
+}
+
+impl Display for TraceErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceErrorKind::MissingMnemonic => write!(f, "missing mnemonic"),
+            TraceErrorKind::UnknownMnemonic => write!(f, "unknown mnemonic"),
+            TraceErrorKind::MissingOperand => write!(f, "missing operand"),
+            TraceErrorKind::UnexpectedOperand => write!(f, "unexpected operand"),
+            TraceErrorKind::BadRegister => write!(f, "bad register"),
+            TraceErrorKind::BadOperand => write!(f, "bad operand"),
+            TraceErrorKind::BadAddress => write!(f, "bad `:addr` suffix"),
+            TraceErrorKind::UnexpectedAddress => {
+                write!(
+                    f,
+                    "`:addr` suffix on an instruction that doesn't access memory"
+                )
+            }
+            TraceErrorKind::BadOutcome => {
+                write!(f, "bad branch outcome, expected `:T` or `:N`")
+            }
+            TraceErrorKind::BadPc => write!(f, "bad instruction address"),
+            TraceErrorKind::DuplicateLabel => write!(f, "label defined more than once"),
+            TraceErrorKind::UndefinedLabel => write!(f, "undefined label"),
+            TraceErrorKind::BadEncoding => write!(f, "bad instruction encoding"),
+            TraceErrorKind::BadMemoryAddress => write!(f, "bad memory address"),
+            TraceErrorKind::UnknownBlock => write!(f, "no instructions logged for the block"),
+        }
+    }
+}
+
+/// An error found while parsing a trace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceError {
+    /// The line number, starting at 1
+    pub line: usize,
+    /// The column of the offending token, starting at 1
+    pub column: usize,
+    /// The offending token, which is empty if something is missing
+    pub token: String,
+    pub kind: TraceErrorKind,
+}
+
+impl Display for TraceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )?;
+        if !self.token.is_empty() {
+            write!(f, " \"{}\"", self.token)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+/// An instruction from a trace, along with its address
+#[derive(Clone, Copy, Debug)]
+pub struct Instruction {
+    pub pc: u64,
+    pub op: RiscVOp,
+    /// The address of the instruction which ran next, which is
+    /// the target of a taken branch
+    pub next_pc: u64,
+}
+
+impl Instruction {
+    /// Parse a line of a trace, which may start with the instruction's address,
+    /// such as `0x1c bne x1,x2,Loop:T`. Otherwise it's assumed to follow `next_pc`.
+    pub fn parse(line: &str, next_pc: u64) -> Result<Self, TraceError> {
+        let (pc, rest) = Self::split_pc(line)?;
+        let op = RiscVOp::parse(&rest)?;
+        Ok(Self::new(pc.unwrap_or(next_pc), op))
+    }
+
+    /// Parse a line of an assembly program, like [`Instruction::parse`], except that it
+    /// can stand for more than one instruction, one after the other, such as a `li`
+    /// too wide for `addi`
+    pub fn parse_expanded(line: &str, next_pc: u64) -> Result<Vec<Self>, TraceError> {
+        let (pc, rest) = Self::split_pc(line)?;
+        let mut pc = pc.unwrap_or(next_pc);
+        let ops = RiscVOp::parse_expanded(&rest)?;
+        Ok(ops
+            .into_iter()
+            .map(|op| {
+                let instruction = Self::new(pc, op);
+                pc = instruction.next_pc;
+                instruction
+            })
+            .collect())
+    }
+
+    /// Split the address off the start of a line, if it has one, and blank it out,
+    /// so errors in the rest of the line have the right columns
+    fn split_pc(line: &str) -> Result<(Option<u64>, String), TraceError> {
+        let start = line.len() - line.trim_start().len();
+        if !line[start..].starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok((None, line.to_string()));
+        }
+
+        let end = line[start..]
+            .find(char::is_whitespace)
+            .map_or(line.len(), |n| start + n);
+        let text = &line[start..end];
+        let pc = match text.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => text.parse::<u64>().ok(),
+        };
+        let pc = pc.ok_or_else(|| TraceError {
+            line: 1,
+            column: start + 1,
+            token: text.to_string(),
+            kind: TraceErrorKind::BadPc,
+        })?;
+        Ok((Some(pc), " ".repeat(end) + &line[end..]))
+    }
+
+    /// An instruction followed by the one 4 bytes on
+    pub fn new(pc: u64, op: RiscVOp) -> Self {
+        Self {
+            pc,
+            op,
+            next_pc: pc.wrapping_add(4),
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}: {}", self.pc, self.op)
+    }
+}
+
+/// Where each label in a trace is defined, as the index of the instruction it marks
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    labels: BTreeMap<&'static str, usize>,
+}
+
+impl SymbolTable {
+    /// Define a label as marking the instruction at `index`. Returns `false` if it
+    /// was already defined.
+    fn define(&mut self, label: Label, index: usize) -> bool {
+        if self.labels.contains_key(label.name()) {
+            return false;
+        }
+        self.labels.insert(label.name(), index);
+        true
+    }
+
+    /// The index of the instruction a label marks, if it's defined. A label at the
+    /// end of the trace marks the index just past the last instruction.
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).copied()
+    }
+
+    /// Every label, in order of name
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        self.labels.iter().map(|(name, index)| (*name, *index))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+/// The instructions of a trace, and the labels defined between them
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    pub instructions: Vec<Instruction>,
+    pub labels: SymbolTable,
+    /// Whether every line gives its instruction's address, so that where the trace goes
+    /// after a branch or jump is where it really went, rather than just the next line
+    pub addressed: bool,
+}
+
+/// A word of a line, along with the column it starts at, starting from 0
+type Word<'a> = (usize, &'a str);
+
+/// Split the label definitions, such as `Loop:`, and the instruction's address off the
+/// start of a line. The labels can come before or after the address, which can be followed
+/// by a colon if it's in hex, as in `0x1c: Loop:`; a decimal one followed by a colon is a
+/// label. Returns the name of each label, the address if there is one, and how far they go.
+fn split_labels(line: &str) -> (Vec<Word<'_>>, Option<Word<'_>>, usize) {
+    let mut labels = Vec::new();
+    let mut pc = None;
+    let mut end = 0;
+    loop {
+        let start = line.len() - line[end..].trim_start().len();
+        let len = line[start..]
+            .find(char::is_whitespace)
+            .unwrap_or(line.len() - start);
+        let word = &line[start..start + len];
+        if pc.is_none()
+            && word.starts_with(|c: char| c.is_ascii_digit())
+            && (word.starts_with("0x") || !word.ends_with(':'))
+        {
+            pc = Some((start, word));
+        } else {
+            match word.strip_suffix(':') {
+                Some(name) => labels.push((start, name)),
+                None => break,
+            }
+        }
+        end = start + len;
+    }
+    (labels, pc, end)
+}
+
+/// Parse every line of a trace.
+///
+/// Blank lines and comments are skipped. Instructions without an address are
+/// assumed to follow the previous one, starting from 0. A line can start with label
+/// definitions, such as `Loop:`, before or after its address, which mark the next
+/// instruction, and branches and jumps to a defined label are given the index of the
+/// instruction it marks. If any line fails to parse, all of the errors in the trace
+/// are returned so they can be fixed at once.
+pub fn parse_trace(contents: &str) -> Result<Trace, Vec<TraceError>> {
+    parse(contents, false)
+}
+
+/// Parse an assembly program, such as a `.s` file, to run with [`run_program`]. It's
+/// written like a trace, but each instruction appears once, and branches and jumps go
+/// to labels, which must all be defined. Assembler directives, such as `.text`, are
+/// skipped.
+pub fn parse_program(contents: &str) -> Result<Trace, Vec<TraceError>> {
+    parse(contents, true)
+}
+
+fn parse(contents: &str, program: bool) -> Result<Trace, Vec<TraceError>> {
+    let mut instructions = Vec::new();
+    // The number and text of the line each instruction is on, for reporting undefined labels
+    let mut lines = Vec::new();
+    let mut labels = SymbolTable::default();
+    let mut errors = Vec::new();
+    let mut next_pc = 0;
+    let mut addressed = true;
+    for (i, line) in contents.lines().enumerate() {
+        let code = match line.find([';', '#']) {
+            Some(end) => &line[..end],
+            None => line,
+        };
+        // A directive starts the line, so `.L2: addi x1,x1,-1` is a label and an instruction
+        let (definitions, pc, end) = split_labels(code);
+        if program && definitions.is_empty() && code[end..].trim_start().starts_with('.') {
+            debug!("Skipping directive: {}", code.trim());
+            continue;
+        }
+        for &(column, name) in &definitions {
+            let kind = match Label::parse(name) {
+                Some(label) if labels.define(label, instructions.len()) => continue,
+                Some(_) => TraceErrorKind::DuplicateLabel,
+                None => TraceErrorKind::BadOperand,
+            };
+            errors.push(TraceError {
+                line: i + 1,
+                column: column + 1,
+                token: format!("{name}:"),
+                kind,
+            });
+        }
+        if code[end..].trim().is_empty() && pc.is_none() {
+            continue;
+        }
+
+        // Blank out the labels, and any colon after the address, so errors in the rest of
+        // the line have the right columns
+        let mut line = line.to_string();
+        for (column, name) in definitions {
+            line.replace_range(column..=column + name.len(), &" ".repeat(name.len() + 1));
+        }
+        if let Some((column, word)) = pc.filter(|(_, word)| word.ends_with(':')) {
+            line.replace_range(column + word.len() - 1..column + word.len(), " ");
+        }
+        addressed &= pc.is_some();
+        let parsed = if program {
+            Instruction::parse_expanded(&line, next_pc)
+        } else {
+            Instruction::parse(&line, next_pc).map(|instruction| vec![instruction])
+        };
+        match parsed {
+            Ok(parsed) => {
+                for instruction in parsed {
+                    next_pc = instruction.pc.wrapping_add(4);
+                    instructions.push(instruction);
+                    lines.push((i + 1, line.clone()));
+                }
+            }
+            Err(mut e) => {
+                e.line = i + 1;
+                errors.push(e);
+            }
+        }
+    }
+
+    // Each instruction ran after the one before it, so taken branches went to the next address
+    for i in 1..instructions.len() {
+        instructions[i - 1].next_pc = instructions[i].pc;
+    }
+
+    for (instruction, (number, line)) in instructions.iter_mut().zip(&lines) {
+        let Some(label) = instruction.op.label() else {
+            continue;
+        };
+        match labels.get(label.name()) {
+            Some(target) => instruction.op = instruction.op.with_target(target),
+            None if program => {
+                // The label is the last operand, but may be followed by a suffix
+                let column = line.rfind(label.name()).unwrap_or_default();
+                errors.push(TraceError {
+                    line: *number,
+                    column: column + 1,
+                    token: label.name().to_string(),
+                    kind: TraceErrorKind::UndefinedLabel,
+                });
+            }
+            // Traces without any labels name branch targets for show
+            None if !labels.is_empty() => warn!("{instruction}: label {label} isn't defined"),
+            None => {}
+        }
+    }
+
+    errors.sort_by_key(|e| e.line);
+    if errors.is_empty() {
+        Ok(Trace {
+            instructions,
+            labels,
+            addressed,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// Run a trace on the emulator, filling in the addresses of loads and stores from their
+/// base registers and offsets. An address given with `:addr` is used instead, but a warning
+/// is logged if it disagrees. With `branches`, the branch outcomes the trace records are
+/// also replaced with the ones computed from the registers. If the trace gives the address
+/// of every instruction, where a taken branch or jump goes is checked against the instruction
+/// its label marks, or with `branches`, the address it computes. The state is left as it was
+/// after the last instruction.
+pub fn execute_trace(trace: &mut Trace, state: &mut ArchState, branches: bool) {
+    let addressed = trace.addressed;
+    let instructions = &mut trace.instructions;
+    let last = instructions.len().saturating_sub(1);
+    for i in 0..instructions.len() {
+        let target = instructions[i]
+            .op
+            .label()
+            .and_then(|label| label.target())
+            .and_then(|target| instructions.get(target))
+            .map(|target| target.pc);
+        let instruction = &mut instructions[i];
+        if let Some(computed) = instruction.op.effective_addr(&state.registers) {
+            match instruction.op.known_addr() {
+                Some(given) if given != computed => warn!(
+                    "{instruction}: the trace gives address {given:#x}, \
+                     but the base register and offset add up to {computed:#x}"
+                ),
+                Some(_) => {}
+                None => instruction.op = instruction.op.with_addr(computed),
+            }
+        }
+
+        let execution = instruction.op.execute(instruction.pc, state);
+        if let Some(taken) = execution.taken.filter(|_| branches) {
+            if instruction
+                .op
+                .taken()
+                .is_some_and(|recorded| recorded != taken)
+            {
+                warn!("{instruction}: the trace says the branch went the other way");
+            }
+            instruction.op = instruction.op.with_taken(taken);
+        }
+        if let Some(target) = execution.target.filter(|_| branches && addressed) {
+            if target != instruction.next_pc {
+                warn!(
+                    "{instruction}: jumped to {target:#x}, but the trace goes to {:#x}",
+                    instruction.next_pc
+                );
+            }
+        }
+        if let Some(target) = target.filter(|_| addressed && instruction.op.taken() == Some(true)) {
+            if i == last {
+                // Nothing follows the last instruction to say where it went
+                instruction.next_pc = target;
+            } else if target != instruction.next_pc {
+                warn!(
+                    "{instruction}: branches to {target:#x}, but the trace goes to {:#x}",
+                    instruction.next_pc
+                );
+            }
+        }
+        state.pc = instruction.next_pc;
+    }
+}
+
+/// Run a program from its first instruction, returning the instructions it executes, in
+/// order, for the pipeline to simulate. Loads and stores access the addresses their base
+/// registers and offsets add up to, and branches go the way their registers say, so any
+/// `:addr`, `:T` or `:N` suffixes are replaced. The program stops when it runs past its
+/// last instruction, jumps to an address without an instruction, or has executed `budget`
+/// instructions. The state is left as it was after the last instruction.
+pub fn run_program(program: &Trace, state: &mut ArchState, budget: u64) -> Vec<Instruction> {
+    let code = &program.instructions;
+    let index: BTreeMap<u64, usize> = code
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| (instruction.pc, i))
+        .collect();
+    // Where a program which runs past its last instruction ends up
+    let end = code.last().map_or(0, |last| last.pc.wrapping_add(4));
+
+    let mut executed = Vec::new();
+    let mut next = (!code.is_empty()).then_some(0);
+    state.pc = code.first().map_or(0, |first| first.pc);
+    while let Some(i) = next {
+        if executed.len() as u64 == budget {
+            info!("Stopped after {budget} instructions, at {:#x}", state.pc);
+            break;
+        }
+
+        let mut instruction = code[i];
+        if let Some(addr) = instruction.op.effective_addr(&state.registers) {
+            instruction.op = instruction.op.with_addr(addr);
+        }
+        let execution = instruction.op.execute(instruction.pc, state);
+        if let Some(taken) = execution.taken {
+            instruction.op = instruction.op.with_taken(taken);
+        }
+
+        next = match (execution.taken, execution.target) {
+            (_, Some(target)) => index.get(&target).copied(),
+            (Some(true), None) => instruction.op.label().and_then(|label| label.target()),
+            _ => Some(i + 1),
+        }
+        .filter(|next| *next < code.len());
+        instruction.next_pc = match next {
+            Some(next) => code[next].pc,
+            None => execution.target.unwrap_or(end),
+        };
+        state.pc = instruction.next_pc;
+        executed.push(instruction);
+    }
+    executed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcs(trace: &Trace) -> Vec<u64> {
+        trace.instructions.iter().map(|i| i.pc).collect()
+    }
+
+    #[test]
+    fn every_bad_line_is_reported_with_its_position() {
+        let errors = parse_trace(
+            "fadd.s f1,f2,f3\n\
+             \n\
+             add    x1,y2,x3 ; a comment\n\
+             sw x1,0(x2):zz\n\
+             frob x1\n\
+             add x1,x2\n\
+             add x1,x2,x3:8\n\
+             beq x1,x2,Loop:X\n",
+        )
+        .unwrap_err();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.column, e.token.as_str(), e.kind))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (3, 11, "y2", TraceErrorKind::BadRegister),
+                (4, 13, "zz", TraceErrorKind::BadAddress),
+                (5, 1, "frob", TraceErrorKind::UnknownMnemonic),
+                (6, 10, "", TraceErrorKind::MissingOperand),
+                (7, 14, "8", TraceErrorKind::UnexpectedAddress),
+                (8, 16, "X", TraceErrorKind::BadOutcome),
+            ]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "line 3, column 11: bad register \"y2\""
+        );
+        assert_eq!(errors[3].to_string(), "line 6, column 10: missing operand");
+    }
+
+    #[test]
+    fn labels_can_come_before_or_after_the_address() {
+        let trace = parse_trace(
+            "0x10: Loop: addi x1,x1,1\n\
+             0x14 Test: bne x1,x2,Loop:T\n\
+             Again: 0x10 addi x1,x1,1\n\
+             End: 0x18: add x3,x3,x3\n",
+        )
+        .unwrap();
+        assert_eq!(pcs(&trace), [0x10, 0x14, 0x10, 0x18]);
+        assert_eq!(trace.labels.get("Loop"), Some(0));
+        assert_eq!(trace.labels.get("Test"), Some(1));
+        assert_eq!(trace.labels.get("Again"), Some(2));
+        assert_eq!(trace.labels.get("End"), Some(3));
+        assert!(trace.addressed);
+    }
+
+    #[test]
+    fn decimal_numbers_followed_by_a_colon_are_labels() {
+        let trace = parse_trace("1: add x3,x3,x3\n8 2: add x3,x3,x3\n").unwrap();
+        assert_eq!(pcs(&trace), [0, 8]);
+        assert_eq!(trace.labels.get("1"), Some(0));
+        assert_eq!(trace.labels.get("2"), Some(1));
+        assert!(!trace.addressed);
+    }
+
+    #[test]
+    fn bad_addresses_are_reported_where_they_are() {
+        let errors = parse_trace("Loop: 0x1g: add x1,x1,x1\n0x18\n").unwrap_err();
+        let found: Vec<_> = errors.iter().map(|e| (e.line, e.column, e.kind)).collect();
+        assert_eq!(
+            found,
+            [
+                (1, 7, TraceErrorKind::BadPc),
+                (2, 5, TraceErrorKind::MissingMnemonic)
+            ]
+        );
+    }
+
+    #[test]
+    fn branches_are_only_checked_against_given_addresses() {
+        let loop_back = |trace: &str| {
+            let mut trace = parse_trace(trace).unwrap();
+            execute_trace(&mut trace, &mut ArchState::default(), false);
+            trace.instructions.last().unwrap().next_pc
+        };
+        assert_eq!(
+            loop_back("0x10 Loop: addi x1,x1,1\n0x14 bne x1,x2,Loop:T\n"),
+            0x10
+        );
+        // Without addresses, where the branch went can't be known
+        assert_eq!(loop_back("Loop: addi x1,x1,1\nbne x1,x2,Loop:T\n"), 8);
+    }
+
+    #[test]
+    fn local_labels_are_not_directives() {
+        let program = parse_program(
+            "        .text\n\
+             \x20       .globl main\n\
+             main:   addi x1,x0,2\n\
+             .L2: addi x1,x1,-1\n\
+             \x20       bnez x1,.L2\n",
+        )
+        .unwrap();
+        assert_eq!(pcs(&program), [0, 4, 8]);
+        assert_eq!(program.labels.get("main"), Some(0));
+        assert_eq!(program.labels.get(".L2"), Some(1));
+        assert_eq!(program.instructions[1].op.to_string(), "addi   x1,x1,-1");
+
+        let executed = run_program(&program, &mut ArchState::default(), 100);
+        assert_eq!(executed.len(), 5);
+    }
+
+    #[test]
+    fn programs_expand_wide_li() {
+        let program = parse_program(
+            "        li   x5,0x12345678\n\
+             Loop:   addi x5,x5,-1\n\
+             \x20       bnez x5,Loop\n",
+        )
+        .unwrap();
+        assert_eq!(pcs(&program), [0, 4, 8, 12]);
+        assert_eq!(program.labels.get("Loop"), Some(2));
+
+        let mut state = ArchState::default();
+        let executed = run_program(&program, &mut state, 3);
+        assert_eq!(executed.len(), 3);
+        assert_eq!(state.registers.read(Register::GP(5)), 0x12345677, "{state}");
+    }
+
+    #[test]
+    fn programs_run_on_their_registers_and_memory() {
+        let program = parse_program(
+            "        li   x1,3\n\
+             \x20       li   x3,256\n\
+             Loop:   lw   x4,0(x3)\n\
+             \x20       add  x2,x2,x4\n\
+             \x20       addi x3,x3,4\n\
+             \x20       addi x1,x1,-1\n\
+             \x20       bnez x1,Loop\n\
+             \x20       sw   x2,0(x3)\n\
+             \x20       addi x0,x0,1\n",
+        )
+        .unwrap();
+        let words = BTreeMap::from([(256, 1), (260, 2), (264, 3)]);
+        let mut state = ArchState {
+            memory: SparseMemory::new(&words),
+            ..ArchState::default()
+        };
+        let executed = run_program(&program, &mut state, 100);
+        assert_eq!(executed.len(), 2 + 5 * 3 + 2);
+
+        let addresses: Vec<_> = executed.iter().filter_map(|i| i.op.addr()).collect();
+        assert_eq!(addresses, [256, 260, 264, 268]);
+        let outcomes: Vec<_> = executed.iter().filter_map(|i| i.op.taken()).collect();
+        assert_eq!(outcomes, [true, true, false]);
+        assert_eq!(executed[6].next_pc, 8);
+
+        assert_eq!(state.registers.read(Register::GP(2)), 6, "{state}");
+        assert_eq!(state.registers.read(Register::GP(0)), 0, "{state}");
+        assert_eq!(state.memory.words()[&268], 6, "{state}");
+        assert_eq!(state.pc, 36);
+    }
+
+    #[test]
+    fn running_out_of_budget_stops_the_program() {
+        let program = parse_program("Loop: addi x1,x1,1\n      j Loop\n").unwrap();
+        let mut state = ArchState::default();
+        assert_eq!(run_program(&program, &mut state, 7).len(), 7);
+        assert_eq!(state.registers.read(Register::GP(1)), 4, "{state}");
+    }
+
+    #[test]
+    fn traces_take_branch_outcomes_from_their_registers() {
+        let mut trace = parse_trace("addi x1,x0,1\nbeq x1,x0,Loop:T\nbne x1,x0,Loop:N\n").unwrap();
+        execute_trace(&mut trace, &mut ArchState::default(), false);
+        let outcomes: Vec<_> = trace.instructions.iter().map(|i| i.op.taken()).collect();
+        assert_eq!(outcomes, [None, Some(true), Some(false)]);
+
+        let mut state = ArchState::default();
+        execute_trace(&mut trace, &mut state, true);
+        let outcomes: Vec<_> = trace.instructions.iter().map(|i| i.op.taken()).collect();
+        assert_eq!(outcomes, [None, Some(false), Some(true)]);
+        assert_eq!(state.registers.read(Register::GP(1)), 1, "{state}");
+    }
+
+    #[test]
+    fn traces_without_addresses_get_them_from_their_registers() {
+        let mut trace = parse_trace("addi x2,x0,256\nsw x1,4(x2)\nlw x3,-4(x2):64\n").unwrap();
+        execute_trace(&mut trace, &mut ArchState::default(), false);
+        let addresses: Vec<_> = trace.instructions.iter().map(|i| i.op.addr()).collect();
+        // The address the trace gives is kept, even though it disagrees
+        assert_eq!(addresses, [None, Some(260), Some(64)]);
+    }
+}